@@ -0,0 +1,5 @@
+ALTER TABLE issue_delivery_queue
+ADD COLUMN status TEXT NOT NULL DEFAULT 'pending',
+ADD COLUMN n_attempts SMALLINT NOT NULL DEFAULT 0,
+ADD COLUMN execute_after timestamptz NOT NULL DEFAULT now(),
+ADD COLUMN last_error TEXT NULL;
@@ -0,0 +1,18 @@
+BEGIN;
+
+ALTER TABLE newsletter_issues
+RENAME COLUMN content TO html_content;
+
+ALTER TABLE newsletter_issues
+ADD COLUMN text_content TEXT NULL;
+
+UPDATE newsletter_issues
+SET
+  text_content = regexp_replace(html_content, '<[^>]*>', '', 'g');
+
+ALTER TABLE newsletter_issues
+ALTER COLUMN text_content
+SET
+  NOT NULL;
+
+COMMIT;
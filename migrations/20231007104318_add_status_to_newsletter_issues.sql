@@ -0,0 +1,16 @@
+BEGIN;
+
+ALTER TABLE newsletter_issues
+ADD COLUMN status TEXT NOT NULL DEFAULT 'sent' CHECK (status IN ('draft', 'scheduled', 'sent')),
+ADD COLUMN created_at timestamptz NOT NULL DEFAULT now(),
+ALTER COLUMN published_at DROP NOT NULL;
+
+UPDATE newsletter_issues
+SET
+  created_at = published_at;
+
+ALTER TABLE newsletter_issues
+ALTER COLUMN status
+SET DEFAULT 'draft';
+
+COMMIT;
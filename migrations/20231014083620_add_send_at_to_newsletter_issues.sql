@@ -0,0 +1,2 @@
+ALTER TABLE newsletter_issues
+ADD COLUMN send_at timestamptz NULL;
@@ -0,0 +1,7 @@
+ALTER TABLE issue_delivery_queue
+ADD COLUMN queued_at timestamptz NOT NULL DEFAULT now(),
+ADD COLUMN sent_at timestamptz NULL;
+
+CREATE INDEX issue_delivery_queue_pending_idx ON issue_delivery_queue (execute_after)
+WHERE
+  status = 'pending';
@@ -0,0 +1,9 @@
+CREATE TABLE tracking_events (
+  newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+  subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+  kind TEXT NOT NULL CHECK (kind IN ('open', 'click')),
+  url TEXT NULL,
+  occurred_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX tracking_events_newsletter_issue_id_idx ON tracking_events (newsletter_issue_id);
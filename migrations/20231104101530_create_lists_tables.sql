@@ -0,0 +1,59 @@
+CREATE TABLE lists (
+  list_id uuid NOT NULL,
+  PRIMARY KEY (list_id),
+  slug TEXT NOT NULL UNIQUE,
+  name TEXT NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT now(),
+  archived_at timestamptz NULL
+);
+
+-- Everyone subscribed so far is on the one list we used to have.
+INSERT INTO lists (list_id, slug, name)
+VALUES (gen_random_uuid(), 'newsletter', 'Newsletter');
+
+CREATE TABLE list_subscriptions (
+  list_id uuid NOT NULL REFERENCES lists (list_id),
+  subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+  PRIMARY KEY (list_id, subscriber_id),
+  status TEXT NOT NULL CHECK (
+    status IN ('pending_confirmation', 'confirmed', 'unsubscribed')
+  ),
+  subscribed_at timestamptz NOT NULL DEFAULT now()
+);
+
+INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+SELECT
+  lists.list_id,
+  subscriptions.id,
+  CASE subscriptions.status
+    WHEN 'pending_confirmation' THEN 'pending_confirmation'
+    WHEN 'unsubscribed' THEN 'unsubscribed'
+    ELSE 'confirmed'
+  END,
+  subscriptions.subscribed_at
+FROM subscriptions, lists
+WHERE lists.slug = 'newsletter';
+
+-- The subscriber status now only tracks whether the address has been verified
+-- and can still be mailed. Whether someone gets a list is tracked per list.
+UPDATE subscriptions
+SET status = 'confirmed'
+WHERE status = 'unsubscribed';
+
+ALTER TABLE subscription_tokens
+ADD COLUMN list_id uuid NULL REFERENCES lists (list_id);
+
+UPDATE subscription_tokens
+SET list_id = (SELECT list_id FROM lists WHERE slug = 'newsletter');
+
+ALTER TABLE subscription_tokens
+ALTER COLUMN list_id SET NOT NULL;
+
+ALTER TABLE newsletter_issues
+ADD COLUMN list_id uuid NULL REFERENCES lists (list_id);
+
+UPDATE newsletter_issues
+SET list_id = (SELECT list_id FROM lists WHERE slug = 'newsletter');
+
+ALTER TABLE newsletter_issues
+ALTER COLUMN list_id SET NOT NULL;
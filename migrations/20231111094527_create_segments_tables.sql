@@ -0,0 +1,27 @@
+-- Tags belong to a subscription to a list, so the same subscriber can be
+-- tagged differently on each list they are on.
+CREATE TABLE subscriber_tags (
+  list_id uuid NOT NULL,
+  subscriber_id uuid NOT NULL,
+  tag TEXT NOT NULL,
+  PRIMARY KEY (list_id, subscriber_id, tag),
+  FOREIGN KEY (list_id, subscriber_id)
+    REFERENCES list_subscriptions (list_id, subscriber_id) ON DELETE CASCADE,
+  tagged_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX subscriber_tags_list_id_tag_idx ON subscriber_tags (list_id, tag);
+
+CREATE TABLE segments (
+  segment_id uuid NOT NULL,
+  PRIMARY KEY (segment_id),
+  list_id uuid NOT NULL REFERENCES lists (list_id),
+  name TEXT NOT NULL,
+  definition jsonb NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT now(),
+  UNIQUE (list_id, name)
+);
+
+-- Issues without a segment go to every confirmed subscriber of their list.
+ALTER TABLE newsletter_issues
+ADD COLUMN segment_id uuid NULL REFERENCES segments (segment_id);
@@ -0,0 +1,22 @@
+-- How often the subscriber would like to hear from us, picked in the
+-- preference center.
+ALTER TABLE subscriptions
+ADD COLUMN frequency TEXT NOT NULL DEFAULT 'every_issue' CHECK (
+  frequency IN ('every_issue', 'weekly', 'monthly')
+);
+
+-- Looked up for every subscriber when an issue is published, to check whether
+-- they have already been sent one recently enough given their frequency.
+CREATE INDEX issue_delivery_queue_subscriber_email_idx ON issue_delivery_queue (subscriber_email);
+
+-- Every change made in the preference center. Lists are recorded as
+-- `list:<slug>` with the old and new status of the subscription.
+CREATE TABLE preference_changes (
+  subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+  field TEXT NOT NULL,
+  old_value TEXT NULL,
+  new_value TEXT NULL,
+  changed_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX preference_changes_subscriber_id_idx ON preference_changes (subscriber_id);
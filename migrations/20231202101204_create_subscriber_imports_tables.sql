@@ -0,0 +1,27 @@
+CREATE TABLE subscriber_imports (
+  import_id uuid NOT NULL,
+  PRIMARY KEY (import_id),
+  list_id uuid NOT NULL REFERENCES lists (list_id),
+  file_name TEXT NOT NULL,
+  status TEXT NOT NULL CHECK (status IN ('pending_confirmation', 'confirmed')),
+  consent_source TEXT NULL,
+  n_imported INT NOT NULL DEFAULT 0,
+  n_duplicates INT NOT NULL DEFAULT 0,
+  n_rejected INT NOT NULL DEFAULT 0,
+  started_at timestamptz NOT NULL DEFAULT now(),
+  finished_at timestamptz NULL,
+  -- Set if the import stopped before the end of the file.
+  error TEXT NULL
+);
+
+CREATE TABLE subscriber_import_rejections (
+  import_id uuid NOT NULL REFERENCES subscriber_imports (import_id) ON DELETE CASCADE,
+  line_number BIGINT NOT NULL,
+  PRIMARY KEY (import_id, line_number),
+  email TEXT NOT NULL,
+  name TEXT NOT NULL,
+  reason TEXT NOT NULL
+);
+
+-- Where subscribers who were added as confirmed agreed to receive the list.
+ALTER TABLE list_subscriptions ADD COLUMN consent_source TEXT NULL;
@@ -0,0 +1,3 @@
+-- When the subscriber confirmed their address. Unknown for subscribers who
+-- confirmed before this column was added.
+ALTER TABLE subscriptions ADD COLUMN confirmed_at timestamptz NULL;
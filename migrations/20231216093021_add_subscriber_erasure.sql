@@ -0,0 +1,15 @@
+-- Deleting a subscriber deletes their tokens too.
+ALTER TABLE subscription_tokens
+DROP CONSTRAINT subscription_tokens_subscriber_id_fkey,
+ADD CONSTRAINT subscription_tokens_subscriber_id_fkey
+  FOREIGN KEY (subscriber_id) REFERENCES subscriptions (id) ON DELETE CASCADE;
+
+-- What is left of subscribers whose data was erased at their request: an
+-- HMAC-SHA256 of their lowercased address keyed with the application secret,
+-- so that it isn't imported again. Without the key the address can't be
+-- recovered by hashing lists of candidate addresses.
+CREATE TABLE erased_subscribers (
+  email_hash TEXT NOT NULL,
+  PRIMARY KEY (email_hash),
+  erased_at timestamptz NOT NULL DEFAULT now()
+);
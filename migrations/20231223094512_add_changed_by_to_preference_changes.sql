@@ -0,0 +1,6 @@
+-- Changes are made by the subscriber in the preference center, or by an admin
+-- on their behalf, e.g. when confirming or unsubscribing them by hand.
+ALTER TABLE preference_changes
+ADD COLUMN changed_by TEXT NOT NULL DEFAULT 'subscriber' CHECK (
+  changed_by IN ('subscriber', 'admin')
+);
@@ -0,0 +1,13 @@
+-- Confirmation emails waiting to be sent by the background worker, e.g. for
+-- the subscribers of an import. The address and list name are looked up when
+-- the email is sent, and the task goes away with the subscriber.
+CREATE TABLE confirmation_email_queue (
+  subscription_token TEXT NOT NULL PRIMARY KEY,
+  subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+  list_id uuid NOT NULL REFERENCES lists (list_id),
+  n_attempts SMALLINT NOT NULL DEFAULT 0,
+  execute_after timestamptz NOT NULL DEFAULT now(),
+  last_error TEXT NULL
+);
+
+CREATE INDEX confirmation_email_queue_execute_after_idx ON confirmation_email_queue (execute_after);
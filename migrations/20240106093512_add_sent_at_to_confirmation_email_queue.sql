@@ -0,0 +1,11 @@
+-- Sent confirmation emails are kept, like deliveries, so that the worker can
+-- tell how much of the daily send quota was used before it restarted.
+ALTER TABLE confirmation_email_queue
+ADD COLUMN sent_at timestamptz NULL;
+
+DROP INDEX confirmation_email_queue_execute_after_idx;
+CREATE INDEX confirmation_email_queue_pending_idx ON confirmation_email_queue (execute_after)
+WHERE
+  sent_at IS NULL;
+
+CREATE INDEX confirmation_email_queue_sent_at_idx ON confirmation_email_queue (sent_at);
@@ -0,0 +1,10 @@
+-- When the worker last tried to send the delivery. `execute_after` is when it
+-- will try next, which says nothing about when a bounced delivery was sent.
+ALTER TABLE issue_delivery_queue
+ADD COLUMN last_attempted_at timestamptz NULL;
+
+-- Dead letters are scheduled at the time of their last attempt, and the
+-- deliveries that went out were last attempted when they were sent.
+UPDATE issue_delivery_queue
+SET last_attempted_at = CASE status WHEN 'dead_letter' THEN execute_after ELSE sent_at END
+WHERE n_attempts > 0;
@@ -1,12 +1,34 @@
 use axum::extract::FromRef;
+use secrecy::Secret;
 use sqlx::PgPool;
 
-use crate::email_client::DynEmailClient;
+use crate::{challenge::DynChallengeVerifier, email_client::DynEmailClient};
 
 #[derive(Clone, FromRef)]
 pub struct AppState {
     pub db_pool: PgPool,
     pub email_client: DynEmailClient,
     pub base_url: String,
+    pub hmac_secret: HmacSecret,
+    pub webhook_secret: WebhookSecret,
+    pub subscription_token_ttl: chrono::Duration,
+    pub subscribe_form_timing: SubscribeFormTiming,
+    pub challenge_verifier: DynChallengeVerifier,
     pub flash_config: axum_flash::Config,
 }
+
+/// The key used to sign and verify tokens embedded in links we send out.
+#[derive(Clone)]
+pub struct HmacSecret(pub Secret<String>);
+
+/// The key our email provider signs the events it sends us with.
+#[derive(Clone)]
+pub struct WebhookSecret(pub Secret<String>);
+
+/// How long a subscription form may take to be filled in.
+#[derive(Clone)]
+pub struct SubscribeFormTiming {
+    /// Forms submitted sooner than this are assumed to be filled in by bots.
+    pub min_time_to_submit: chrono::Duration,
+    pub max_age: chrono::Duration,
+}
@@ -1,14 +1,19 @@
 use std::ops::Deref;
 
 use axum::{
+    headers::{authorization::Basic, Authorization},
     middleware::Next,
-    response::{Redirect, Response},
+    response::{IntoResponse, Redirect, Response},
+    TypedHeader,
 };
-use hyper::Request;
+use hyper::{header, Request, StatusCode};
+use sqlx::PgPool;
 use uuid::Uuid;
 
 use crate::session_state::TypedSession;
 
+use super::{validate_credentials, AuthError, Credentials};
+
 pub async fn reject_anonymous_users<B>(
     session: TypedSession,
     mut request: Request<B>,
@@ -23,6 +28,62 @@ pub async fn reject_anonymous_users<B>(
     }
 }
 
+/// Identify the caller of an API endpoint, preferring an existing admin
+/// session over HTTP Basic credentials.
+pub async fn authenticate_api_user(
+    pool: &PgPool,
+    session: &TypedSession,
+    authorization: Option<TypedHeader<Authorization<Basic>>>,
+) -> Result<Uuid, ApiAuthError> {
+    if let Some(user_id) = session.get_user_id() {
+        return Ok(user_id);
+    }
+
+    let TypedHeader(authorization) = authorization.ok_or_else(|| {
+        ApiAuthError::InvalidCredentials(anyhow::anyhow!("The 'Authorization' header was missing"))
+    })?;
+    let credentials: Credentials = authorization.into();
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+
+    Ok(validate_credentials(credentials, pool).await?)
+}
+
+/// The error of [`authenticate_api_user`], shared by the error types of the
+/// API endpoints. Callers without valid credentials are asked for HTTP Basic
+/// credentials, with the same realm across the API.
+#[derive(Debug, thiserror::Error)]
+pub enum ApiAuthError {
+    #[error("Authentication failed")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+impl From<AuthError> for ApiAuthError {
+    fn from(err: AuthError) -> Self {
+        match err {
+            AuthError::InvalidCredentials(e) => Self::InvalidCredentials(e),
+            AuthError::Unexpected(e) => Self::Unexpected(e),
+        }
+    }
+}
+
+impl IntoResponse for ApiAuthError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::InvalidCredentials(_) => (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, r#"Basic realm="api""#)],
+                self.to_string(),
+            )
+                .into_response(),
+            Self::Unexpected(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()).into_response()
+            }
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct UserId(Uuid);
 
@@ -0,0 +1,61 @@
+use axum::async_trait;
+use secrecy::{ExposeSecret, Secret};
+
+use super::{Challenge, ChallengeError, ChallengeVerifier};
+
+/// Stands in for a CAPTCHA provider until we integrate one, e.g. in local
+/// development. It hands out the configured site key and accepts a single
+/// configured response, instead of asking the provider to verify it.
+pub struct CaptchaStubVerifier {
+    site_key: String,
+    expected_response: Secret<String>,
+}
+
+impl CaptchaStubVerifier {
+    pub fn new(site_key: String, expected_response: Secret<String>) -> Self {
+        Self {
+            site_key,
+            expected_response,
+        }
+    }
+}
+
+#[async_trait]
+impl ChallengeVerifier for CaptchaStubVerifier {
+    fn issue(&self) -> Challenge {
+        Challenge::Captcha {
+            site_key: self.site_key.clone(),
+        }
+    }
+
+    async fn verify(&self, _challenge: &str, solution: &str) -> Result<(), ChallengeError> {
+        if solution != self.expected_response.expose_secret() {
+            return Err(ChallengeError::Failed("The CAPTCHA was not solved".into()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+    use secrecy::Secret;
+
+    use super::CaptchaStubVerifier;
+    use crate::challenge::{Challenge, ChallengeVerifier};
+
+    #[tokio::test]
+    async fn only_the_expected_response_is_accepted() {
+        let verifier = CaptchaStubVerifier::new("site-key".into(), Secret::new("pass".into()));
+
+        assert_eq!(
+            verifier.issue(),
+            Challenge::Captcha {
+                site_key: "site-key".into()
+            }
+        );
+        assert_ok!(verifier.verify("", "pass").await);
+        assert_err!(verifier.verify("", "").await);
+        assert_err!(verifier.verify("", "fail").await);
+    }
+}
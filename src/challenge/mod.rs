@@ -0,0 +1,69 @@
+mod captcha_stub;
+mod proof_of_work;
+
+use std::sync::Arc;
+
+use axum::async_trait;
+use secrecy::Secret;
+use serde::Serialize;
+
+use crate::configuration::{BotProtectionSettings, ChallengeSettings};
+
+pub use captcha_stub::CaptchaStubVerifier;
+pub use proof_of_work::{solve, ProofOfWorkVerifier};
+
+/// A challenge the subscription form has to solve before it is submitted, as
+/// sent to the page or app that shows the form.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Challenge {
+    /// Find a solution such that the SHA-256 hash of `{challenge}:{solution}`
+    /// starts with `difficulty` zero bits.
+    ProofOfWork { challenge: String, difficulty: u8 },
+    /// Show the widget of the CAPTCHA provider with this site key. The
+    /// response of the widget is the solution.
+    Captcha { site_key: String },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ChallengeError {
+    /// The solution is wrong, or the challenge is no longer valid.
+    #[error("{0}")]
+    Failed(String),
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+/// Issues the challenges of subscription forms and checks their solutions, so
+/// that bots have to work, or get past a CAPTCHA, for every subscription.
+#[async_trait]
+pub trait ChallengeVerifier {
+    /// A new challenge to embed in a subscription form.
+    fn issue(&self) -> Challenge;
+
+    /// Check the solution to a challenge issued by [`ChallengeVerifier::issue`].
+    /// `challenge` is the `challenge` field of the challenge, if it has one.
+    async fn verify(&self, challenge: &str, solution: &str) -> Result<(), ChallengeError>;
+}
+
+pub type DynChallengeVerifier = Arc<dyn ChallengeVerifier + Send + Sync>;
+
+pub fn from_settings(
+    settings: &BotProtectionSettings,
+    hmac_secret: &Secret<String>,
+) -> DynChallengeVerifier {
+    match &settings.challenge {
+        ChallengeSettings::ProofOfWork { difficulty } => Arc::new(ProofOfWorkVerifier::new(
+            hmac_secret.clone(),
+            *difficulty,
+            settings.max_form_age(),
+        )),
+        ChallengeSettings::CaptchaStub {
+            site_key,
+            expected_response,
+        } => Arc::new(CaptchaStubVerifier::new(
+            site_key.clone(),
+            expected_response.clone(),
+        )),
+    }
+}
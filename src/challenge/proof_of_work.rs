@@ -0,0 +1,196 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use axum::async_trait;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use rand::{thread_rng, Rng};
+use secrecy::Secret;
+use sha2::{Digest, Sha256};
+
+use super::{Challenge, ChallengeError, ChallengeVerifier};
+use crate::domain::signature;
+
+/// Makes the browser or app spend some CPU time on every subscription, which
+/// is negligible for a person but adds up for a bot subscribing in bulk.
+///
+/// A challenge is the time it was issued at and a random nonce, followed by a
+/// [signature](signature) of both, so nothing is stored until it is solved. Solved
+/// challenges are remembered until they expire so that each one can only be
+/// used once. They are only remembered by this process, so with several
+/// replicas a solution can be used once on each of them.
+const PURPOSE: &str = "proof-of-work";
+
+pub struct ProofOfWorkVerifier {
+    secret: Secret<String>,
+    difficulty: u8,
+    ttl: Duration,
+    /// The challenges solved so far, with the time they expire at.
+    used: Mutex<HashMap<String, DateTime<Utc>>>,
+}
+
+impl ProofOfWorkVerifier {
+    pub fn new(secret: Secret<String>, difficulty: u8, ttl: Duration) -> Self {
+        Self {
+            secret,
+            difficulty,
+            ttl,
+            used: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn issue_at(&self, now: DateTime<Utc>) -> Challenge {
+        let payload = format!(
+            "{}.{}",
+            now.timestamp(),
+            hex::encode(thread_rng().gen::<[u8; 16]>())
+        );
+        let tag = signature::sign(PURPOSE, &[payload.as_bytes()], &self.secret);
+
+        Challenge::ProofOfWork {
+            challenge: format!("{payload}.{tag}"),
+            difficulty: self.difficulty,
+        }
+    }
+
+    fn verify_at(
+        &self,
+        challenge: &str,
+        solution: &str,
+        now: DateTime<Utc>,
+    ) -> Result<(), ChallengeError> {
+        let invalid = || ChallengeError::Failed("The challenge is not valid".into());
+        let (payload, tag) = challenge.rsplit_once('.').ok_or_else(invalid)?;
+        signature::verify(PURPOSE, &[payload.as_bytes()], tag, &self.secret)
+            .map_err(|_| invalid())?;
+        let (issued_at, _nonce) = payload.split_once('.').ok_or_else(invalid)?;
+        let expires_at = issued_at
+            .parse()
+            .ok()
+            .and_then(|issued_at| Utc.timestamp_opt(issued_at, 0).single())
+            .ok_or_else(invalid)?
+            + self.ttl;
+        if expires_at <= now {
+            return Err(ChallengeError::Failed("The challenge has expired".into()));
+        }
+        if leading_zero_bits(challenge, solution) < u32::from(self.difficulty) {
+            return Err(ChallengeError::Failed(
+                "The solution does not solve the challenge".into(),
+            ));
+        }
+
+        let mut used = self.used.lock().unwrap();
+        used.retain(|_, expires_at| *expires_at > now);
+        if used.insert(challenge.to_owned(), expires_at).is_some() {
+            return Err(ChallengeError::Failed(
+                "The challenge has already been used".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ChallengeVerifier for ProofOfWorkVerifier {
+    fn issue(&self) -> Challenge {
+        self.issue_at(Utc::now())
+    }
+
+    async fn verify(&self, challenge: &str, solution: &str) -> Result<(), ChallengeError> {
+        self.verify_at(challenge, solution, Utc::now())
+    }
+}
+
+/// Find a solution to a proof-of-work challenge, the way the subscription form
+/// does.
+pub fn solve(challenge: &str, difficulty: u8) -> String {
+    (0u64..)
+        .map(|counter| counter.to_string())
+        .find(|solution| leading_zero_bits(challenge, solution) >= u32::from(difficulty))
+        .expect("A solution is found long before running out of numbers")
+}
+
+/// The number of zero bits the hash of the solution starts with.
+fn leading_zero_bits(challenge: &str, solution: &str) -> u32 {
+    let hash = Sha256::digest(format!("{challenge}:{solution}").as_bytes());
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use claims::{assert_err, assert_ok};
+    use secrecy::Secret;
+
+    use super::{leading_zero_bits, solve, ProofOfWorkVerifier};
+    use crate::challenge::Challenge;
+
+    fn verifier() -> ProofOfWorkVerifier {
+        ProofOfWorkVerifier::new(
+            Secret::new("super-secret".to_string()),
+            8,
+            Duration::hours(2),
+        )
+    }
+
+    fn challenge(verifier: &ProofOfWorkVerifier) -> String {
+        match verifier.issue_at(Utc::now()) {
+            Challenge::ProofOfWork { challenge, .. } => challenge,
+            challenge => panic!("Unexpected challenge: {challenge:?}"),
+        }
+    }
+
+    #[test]
+    fn a_solved_challenge_is_accepted_once() {
+        let verifier = verifier();
+        let challenge = challenge(&verifier);
+        let solution = solve(&challenge, 8);
+
+        assert!(leading_zero_bits(&challenge, &solution) >= 8);
+        assert_ok!(verifier.verify_at(&challenge, &solution, Utc::now()));
+        assert_err!(verifier.verify_at(&challenge, &solution, Utc::now()));
+    }
+
+    #[test]
+    fn a_wrong_solution_is_rejected() {
+        let verifier = verifier();
+        let challenge = challenge(&verifier);
+        let wrong = (0u64..)
+            .map(|counter| counter.to_string())
+            .find(|solution| leading_zero_bits(&challenge, solution) < 8)
+            .unwrap();
+
+        assert_err!(verifier.verify_at(&challenge, &wrong, Utc::now()));
+    }
+
+    #[test]
+    fn an_expired_challenge_is_rejected() {
+        let verifier = verifier();
+        let challenge = challenge(&verifier);
+        let solution = solve(&challenge, 8);
+
+        assert_err!(verifier.verify_at(&challenge, &solution, Utc::now() + Duration::hours(3)));
+    }
+
+    #[test]
+    fn a_challenge_issued_with_another_secret_is_rejected() {
+        let other = ProofOfWorkVerifier::new(Secret::new("other".into()), 8, Duration::hours(2));
+        let challenge = challenge(&other);
+        let solution = solve(&challenge, 8);
+
+        assert_err!(verifier().verify_at(&challenge, &solution, Utc::now()));
+    }
+
+    #[test]
+    fn malformed_challenges_are_rejected() {
+        for challenge in ["", "abc", "1.abcd", "a.b.c"] {
+            let solution = solve(challenge, 8);
+            assert_err!(verifier().verify_at(challenge, &solution, Utc::now()));
+        }
+    }
+}
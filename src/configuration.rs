@@ -14,6 +14,9 @@ pub struct Settings {
     pub application: ApplicationSettings,
     pub database: DatabaseSettings,
     pub email_client: EmailClientSettings,
+    pub issue_delivery: IssueDeliverySettings,
+    pub tracking: TrackingSettings,
+    pub bot_protection: BotProtectionSettings,
     pub redis_uri: Secret<String>,
 }
 
@@ -23,6 +26,12 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    pub hmac_secret: Secret<String>,
+    /// Shared with the email provider to sign the events it posts to us.
+    pub email_events_webhook_secret: Secret<String>,
+    /// How long a confirmation link stays valid after it was sent.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub subscription_token_ttl_hours: u32,
 }
 
 #[derive(Clone, Deserialize)]
@@ -42,6 +51,86 @@ pub struct EmailClientSettings {
     pub smtp_username: String,
     pub smtp_password: Secret<String>,
     pub sender_email: String,
+    /// Newsletter sends are paced so that no more than this many go out per
+    /// second. Must be positive.
+    #[serde(deserialize_with = "deserialize_positive_rate")]
+    pub max_messages_per_second: f64,
+    /// Newsletter sending pauses for the rest of the day once this many have
+    /// gone out. Transactional emails aren't counted, so leave room for them
+    /// in the quota of the relay.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_messages_per_day: u32,
+}
+
+/// A number of messages per second that sends can be paced at.
+fn deserialize_positive_rate<'de, D>(deserializer: D) -> Result<f64, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let rate: f64 = deserialize_number_from_string(deserializer)?;
+    if !rate.is_finite() || rate <= 0.0 {
+        return Err(serde::de::Error::custom(format!(
+            "{rate} is not a valid number of messages per second, it must be positive"
+        )));
+    }
+    Ok(rate)
+}
+
+#[derive(Clone, Deserialize)]
+pub struct IssueDeliverySettings {
+    /// How many times a delivery is attempted before it is dead-lettered.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts: i16,
+    /// Delay before the first retry. It doubles with every further attempt.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub retry_base_delay_seconds: u64,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct TrackingSettings {
+    /// Rewrite links and add a tracking pixel to newsletter emails so that
+    /// opens and clicks are recorded.
+    pub enabled: bool,
+}
+
+/// How the public subscription endpoint tells people from bots.
+#[derive(Clone, Deserialize)]
+pub struct BotProtectionSettings {
+    /// Forms submitted sooner than this after they were served are rejected.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_seconds_to_submit: u32,
+    /// Forms, and their challenges, can't be submitted once they are older
+    /// than this.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_form_age_minutes: u32,
+    pub challenge: ChallengeSettings,
+}
+
+impl BotProtectionSettings {
+    pub fn min_time_to_submit(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.min_seconds_to_submit.into())
+    }
+
+    pub fn max_form_age(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.max_form_age_minutes.into())
+    }
+}
+
+/// The challenge subscription forms have to solve.
+#[derive(Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ChallengeSettings {
+    ProofOfWork {
+        /// The number of leading zero bits the hash of the solution must have.
+        /// Each additional bit doubles the work.
+        #[serde(deserialize_with = "deserialize_number_from_string")]
+        difficulty: u8,
+    },
+    /// A local stand-in for a CAPTCHA provider.
+    CaptchaStub {
+        site_key: String,
+        expected_response: Secret<String>,
+    },
 }
 
 impl EmailClientSettings {
@@ -125,3 +214,28 @@ impl TryFrom<String> for Environment {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct Pacing {
+        #[serde(deserialize_with = "super::deserialize_positive_rate")]
+        #[allow(dead_code)]
+        rate: f64,
+    }
+
+    #[test]
+    fn the_send_rate_must_be_positive() {
+        for rate in ["0", "-1", "\"0\""] {
+            let pacing = format!(r#"{{"rate": {rate}}}"#);
+            assert_err!(serde_json::from_str::<Pacing>(&pacing));
+        }
+        for rate in ["0.5", "\"10\""] {
+            let pacing = format!(r#"{{"rate": {rate}}}"#);
+            assert_ok!(serde_json::from_str::<Pacing>(&pacing));
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+
+use crate::{
+    configuration::{IssueDeliverySettings, Settings},
+    domain::SubscriberEmail,
+    email_client::{DynEmailClient, SendEmailError},
+    issue_delivery_worker::{retry_delay, ExecutionOutcome},
+    routes::confirmation_email,
+};
+
+/// Queue the confirmation emails of freshly stored subscription tokens, to be
+/// sent by the background worker once `transaction` commits.
+pub async fn enqueue_confirmation_emails(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_tokens: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO confirmation_email_queue (subscription_token, subscriber_id, list_id)
+        SELECT subscription_token, subscriber_id, list_id
+        FROM subscription_tokens
+        WHERE subscription_token = ANY($1)
+        "#,
+        subscription_tokens
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// Dequeue a single confirmation email that is due and try to send it.
+///
+/// Works like [`try_execute_task`](crate::issue_delivery_worker::try_execute_task):
+/// transient failures are retried with the backoff of `issue_delivery`, and
+/// the email is put back without counting the attempt when the send quota is
+/// used up. Sent emails stay in the table, see [`count_recent_sends`](crate::issue_delivery_worker::count_recent_sends).
+/// Emails that can't be sent are dropped, since the subscriber can ask for a
+/// new one by subscribing again, as are the emails of subscriptions that are
+/// no longer waiting for confirmation.
+#[tracing::instrument(skip_all, fields(subscriber_email = tracing::field::Empty), err)]
+pub async fn try_send_confirmation_email(
+    pool: &PgPool,
+    email_client: &DynEmailClient,
+    config: &Settings,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some((transaction, task)) = dequeue_task(pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    Span::current().record("subscriber_email", display(&task.email));
+
+    if task.list_status != "pending_confirmation" {
+        tracing::info!("Skipping a subscription that is no longer waiting for confirmation");
+        delete_task(transaction, &task).await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    }
+    let recipient = match SubscriberEmail::parse(task.email.clone()) {
+        Ok(recipient) => recipient,
+        Err(e) => {
+            tracing::error!(error.message = %e, "Dropping a confirmation email to an invalid address");
+            delete_task(transaction, &task).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+    let message = match confirmation_email(
+        &task.list_name,
+        &config.application.base_url,
+        &task.subscription_token,
+    ) {
+        Ok(message) => message,
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Dropping a confirmation email that could not be rendered"
+            );
+            delete_task(transaction, &task).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+
+    match email_client.send_email(&recipient, &message).await {
+        Ok(()) => mark_task_sent(transaction, &task).await?,
+        Err(SendEmailError::RateLimited(retry_after)) => {
+            tracing::warn!(
+                retry_in_seconds = retry_after.as_secs(),
+                "The send quota has been reached. Pausing deliveries."
+            );
+            postpone_task(transaction, &task, retry_after).await?;
+            return Ok(ExecutionOutcome::Paused(retry_after));
+        }
+        Err(e) => record_failure(transaction, &task, e, &config.issue_delivery).await?,
+    }
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+struct ConfirmationTask {
+    subscription_token: String,
+    n_attempts: i16,
+    email: String,
+    list_name: String,
+    list_status: String,
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(
+    pool: &PgPool,
+) -> Result<Option<(PgTransaction, ConfirmationTask)>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let task = sqlx::query_as!(
+        ConfirmationTask,
+        r#"
+        SELECT
+            confirmation_email_queue.subscription_token,
+            confirmation_email_queue.n_attempts,
+            subscriptions.email,
+            lists.name AS list_name,
+            list_subscriptions.status AS list_status
+        FROM confirmation_email_queue
+        JOIN subscriptions ON subscriptions.id = confirmation_email_queue.subscriber_id
+        JOIN lists ON lists.list_id = confirmation_email_queue.list_id
+        JOIN list_subscriptions ON
+            list_subscriptions.list_id = confirmation_email_queue.list_id AND
+            list_subscriptions.subscriber_id = confirmation_email_queue.subscriber_id
+        WHERE
+            confirmation_email_queue.sent_at IS NULL AND
+            confirmation_email_queue.execute_after <= now()
+        ORDER BY confirmation_email_queue.execute_after
+        FOR UPDATE OF confirmation_email_queue
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to dequeue a confirmation email")?;
+
+    Ok(task.map(|task| (transaction, task)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn mark_task_sent(
+    mut transaction: PgTransaction,
+    task: &ConfirmationTask,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE confirmation_email_queue
+        SET
+            n_attempts = n_attempts + 1,
+            sent_at = now()
+        WHERE subscription_token = $1
+        "#,
+        task.subscription_token
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to mark a confirmation email as sent")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the confirmation email task transaction")?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    mut transaction: PgTransaction,
+    task: &ConfirmationTask,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "DELETE FROM confirmation_email_queue WHERE subscription_token = $1",
+        task.subscription_token
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete a confirmation email task")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the confirmation email task transaction")?;
+
+    Ok(())
+}
+
+/// Put a task back in the queue without counting it as a failed attempt.
+#[tracing::instrument(skip_all)]
+async fn postpone_task(
+    mut transaction: PgTransaction,
+    task: &ConfirmationTask,
+    delay: Duration,
+) -> Result<(), anyhow::Error> {
+    let execute_after =
+        Utc::now() + chrono::Duration::from_std(delay).context("Postponement is too long")?;
+    sqlx::query!(
+        r#"
+        UPDATE confirmation_email_queue
+        SET execute_after = $2
+        WHERE subscription_token = $1
+        "#,
+        task.subscription_token,
+        execute_after
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to postpone a confirmation email task")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the confirmation email task transaction")?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn record_failure(
+    mut transaction: PgTransaction,
+    task: &ConfirmationTask,
+    error: SendEmailError,
+    settings: &IssueDeliverySettings,
+) -> Result<(), anyhow::Error> {
+    let n_attempts = task.n_attempts + 1;
+    if !error.is_transient() || n_attempts >= settings.max_attempts {
+        tracing::error!(
+            error.message = %error,
+            n_attempts,
+            "Failed to send a confirmation email. Dropping it.",
+        );
+        return delete_task(transaction, task).await;
+    }
+
+    let delay = retry_delay(settings, n_attempts);
+    tracing::warn!(
+        error.message = %error,
+        n_attempts,
+        retry_in_seconds = delay.as_secs(),
+        "Failed to send a confirmation email. Retrying later.",
+    );
+    sqlx::query!(
+        r#"
+        UPDATE confirmation_email_queue
+        SET
+            n_attempts = $2,
+            execute_after = $3,
+            last_error = $4
+        WHERE subscription_token = $1
+        "#,
+        task.subscription_token,
+        n_attempts,
+        Utc::now() + chrono::Duration::from_std(delay).context("Retry delay is too long")?,
+        error.to_string()
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to record a failed confirmation email")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the confirmation email task transaction")?;
+
+    Ok(())
+}
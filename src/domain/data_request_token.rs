@@ -0,0 +1,116 @@
+use chrono::{DateTime, TimeZone, Utc};
+use secrecy::Secret;
+use uuid::Uuid;
+
+use super::signature;
+
+/// A token in the links we email to subscribers who ask for a copy of their
+/// data or for its erasure.
+///
+/// It is the subscriber id and the time the token expires at, followed by a
+/// [signature](signature) of both. Unlike [`super::PreferencesToken`] it expires,
+/// since it gives access to everything we know about the subscriber.
+#[derive(Debug)]
+pub struct DataRequestToken(String);
+
+const PURPOSE: &str = "data-request";
+
+impl DataRequestToken {
+    pub fn generate(
+        subscriber_id: Uuid,
+        expires_at: DateTime<Utc>,
+        secret: &Secret<String>,
+    ) -> Self {
+        let expires_at = expires_at.timestamp();
+        let tag = signature::sign(
+            PURPOSE,
+            &[subscriber_id.as_bytes(), &expires_at.to_be_bytes()],
+            secret,
+        );
+
+        Self(format!("{}.{expires_at}.{tag}", subscriber_id.simple()))
+    }
+
+    /// Verify the token and return the subscriber it was issued for, as long
+    /// as it hasn't expired by `now`.
+    pub fn parse(s: &str, secret: &Secret<String>, now: DateTime<Utc>) -> Result<Uuid, String> {
+        let invalid = || "The data request token is not valid".to_string();
+        let mut parts = s.split('.');
+        let (Some(subscriber_id), Some(expires_at), Some(tag), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        let subscriber_id = Uuid::try_parse(subscriber_id).map_err(|_| invalid())?;
+        let expires_at: i64 = expires_at.parse().map_err(|_| invalid())?;
+        signature::verify(
+            PURPOSE,
+            &[subscriber_id.as_bytes(), &expires_at.to_be_bytes()],
+            tag,
+            secret,
+        )
+        .map_err(|_| invalid())?;
+        match Utc.timestamp_opt(expires_at, 0).single() {
+            Some(expires_at) if now < expires_at => Ok(subscriber_id),
+            _ => Err("The data request token has expired".to_string()),
+        }
+    }
+}
+
+impl AsRef<str> for DataRequestToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use claims::{assert_err, assert_ok_eq};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    use super::DataRequestToken;
+    use crate::domain::PreferencesToken;
+
+    fn secret() -> Secret<String> {
+        Secret::new("super-secret".to_string())
+    }
+
+    #[test]
+    fn a_generated_token_is_accepted_until_it_expires() {
+        let subscriber_id = Uuid::new_v4();
+        let now = Utc::now();
+        let token = DataRequestToken::generate(subscriber_id, now + Duration::hours(1), &secret());
+
+        assert_ok_eq!(
+            DataRequestToken::parse(token.as_ref(), &secret(), now),
+            subscriber_id
+        );
+        assert_err!(DataRequestToken::parse(
+            token.as_ref(),
+            &secret(),
+            now + Duration::hours(2)
+        ));
+    }
+
+    #[test]
+    fn an_extended_expiry_is_rejected() {
+        let token =
+            DataRequestToken::generate(Uuid::new_v4(), Utc::now() + Duration::hours(1), &secret());
+        let parts: Vec<_> = token.as_ref().split('.').collect();
+        let later = (Utc::now() + Duration::days(365)).timestamp();
+        let forged = format!("{}.{later}.{}", parts[0], parts[2]);
+        assert_err!(DataRequestToken::parse(&forged, &secret(), Utc::now()));
+    }
+
+    #[test]
+    fn a_preferences_token_is_rejected() {
+        let token = PreferencesToken::generate(Uuid::new_v4(), &secret());
+        assert_err!(DataRequestToken::parse(
+            token.as_ref(),
+            &secret(),
+            Utc::now()
+        ));
+    }
+}
@@ -0,0 +1,59 @@
+/// How often a subscriber would like to hear from us.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeliveryFrequency {
+    EveryIssue,
+    Weekly,
+    Monthly,
+}
+
+impl DeliveryFrequency {
+    pub const ALL: [DeliveryFrequency; 3] = [
+        DeliveryFrequency::EveryIssue,
+        DeliveryFrequency::Weekly,
+        DeliveryFrequency::Monthly,
+    ];
+
+    pub fn parse(s: &str) -> Result<DeliveryFrequency, String> {
+        Self::ALL
+            .into_iter()
+            .find(|frequency| frequency.as_str() == s)
+            .ok_or_else(|| format!("{s} is not a valid delivery frequency"))
+    }
+
+    /// The value stored in the database and used in forms.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryFrequency::EveryIssue => "every_issue",
+            DeliveryFrequency::Weekly => "weekly",
+            DeliveryFrequency::Monthly => "monthly",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            DeliveryFrequency::EveryIssue => "Every issue",
+            DeliveryFrequency::Weekly => "At most once a week",
+            DeliveryFrequency::Monthly => "At most once a month",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::DeliveryFrequency;
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn every_frequency_can_be_parsed_back() {
+        for frequency in DeliveryFrequency::ALL {
+            assert_ok_eq!(DeliveryFrequency::parse(frequency.as_str()), frequency);
+        }
+    }
+
+    #[test]
+    fn unknown_frequencies_are_rejected() {
+        for frequency in ["", "daily", "Weekly"] {
+            assert_err!(DeliveryFrequency::parse(frequency));
+        }
+    }
+}
@@ -0,0 +1,69 @@
+/// The short name of a mailing list used in URLs and API requests, e.g.
+/// `weekly-digest`.
+#[derive(Debug)]
+pub struct ListSlug(String);
+
+impl ListSlug {
+    pub fn parse(s: String) -> Result<ListSlug, String> {
+        let is_empty = s.is_empty();
+        let is_too_long = s.len() > 64;
+        let has_invalid_characters = !s
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+        let has_dangling_hyphen = s.starts_with('-') || s.ends_with('-');
+
+        if is_empty || is_too_long || has_invalid_characters || has_dangling_hyphen {
+            return Err(format!(
+                "{s} is not a valid list slug - use lowercase letters, digits and hyphens"
+            ));
+        }
+
+        Ok(Self(s))
+    }
+}
+
+impl AsRef<str> for ListSlug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::ListSlug;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn a_64_character_long_slug_is_valid() {
+        assert_ok!(ListSlug::parse("a".repeat(64)));
+    }
+
+    #[test]
+    fn a_slug_longer_than_64_characters_is_rejected() {
+        assert_err!(ListSlug::parse("a".repeat(65)));
+    }
+
+    #[test]
+    fn empty_string_is_rejected() {
+        assert_err!(ListSlug::parse("".to_string()));
+    }
+
+    #[test]
+    fn slugs_with_invalid_characters_are_rejected() {
+        for slug in ["Weekly", "weekly digest", "weekly_digest", "wöchentlich"] {
+            assert_err!(ListSlug::parse(slug.to_string()));
+        }
+    }
+
+    #[test]
+    fn slugs_starting_or_ending_with_a_hyphen_are_rejected() {
+        for slug in ["-weekly", "weekly-"] {
+            assert_err!(ListSlug::parse(slug.to_string()));
+        }
+    }
+
+    #[test]
+    fn a_valid_slug_is_parsed_successfully() {
+        assert_ok!(ListSlug::parse("weekly-digest-2".to_string()));
+    }
+}
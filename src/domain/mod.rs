@@ -1,7 +1,24 @@
+mod data_request_token;
+mod delivery_frequency;
+mod list_slug;
 mod new_subscriber;
+mod preferences_token;
+pub mod signature;
+mod subscribe_form_token;
 mod subscriber_email;
 mod subscriber_name;
+mod subscriber_tag;
+mod tracking_token;
+mod unsubscribe_token;
 
+pub use data_request_token::DataRequestToken;
+pub use delivery_frequency::DeliveryFrequency;
+pub use list_slug::ListSlug;
 pub use new_subscriber::NewSubscriber;
+pub use preferences_token::PreferencesToken;
+pub use subscribe_form_token::SubscribeFormToken;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use subscriber_tag::SubscriberTag;
+pub use tracking_token::{TrackedRecipient, TrackingToken};
+pub use unsubscribe_token::{UnsubscribeTarget, UnsubscribeToken};
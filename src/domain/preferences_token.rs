@@ -0,0 +1,77 @@
+use secrecy::Secret;
+use uuid::Uuid;
+
+use super::signature;
+
+/// A token identifying a subscriber in links to their preference center.
+///
+/// It is the subscriber id followed by its [signature](signature), so it
+/// can be verified without storing anything and cannot be forged for other
+/// subscribers.
+#[derive(Debug)]
+pub struct PreferencesToken(String);
+
+const PURPOSE: &str = "preferences";
+
+impl PreferencesToken {
+    pub fn generate(subscriber_id: Uuid, secret: &Secret<String>) -> Self {
+        let tag = signature::sign(PURPOSE, &[subscriber_id.as_bytes()], secret);
+
+        Self(format!("{}.{tag}", subscriber_id.simple()))
+    }
+
+    /// Verify the token and return the subscriber it was issued for.
+    pub fn parse(s: &str, secret: &Secret<String>) -> Result<Uuid, String> {
+        let invalid = || "The preferences token is not valid".to_string();
+        let (subscriber_id, tag) = s.split_once('.').ok_or_else(invalid)?;
+        let subscriber_id = Uuid::try_parse(subscriber_id).map_err(|_| invalid())?;
+        signature::verify(PURPOSE, &[subscriber_id.as_bytes()], tag, secret)
+            .map_err(|_| invalid())?;
+
+        Ok(subscriber_id)
+    }
+}
+
+impl AsRef<str> for PreferencesToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok_eq};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    use super::PreferencesToken;
+    use crate::domain::UnsubscribeToken;
+
+    fn secret() -> Secret<String> {
+        Secret::new("super-secret".to_string())
+    }
+
+    #[test]
+    fn a_generated_token_is_accepted() {
+        let subscriber_id = Uuid::new_v4();
+        let token = PreferencesToken::generate(subscriber_id, &secret());
+        assert_ok_eq!(
+            PreferencesToken::parse(token.as_ref(), &secret()),
+            subscriber_id
+        );
+    }
+
+    #[test]
+    fn a_token_for_another_subscriber_is_rejected() {
+        let token = PreferencesToken::generate(Uuid::new_v4(), &secret());
+        let (_, tag) = token.as_ref().split_once('.').unwrap();
+        let forged = format!("{}.{tag}", Uuid::new_v4().simple());
+        assert_err!(PreferencesToken::parse(&forged, &secret()));
+    }
+
+    #[test]
+    fn an_unsubscribe_token_is_rejected() {
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), Uuid::new_v4(), &secret());
+        assert_err!(PreferencesToken::parse(token.as_ref(), &secret()));
+    }
+}
@@ -0,0 +1,102 @@
+//! HMAC-SHA256 signatures for the tokens we hand out and verify later, so
+//! that they can be checked without storing anything.
+//!
+//! Every signature is computed over a purpose, e.g. `unsubscribe`, followed by
+//! the message, so a token issued for one purpose can't be used for another
+//! even when the messages look the same.
+
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+/// The signature does not match the message.
+#[derive(Debug, thiserror::Error)]
+#[error("The signature is not valid")]
+pub struct InvalidSignature;
+
+/// The hex-encoded signature of the concatenation of `message` for `purpose`.
+pub fn sign(purpose: &str, message: &[&[u8]], secret: &Secret<String>) -> String {
+    hex::encode(mac(Some(purpose), message, secret).finalize().into_bytes())
+}
+
+/// Check a signature produced by [`sign`].
+pub fn verify(
+    purpose: &str,
+    message: &[&[u8]],
+    signature: &str,
+    secret: &Secret<String>,
+) -> Result<(), InvalidSignature> {
+    verify_mac(mac(Some(purpose), message, secret), signature)
+}
+
+/// Check a hex-encoded signature computed by someone else over `message`
+/// alone, like the one our email provider puts on its webhooks.
+pub fn verify_external(
+    message: &[u8],
+    signature: &str,
+    secret: &Secret<String>,
+) -> Result<(), InvalidSignature> {
+    verify_mac(mac(None, &[message], secret), signature)
+}
+
+fn mac(purpose: Option<&str>, message: &[&[u8]], secret: &Secret<String>) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    if let Some(purpose) = purpose {
+        mac.update(purpose.as_bytes());
+        mac.update(b":");
+    }
+    for part in message {
+        mac.update(part);
+    }
+    mac
+}
+
+fn verify_mac(mac: Hmac<Sha256>, signature: &str) -> Result<(), InvalidSignature> {
+    let signature = hex::decode(signature).map_err(|_| InvalidSignature)?;
+    mac.verify_slice(&signature).map_err(|_| InvalidSignature)
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+    use secrecy::Secret;
+
+    use super::{sign, verify, verify_external};
+
+    fn secret() -> Secret<String> {
+        Secret::new("super-secret".to_string())
+    }
+
+    #[test]
+    fn a_signature_is_accepted() {
+        let signature = sign("test", &[b"abc", b"def"], &secret());
+        assert_ok!(verify("test", &[b"abc", b"def"], &signature, &secret()));
+    }
+
+    #[test]
+    fn a_signature_made_with_another_secret_is_rejected() {
+        let signature = sign("test", &[b"abc"], &Secret::new("other".into()));
+        assert_err!(verify("test", &[b"abc"], &signature, &secret()));
+    }
+
+    #[test]
+    fn a_signature_for_another_purpose_is_rejected() {
+        let signature = sign("other", &[b"abc"], &secret());
+        assert_err!(verify("test", &[b"abc"], &signature, &secret()));
+    }
+
+    #[test]
+    fn a_signature_of_another_message_is_rejected() {
+        let signature = sign("test", &[b"abc"], &secret());
+        assert_err!(verify("test", &[b"abd"], &signature, &secret()));
+    }
+
+    #[test]
+    fn malformed_signatures_are_rejected() {
+        for signature in ["", "not-hex", "abcd"] {
+            assert_err!(verify("test", &[b"abc"], signature, &secret()));
+            assert_err!(verify_external(b"abc", signature, &secret()));
+        }
+    }
+}
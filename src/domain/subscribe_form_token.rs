@@ -0,0 +1,74 @@
+use chrono::{DateTime, TimeZone, Utc};
+use secrecy::Secret;
+
+use super::signature;
+
+/// A token embedded in the subscription form, recording when the form was
+/// served.
+///
+/// It is the time the form was issued at, followed by its
+/// [signature](signature), so that the time can't be moved back to pass for a human who took a while
+/// to fill in the form.
+#[derive(Debug)]
+pub struct SubscribeFormToken(String);
+
+const PURPOSE: &str = "subscribe-form";
+
+impl SubscribeFormToken {
+    pub fn generate(issued_at: DateTime<Utc>, secret: &Secret<String>) -> Self {
+        let issued_at = issued_at.timestamp();
+        let tag = signature::sign(PURPOSE, &[&issued_at.to_be_bytes()], secret);
+
+        Self(format!("{issued_at}.{tag}"))
+    }
+
+    /// Verify the token and return the time the form was issued at.
+    pub fn parse(s: &str, secret: &Secret<String>) -> Result<DateTime<Utc>, String> {
+        let invalid = || "The form token is not valid".to_string();
+        let (issued_at, tag) = s.split_once('.').ok_or_else(invalid)?;
+        let issued_at: i64 = issued_at.parse().map_err(|_| invalid())?;
+        signature::verify(PURPOSE, &[&issued_at.to_be_bytes()], tag, secret)
+            .map_err(|_| invalid())?;
+
+        Utc.timestamp_opt(issued_at, 0).single().ok_or_else(invalid)
+    }
+}
+
+impl AsRef<str> for SubscribeFormToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+    use claims::{assert_err, assert_ok_eq};
+    use secrecy::Secret;
+
+    use super::SubscribeFormToken;
+
+    fn secret() -> Secret<String> {
+        Secret::new("super-secret".to_string())
+    }
+
+    #[test]
+    fn a_generated_token_is_accepted() {
+        let issued_at = Utc.with_ymd_and_hms(2023, 12, 23, 10, 0, 0).unwrap();
+        let token = SubscribeFormToken::generate(issued_at, &secret());
+
+        assert_ok_eq!(
+            SubscribeFormToken::parse(token.as_ref(), &secret()),
+            issued_at
+        );
+    }
+
+    #[test]
+    fn an_earlier_issue_time_is_rejected() {
+        let token = SubscribeFormToken::generate(Utc::now(), &secret());
+        let (_, tag) = token.as_ref().split_once('.').unwrap();
+        let earlier = (Utc::now() - Duration::minutes(5)).timestamp();
+        let forged = format!("{earlier}.{tag}");
+        assert_err!(SubscribeFormToken::parse(&forged, &secret()));
+    }
+}
@@ -32,9 +32,6 @@ mod tests {
     use fake::faker::internet::en::SafeEmail;
     use fake::Fake;
 
-    #[derive(Debug, Clone)]
-    struct ValidEmailFixture(pub String);
-
     #[test]
     fn empty_string_is_rejected() {
         let email = "".to_string();
@@ -0,0 +1,61 @@
+/// A label put on the subscribers of a list, e.g. `beta` or `paid`, which
+/// segments use to pick who an issue goes to.
+#[derive(Debug)]
+pub struct SubscriberTag(String);
+
+impl SubscriberTag {
+    pub fn parse(s: String) -> Result<SubscriberTag, String> {
+        let is_empty = s.is_empty();
+        let is_too_long = s.len() > 64;
+        let has_invalid_characters = !s
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+
+        if is_empty || is_too_long || has_invalid_characters {
+            return Err(format!(
+                "{s} is not a valid tag - use lowercase letters, digits, hyphens and underscores"
+            ));
+        }
+
+        Ok(Self(s))
+    }
+}
+
+impl AsRef<str> for SubscriberTag {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberTag;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn a_64_character_long_tag_is_valid() {
+        assert_ok!(SubscriberTag::parse("a".repeat(64)));
+    }
+
+    #[test]
+    fn a_tag_longer_than_64_characters_is_rejected() {
+        assert_err!(SubscriberTag::parse("a".repeat(65)));
+    }
+
+    #[test]
+    fn empty_string_is_rejected() {
+        assert_err!(SubscriberTag::parse("".to_string()));
+    }
+
+    #[test]
+    fn tags_with_invalid_characters_are_rejected() {
+        for tag in ["Beta", "early access", "bêta", "paid!"] {
+            assert_err!(SubscriberTag::parse(tag.to_string()));
+        }
+    }
+
+    #[test]
+    fn a_valid_tag_is_parsed_successfully() {
+        assert_ok!(SubscriberTag::parse("early_access-2".to_string()));
+    }
+}
@@ -0,0 +1,154 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use secrecy::Secret;
+use uuid::Uuid;
+
+use super::signature;
+
+/// A token embedded in tracked links and tracking pixels of a newsletter
+/// issue.
+///
+/// It names the issue and the subscriber it was sent to and, for links, the
+/// URL the link points to. Like [`super::UnsubscribeToken`] it carries a
+/// [signature](signature), so nothing needs to be stored to resolve it and it can't
+/// be used to record events for someone else or to redirect elsewhere.
+#[derive(Debug)]
+pub struct TrackingToken(String);
+
+const PURPOSE: &str = "tracking";
+
+/// What a verified [`TrackingToken`] refers to.
+#[derive(Debug, PartialEq)]
+pub struct TrackedRecipient {
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_id: Uuid,
+    /// Only set for links.
+    pub url: Option<String>,
+}
+
+impl TrackingToken {
+    pub fn open(newsletter_issue_id: Uuid, subscriber_id: Uuid, secret: &Secret<String>) -> Self {
+        Self::sign(
+            format!(
+                "{}.{}",
+                newsletter_issue_id.simple(),
+                subscriber_id.simple()
+            ),
+            secret,
+        )
+    }
+
+    pub fn click(
+        newsletter_issue_id: Uuid,
+        subscriber_id: Uuid,
+        url: &str,
+        secret: &Secret<String>,
+    ) -> Self {
+        Self::sign(
+            format!(
+                "{}.{}.{}",
+                newsletter_issue_id.simple(),
+                subscriber_id.simple(),
+                URL_SAFE_NO_PAD.encode(url)
+            ),
+            secret,
+        )
+    }
+
+    /// Verify the token and return what it was issued for.
+    pub fn parse(s: &str, secret: &Secret<String>) -> Result<TrackedRecipient, String> {
+        let invalid = || "The tracking token is not valid".to_string();
+        let (payload, tag) = s.rsplit_once('.').ok_or_else(invalid)?;
+        signature::verify(PURPOSE, &[payload.as_bytes()], tag, secret).map_err(|_| invalid())?;
+
+        let mut parts = payload.split('.');
+        let mut next_uuid = || {
+            parts
+                .next()
+                .and_then(|part| Uuid::try_parse(part).ok())
+                .ok_or_else(invalid)
+        };
+        let newsletter_issue_id = next_uuid()?;
+        let subscriber_id = next_uuid()?;
+        let url = match parts.next() {
+            Some(url) => {
+                let url = URL_SAFE_NO_PAD.decode(url).map_err(|_| invalid())?;
+                Some(String::from_utf8(url).map_err(|_| invalid())?)
+            }
+            None => None,
+        };
+
+        Ok(TrackedRecipient {
+            newsletter_issue_id,
+            subscriber_id,
+            url,
+        })
+    }
+
+    fn sign(payload: String, secret: &Secret<String>) -> Self {
+        let tag = signature::sign(PURPOSE, &[payload.as_bytes()], secret);
+
+        Self(format!("{payload}.{tag}"))
+    }
+}
+
+impl AsRef<str> for TrackingToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok_eq};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    use super::{TrackedRecipient, TrackingToken};
+
+    fn secret() -> Secret<String> {
+        Secret::new("super-secret".to_string())
+    }
+
+    #[test]
+    fn an_open_token_is_accepted() {
+        let (issue_id, subscriber_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let token = TrackingToken::open(issue_id, subscriber_id, &secret());
+        assert_ok_eq!(
+            TrackingToken::parse(token.as_ref(), &secret()),
+            TrackedRecipient {
+                newsletter_issue_id: issue_id,
+                subscriber_id,
+                url: None,
+            }
+        );
+    }
+
+    #[test]
+    fn a_click_token_carries_its_url() {
+        let (issue_id, subscriber_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let url = "https://example.com/a.b?c=d&e=f";
+        let token = TrackingToken::click(issue_id, subscriber_id, url, &secret());
+        assert_ok_eq!(
+            TrackingToken::parse(token.as_ref(), &secret()),
+            TrackedRecipient {
+                newsletter_issue_id: issue_id,
+                subscriber_id,
+                url: Some(url.to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn a_token_pointing_elsewhere_is_rejected() {
+        let token = TrackingToken::click(
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            "https://example.com",
+            &secret(),
+        );
+        let (payload, tag) = token.as_ref().rsplit_once('.').unwrap();
+        let (ids, _) = payload.rsplit_once('.').unwrap();
+        let forged = format!("{ids}.aHR0cHM6Ly9ldmlsLmNvbQ.{tag}");
+        assert_err!(TrackingToken::parse(&forged, &secret()));
+    }
+}
@@ -0,0 +1,161 @@
+use secrecy::Secret;
+use uuid::Uuid;
+
+use super::signature;
+
+/// A token identifying a subscriber and a mailing list in one-click
+/// unsubscribe links.
+///
+/// It is the subscriber id and the list id followed by a
+/// [signature](signature) of both, so it can be verified without storing anything and cannot be forged
+/// for other subscribers or lists. Tokens sent before there were several lists
+/// only hold the subscriber id and still unsubscribe from every list.
+#[derive(Debug)]
+pub struct UnsubscribeToken(String);
+
+/// Who a verified [`UnsubscribeToken`] unsubscribes, and from which list.
+#[derive(Debug, PartialEq)]
+pub struct UnsubscribeTarget {
+    pub subscriber_id: Uuid,
+    /// `None` for tokens that predate mailing lists, which apply to all lists.
+    pub list_id: Option<Uuid>,
+}
+
+const PURPOSE: &str = "unsubscribe";
+
+impl UnsubscribeToken {
+    pub fn generate(subscriber_id: Uuid, list_id: Uuid, secret: &Secret<String>) -> Self {
+        let tag = Self::sign(subscriber_id, Some(list_id), secret);
+
+        Self(format!(
+            "{}.{}.{tag}",
+            subscriber_id.simple(),
+            list_id.simple()
+        ))
+    }
+
+    /// Verify the token and return the subscriber and list it was issued for.
+    pub fn parse(s: &str, secret: &Secret<String>) -> Result<UnsubscribeTarget, String> {
+        let invalid = || "The unsubscribe token is not valid".to_string();
+        let (ids, tag) = s.rsplit_once('.').ok_or_else(invalid)?;
+        let (subscriber_id, list_id) = match ids.split_once('.') {
+            Some((subscriber_id, list_id)) => (subscriber_id, Some(list_id)),
+            None => (ids, None),
+        };
+        let subscriber_id = Uuid::try_parse(subscriber_id).map_err(|_| invalid())?;
+        let list_id = list_id
+            .map(|list_id| Uuid::try_parse(list_id).map_err(|_| invalid()))
+            .transpose()?;
+        signature::verify(
+            PURPOSE,
+            &Self::message(&subscriber_id, &list_id),
+            tag,
+            secret,
+        )
+        .map_err(|_| invalid())?;
+
+        Ok(UnsubscribeTarget {
+            subscriber_id,
+            list_id,
+        })
+    }
+
+    fn sign(subscriber_id: Uuid, list_id: Option<Uuid>, secret: &Secret<String>) -> String {
+        signature::sign(PURPOSE, &Self::message(&subscriber_id, &list_id), secret)
+    }
+
+    fn message<'a>(subscriber_id: &'a Uuid, list_id: &'a Option<Uuid>) -> Vec<&'a [u8]> {
+        let mut message = vec![subscriber_id.as_bytes().as_slice()];
+        message.extend(
+            list_id
+                .as_ref()
+                .map(|list_id| list_id.as_bytes().as_slice()),
+        );
+        message
+    }
+}
+
+impl AsRef<str> for UnsubscribeToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok_eq};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    use super::{UnsubscribeTarget, UnsubscribeToken};
+
+    fn secret() -> Secret<String> {
+        Secret::new("super-secret".to_string())
+    }
+
+    #[test]
+    fn a_generated_token_is_accepted() {
+        let subscriber_id = Uuid::new_v4();
+        let list_id = Uuid::new_v4();
+        let token = UnsubscribeToken::generate(subscriber_id, list_id, &secret());
+        assert_ok_eq!(
+            UnsubscribeToken::parse(token.as_ref(), &secret()),
+            UnsubscribeTarget {
+                subscriber_id,
+                list_id: Some(list_id),
+            }
+        );
+    }
+
+    #[test]
+    fn a_token_without_a_list_is_accepted() {
+        let subscriber_id = Uuid::new_v4();
+        let tag = UnsubscribeToken::sign(subscriber_id, None, &secret());
+        let token = format!("{}.{tag}", subscriber_id.simple());
+        assert_ok_eq!(
+            UnsubscribeToken::parse(&token, &secret()),
+            UnsubscribeTarget {
+                subscriber_id,
+                list_id: None,
+            }
+        );
+    }
+
+    #[test]
+    fn a_token_for_another_subscriber_is_rejected() {
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), Uuid::new_v4(), &secret());
+        let (_, rest) = token.as_ref().split_once('.').unwrap();
+        let forged = format!("{}.{rest}", Uuid::new_v4().simple());
+        assert_err!(UnsubscribeToken::parse(&forged, &secret()));
+    }
+
+    #[test]
+    fn a_token_for_another_list_is_rejected() {
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), Uuid::new_v4(), &secret());
+        let (subscriber_id, rest) = token.as_ref().split_once('.').unwrap();
+        let (_, tag) = rest.split_once('.').unwrap();
+        let forged = format!("{subscriber_id}.{}.{tag}", Uuid::new_v4().simple());
+        assert_err!(UnsubscribeToken::parse(&forged, &secret()));
+    }
+
+    #[test]
+    fn a_token_stripped_of_its_list_is_rejected() {
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), Uuid::new_v4(), &secret());
+        let (subscriber_id, rest) = token.as_ref().split_once('.').unwrap();
+        let (_, tag) = rest.split_once('.').unwrap();
+        let forged = format!("{subscriber_id}.{tag}");
+        assert_err!(UnsubscribeToken::parse(&forged, &secret()));
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        for token in [
+            "",
+            "abc",
+            "not-a-uuid.abcd",
+            &Uuid::new_v4().simple().to_string(),
+        ] {
+            assert_err!(UnsubscribeToken::parse(token, &secret()));
+        }
+    }
+}
@@ -1,19 +1,63 @@
-use std::sync::Arc;
+mod rate_limit;
+
+use std::{sync::Arc, time::Duration};
 
 use axum::async_trait;
 use lettre::{
-    error::Error as EmailError, message::header::ContentType, transport::smtp::Error as SmtpError,
+    error::Error as EmailError,
+    message::{
+        header::{Header, HeaderName, HeaderValue},
+        MultiPart,
+    },
+    transport::smtp::Error as SmtpError,
     AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
 };
 
 use crate::domain::SubscriberEmail;
 
+pub use rate_limit::{Clock, RateLimitedEmailClient, RateLimiter, SystemClock};
+
 #[derive(Debug, thiserror::Error)]
 pub enum SendEmailError {
     #[error(transparent)]
     EmailError(#[from] EmailError),
     #[error(transparent)]
     SmtpError(#[from] SmtpError),
+    /// The daily send quota is used up. Sending can resume after the given
+    /// delay.
+    #[error("The daily send quota has been reached")]
+    RateLimited(Duration),
+}
+
+impl SendEmailError {
+    /// Whether sending the same email again later could succeed.
+    ///
+    /// SMTP failures are retryable unless the server rejected the message with
+    /// a permanent (5xx) response. Errors building the message never are.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::EmailError(_) => false,
+            Self::SmtpError(e) => !e.is_permanent(),
+            Self::RateLimited(_) => true,
+        }
+    }
+}
+
+/// An email with an HTML body and a plain-text alternative for clients that
+/// don't render HTML.
+#[derive(Clone, Debug)]
+pub struct EmailMessage {
+    pub subject: String,
+    pub html_content: String,
+    pub text_content: String,
+    /// Set for bulk emails. The URL must accept a one-click `POST` as
+    /// described in RFC 8058.
+    pub unsubscribe_url: Option<String>,
+}
+
+/// Derive a plain-text body from an HTML body.
+pub fn html_to_text(html_content: &str) -> String {
+    html2text::from_read(html_content.as_bytes(), 80)
 }
 
 #[async_trait]
@@ -21,8 +65,7 @@ pub trait EmailClient {
     async fn send_email(
         &self,
         recipient: &SubscriberEmail,
-        subject: &str,
-        html_content: &str,
+        message: &EmailMessage,
     ) -> Result<(), SendEmailError>;
 }
 
@@ -45,18 +88,152 @@ impl EmailClient for SmtpEmailClient {
     async fn send_email(
         &self,
         recipient: &SubscriberEmail,
-        subject: &str,
-        html_content: &str,
+        message: &EmailMessage,
     ) -> Result<(), SendEmailError> {
-        let email = Message::builder()
-            .from(self.sender.as_ref().parse().unwrap())
-            .to(recipient.as_ref().parse().unwrap())
-            .subject(subject)
-            .header(ContentType::TEXT_HTML)
-            .body(html_content.to_string())?;
+        let email = self.build_message(recipient, message)?;
 
         self.mailer.send(email).await?;
 
         Ok(())
     }
 }
+
+impl SmtpEmailClient {
+    fn build_message(
+        &self,
+        recipient: &SubscriberEmail,
+        message: &EmailMessage,
+    ) -> Result<Message, EmailError> {
+        let mut builder = Message::builder()
+            .from(self.sender.as_ref().parse().unwrap())
+            .to(recipient.as_ref().parse().unwrap())
+            .subject(&message.subject);
+        if let Some(url) = &message.unsubscribe_url {
+            builder = builder
+                .header(ListUnsubscribe(url.clone()))
+                .header(ListUnsubscribePost);
+        }
+
+        builder.multipart(MultiPart::alternative_plain_html(
+            message.text_content.clone(),
+            message.html_content.clone(),
+        ))
+    }
+}
+
+/// The `List-Unsubscribe` header from RFC 2369.
+#[derive(Clone)]
+struct ListUnsubscribe(String);
+
+impl Header for ListUnsubscribe {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("List-Unsubscribe")
+    }
+
+    fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let url = s
+            .strip_prefix('<')
+            .and_then(|s| s.strip_suffix('>'))
+            .ok_or("List-Unsubscribe must be enclosed in angle brackets")?;
+        Ok(Self(url.to_owned()))
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), format!("<{}>", self.0))
+    }
+}
+
+/// The `List-Unsubscribe-Post` header from RFC 8058, which tells mailbox
+/// providers that the `List-Unsubscribe` URL supports one-click unsubscribing.
+#[derive(Clone)]
+struct ListUnsubscribePost;
+
+impl Header for ListUnsubscribePost {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("List-Unsubscribe-Post")
+    }
+
+    fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        match s {
+            "List-Unsubscribe=One-Click" => Ok(Self),
+            _ => Err("List-Unsubscribe-Post must be List-Unsubscribe=One-Click".into()),
+        }
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), "List-Unsubscribe=One-Click".to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lettre::{AsyncSmtpTransport, Tokio1Executor};
+
+    use super::{html_to_text, EmailMessage, SmtpEmailClient};
+    use crate::domain::SubscriberEmail;
+
+    fn email_client() -> SmtpEmailClient {
+        SmtpEmailClient::new(
+            AsyncSmtpTransport::<Tokio1Executor>::unencrypted_localhost(),
+            SubscriberEmail::parse("sender@example.com".into()).unwrap(),
+        )
+    }
+
+    fn recipient() -> SubscriberEmail {
+        SubscriberEmail::parse("recipient@example.com".into()).unwrap()
+    }
+
+    fn message(unsubscribe_url: Option<&str>) -> EmailMessage {
+        EmailMessage {
+            subject: "Subject".into(),
+            html_content: "<p>HTML content</p>".into(),
+            text_content: "Text content".into(),
+            unsubscribe_url: unsubscribe_url.map(ToOwned::to_owned),
+        }
+    }
+
+    fn format(message: &EmailMessage) -> String {
+        let message = email_client().build_message(&recipient(), message).unwrap();
+        String::from_utf8(message.formatted()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn emails_are_sent_as_multipart_alternative() {
+        let formatted = format(&message(None));
+
+        assert!(formatted.contains("Content-Type: multipart/alternative;"));
+        assert!(formatted.contains("Content-Type: text/plain; charset=utf-8"));
+        assert!(formatted.contains("Text content"));
+        assert!(formatted.contains("Content-Type: text/html; charset=utf-8"));
+        assert!(formatted.contains("<p>HTML content</p>"));
+    }
+
+    #[tokio::test]
+    async fn bulk_emails_have_one_click_unsubscribe_headers() {
+        let formatted = format(&message(Some(
+            "https://example.com/subscriptions/unsubscribe?token=abc",
+        )));
+
+        assert!(formatted.contains(
+            "List-Unsubscribe: <https://example.com/subscriptions/unsubscribe?token=abc>\r\n"
+        ));
+        assert!(formatted.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click\r\n"));
+    }
+
+    #[tokio::test]
+    async fn transactional_emails_have_no_unsubscribe_headers() {
+        let formatted = format(&message(None));
+
+        assert!(!formatted.contains("List-Unsubscribe"));
+    }
+
+    #[test]
+    fn html_is_converted_to_text_without_tags() {
+        let text =
+            html_to_text(r#"<h1>Title</h1><p>Read <a href="https://example.com">this</a>.</p>"#);
+
+        assert!(!text.contains('<'));
+        assert!(text.contains("Title"));
+        assert!(text.contains("https://example.com"));
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::async_trait;
+
+use crate::{configuration::EmailClientSettings, domain::SubscriberEmail};
+
+use super::{DynEmailClient, EmailClient, EmailMessage, SendEmailError};
+
+const QUOTA_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The source of time for [`RateLimiter`], so that tests don't have to wait.
+#[async_trait]
+pub trait Clock {
+    fn now(&self) -> Instant;
+    async fn sleep(&self, duration: Duration);
+}
+
+pub struct SystemClock;
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+}
+
+/// Paces sends with a token bucket and caps how many are sent per day.
+///
+/// The daily quota is counted over a 24 hour window that starts with the first
+/// send after the previous window ended. The limiter only counts the sends of
+/// its own process, so the limits have to be split between replicas, and the
+/// sends made before it was created have to be passed to
+/// [`resume_window`](Self::resume_window).
+pub struct RateLimiter {
+    clock: Arc<dyn Clock + Send + Sync>,
+    messages_per_second: f64,
+    messages_per_day: u32,
+    state: Mutex<RateLimiterState>,
+}
+
+struct RateLimiterState {
+    tokens: f64,
+    last_refill: Instant,
+    sent_in_window: u32,
+    window_start: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(
+        messages_per_second: f64,
+        messages_per_day: u32,
+        clock: Arc<dyn Clock + Send + Sync>,
+    ) -> Self {
+        let now = clock.now();
+        Self {
+            clock,
+            messages_per_second,
+            messages_per_day,
+            state: Mutex::new(RateLimiterState {
+                tokens: Self::burst(messages_per_second),
+                last_refill: now,
+                sent_in_window: 0,
+                window_start: now,
+            }),
+        }
+    }
+
+    /// Count `n_sent` messages sent before the limiter was created, e.g. by the
+    /// process it replaces, the first of them `window_age` ago, against the
+    /// daily quota.
+    pub fn resume_window(mut self, n_sent: u32, window_age: Duration) -> Self {
+        let state = self.state.get_mut().unwrap();
+        state.sent_in_window = n_sent;
+        if let Some(window_start) = state.window_start.checked_sub(window_age) {
+            state.window_start = window_start;
+        }
+        self
+    }
+
+    /// At most one second's worth of messages can be sent back to back.
+    fn burst(messages_per_second: f64) -> f64 {
+        messages_per_second.max(1.0)
+    }
+
+    /// Wait until the next message may be sent and count it against the quota.
+    ///
+    /// Fails with the time left until the quota resets if today's quota has
+    /// already been used up.
+    pub async fn acquire(&self) -> Result<(), Duration> {
+        loop {
+            let wait = self.try_acquire()?;
+            if wait.is_zero() {
+                return Ok(());
+            }
+            self.clock.sleep(wait).await;
+        }
+    }
+
+    /// Take a token if one is available, otherwise return how long it will
+    /// take for the next one to become available.
+    fn try_acquire(&self) -> Result<Duration, Duration> {
+        let now = self.clock.now();
+        let mut state = self.state.lock().unwrap();
+
+        if now.duration_since(state.window_start) >= QUOTA_WINDOW {
+            state.window_start = now;
+            state.sent_in_window = 0;
+        }
+        if state.sent_in_window >= self.messages_per_day {
+            return Err(QUOTA_WINDOW - now.duration_since(state.window_start));
+        }
+
+        let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * self.messages_per_second)
+            .min(Self::burst(self.messages_per_second));
+        state.last_refill = now;
+        if state.tokens < 1.0 {
+            let missing = 1.0 - state.tokens;
+            return Ok(Duration::from_secs_f64(missing / self.messages_per_second));
+        }
+
+        state.tokens -= 1.0;
+        state.sent_in_window += 1;
+        Ok(Duration::ZERO)
+    }
+}
+
+/// An [`EmailClient`] that respects the send limits of the relay behind it.
+///
+/// Sends are delayed to stay under `max_messages_per_second`. Once
+/// `max_messages_per_day` is reached, sends fail with
+/// [`SendEmailError::RateLimited`] until the quota resets.
+pub struct RateLimitedEmailClient {
+    inner: DynEmailClient,
+    limiter: RateLimiter,
+}
+
+impl RateLimitedEmailClient {
+    pub fn new(inner: DynEmailClient, limiter: RateLimiter) -> Self {
+        Self { inner, limiter }
+    }
+
+    /// Apply the limits of `settings`, counting the `n_sent` messages sent in
+    /// the last day, the first of them `window_age` ago.
+    pub fn from_settings(
+        inner: DynEmailClient,
+        settings: &EmailClientSettings,
+        n_sent: u32,
+        window_age: Duration,
+    ) -> Self {
+        let limiter = RateLimiter::new(
+            settings.max_messages_per_second,
+            settings.max_messages_per_day,
+            Arc::new(SystemClock),
+        )
+        .resume_window(n_sent, window_age);
+        Self::new(inner, limiter)
+    }
+}
+
+#[async_trait]
+impl EmailClient for RateLimitedEmailClient {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        message: &EmailMessage,
+    ) -> Result<(), SendEmailError> {
+        self.limiter
+            .acquire()
+            .await
+            .map_err(SendEmailError::RateLimited)?;
+
+        self.inner.send_email(recipient, message).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use axum::async_trait;
+    use claims::{assert_err, assert_ok};
+
+    use super::{Clock, RateLimiter};
+
+    /// A clock that only moves when told to, or when someone sleeps on it.
+    struct FakeClock {
+        start: Instant,
+        elapsed: Mutex<Duration>,
+    }
+
+    impl FakeClock {
+        fn new() -> Arc<Self> {
+            Arc::new(Self {
+                start: Instant::now(),
+                elapsed: Mutex::new(Duration::ZERO),
+            })
+        }
+
+        fn advance(&self, duration: Duration) {
+            *self.elapsed.lock().unwrap() += duration;
+        }
+
+        fn elapsed(&self) -> Duration {
+            *self.elapsed.lock().unwrap()
+        }
+    }
+
+    #[async_trait]
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.start + self.elapsed()
+        }
+
+        async fn sleep(&self, duration: Duration) {
+            self.advance(duration);
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_are_spread_out_to_match_the_rate() {
+        let clock = FakeClock::new();
+        let limiter = RateLimiter::new(2.0, 1000, clock.clone());
+
+        for _ in 0..6 {
+            assert_ok!(limiter.acquire().await);
+        }
+
+        // Two messages go out straight away, the other four wait half a second each.
+        assert_eq!(clock.elapsed(), Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn rates_below_one_message_per_second_are_respected() {
+        let clock = FakeClock::new();
+        let limiter = RateLimiter::new(0.1, 1000, clock.clone());
+
+        for _ in 0..3 {
+            assert_ok!(limiter.acquire().await);
+        }
+
+        assert_eq!(clock.elapsed(), Duration::from_secs(20));
+    }
+
+    #[tokio::test]
+    async fn the_daily_quota_pauses_sending_until_it_resets() {
+        let clock = FakeClock::new();
+        let limiter = RateLimiter::new(100.0, 3, clock.clone());
+        for _ in 0..3 {
+            assert_ok!(limiter.acquire().await);
+        }
+
+        clock.advance(Duration::from_secs(60 * 60));
+        let retry_after = assert_err!(limiter.acquire().await);
+        assert_eq!(retry_after, Duration::from_secs(23 * 60 * 60));
+
+        clock.advance(retry_after);
+        assert_ok!(limiter.acquire().await);
+    }
+
+    #[tokio::test]
+    async fn sends_made_before_a_restart_count_against_the_quota() {
+        let clock = FakeClock::new();
+        clock.advance(Duration::from_secs(24 * 60 * 60));
+        let limiter = RateLimiter::new(100.0, 3, clock.clone())
+            .resume_window(2, Duration::from_secs(23 * 60 * 60));
+
+        assert_ok!(limiter.acquire().await);
+        let retry_after = assert_err!(limiter.acquire().await);
+        assert_eq!(retry_after, Duration::from_secs(60 * 60));
+    }
+}
@@ -0,0 +1,96 @@
+use askama::Template;
+
+use crate::email_client::EmailMessage;
+
+/// The email sent to new subscribers to confirm their address.
+pub struct ConfirmationEmail<'a> {
+    pub list_name: &'a str,
+    pub confirmation_link: &'a str,
+}
+
+impl ConfirmationEmail<'_> {
+    pub fn render(&self) -> Result<EmailMessage, askama::Error> {
+        Ok(EmailMessage {
+            subject: "Welcome!".into(),
+            html_content: ConfirmationHtml { email: self }.render()?,
+            text_content: ConfirmationText { email: self }.render()?,
+            unsubscribe_url: None,
+        })
+    }
+}
+
+#[derive(Template)]
+#[template(path = "emails/confirmation.html")]
+struct ConfirmationHtml<'a> {
+    email: &'a ConfirmationEmail<'a>,
+}
+
+#[derive(Template)]
+#[template(path = "emails/confirmation.txt")]
+struct ConfirmationText<'a> {
+    email: &'a ConfirmationEmail<'a>,
+}
+
+/// The email sent to subscribers who ask for a copy of their data or for its
+/// erasure, so that only the owner of the address can do either.
+pub struct DataRequestEmail<'a> {
+    pub export_link: &'a str,
+    pub erase_link: &'a str,
+    pub valid_for_hours: i64,
+}
+
+impl DataRequestEmail<'_> {
+    pub fn render(&self) -> Result<EmailMessage, askama::Error> {
+        Ok(EmailMessage {
+            subject: "Your data".into(),
+            html_content: DataRequestHtml { email: self }.render()?,
+            text_content: DataRequestText { email: self }.render()?,
+            unsubscribe_url: None,
+        })
+    }
+}
+
+#[derive(Template)]
+#[template(path = "emails/data_request.html")]
+struct DataRequestHtml<'a> {
+    email: &'a DataRequestEmail<'a>,
+}
+
+#[derive(Template)]
+#[template(path = "emails/data_request.txt")]
+struct DataRequestText<'a> {
+    email: &'a DataRequestEmail<'a>,
+}
+
+/// A newsletter issue as delivered to a single subscriber.
+pub struct NewsletterEmail<'a> {
+    pub title: &'a str,
+    /// Inserted into the layout without escaping.
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+    pub unsubscribe_url: &'a str,
+    pub preferences_url: &'a str,
+}
+
+impl NewsletterEmail<'_> {
+    pub fn render(&self) -> Result<EmailMessage, askama::Error> {
+        Ok(EmailMessage {
+            subject: self.title.to_owned(),
+            html_content: NewsletterHtml { email: self }.render()?,
+            text_content: NewsletterText { email: self }.render()?,
+            unsubscribe_url: Some(self.unsubscribe_url.to_owned()),
+        })
+    }
+}
+
+#[derive(Template)]
+#[template(path = "emails/newsletter.html")]
+struct NewsletterHtml<'a> {
+    email: &'a NewsletterEmail<'a>,
+}
+
+#[derive(Template)]
+#[template(path = "emails/newsletter.txt")]
+struct NewsletterText<'a> {
+    email: &'a NewsletterEmail<'a>,
+}
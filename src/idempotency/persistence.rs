@@ -0,0 +1,163 @@
+use anyhow::Context;
+use axum::{
+    body::{boxed, Full},
+    response::Response,
+};
+use hyper::StatusCode;
+use sqlx::{postgres::PgHasArrayType, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::IdempotencyKey;
+
+#[derive(Debug, sqlx::Type)]
+#[sqlx(type_name = "header_pair")]
+struct HeaderPairRecord {
+    name: String,
+    value: Vec<u8>,
+}
+
+impl PgHasArrayType for HeaderPairRecord {
+    fn array_type_info() -> sqlx::postgres::PgTypeInfo {
+        sqlx::postgres::PgTypeInfo::with_name("_header_pair")
+    }
+}
+
+/// What a request handler should do after trying to claim an idempotency key.
+#[allow(clippy::large_enum_variant)]
+pub enum NextAction {
+    /// The key was claimed by this request. The handler does its work inside
+    /// the transaction and hands it back to [`save_response`].
+    StartProcessing(Transaction<'static, Postgres>),
+    /// The key has already been processed, so the saved response is returned
+    /// as-is.
+    ReturnSavedResponse(Response),
+}
+
+/// Claim `idempotency_key` for `user_id`.
+///
+/// If another request with the same key is in flight, the insert blocks until
+/// that request's transaction commits, after which its saved response is
+/// returned.
+#[tracing::instrument(name = "Try processing idempotent request", skip(pool))]
+pub async fn try_processing(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<NextAction, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let n_inserted_rows = sqlx::query!(
+        r#"
+        INSERT INTO idempotency (user_id, idempotency_key, created_at)
+        VALUES ($1, $2, now())
+        ON CONFLICT DO NOTHING
+        "#,
+        user_id,
+        idempotency_key.as_ref()
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to insert idempotency key")?
+    .rows_affected();
+
+    if n_inserted_rows > 0 {
+        Ok(NextAction::StartProcessing(transaction))
+    } else {
+        let saved_response = get_saved_response(pool, idempotency_key, user_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("We expected a saved response, we didn't find it"))?;
+        Ok(NextAction::ReturnSavedResponse(saved_response))
+    }
+}
+
+#[tracing::instrument(name = "Get saved response", skip(pool))]
+async fn get_saved_response(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<Option<Response>, anyhow::Error> {
+    let saved_response = sqlx::query!(
+        r#"
+        SELECT
+            response_status_code as "response_status_code!",
+            response_headers as "response_headers!: Vec<HeaderPairRecord>",
+            response_body as "response_body!"
+        FROM idempotency
+        WHERE
+            user_id = $1 AND
+            idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref()
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve saved response")?;
+
+    let Some(record) = saved_response else {
+        return Ok(None);
+    };
+
+    let status_code = StatusCode::from_u16(record.response_status_code.try_into()?)?;
+    let mut response = Response::builder().status(status_code);
+    for HeaderPairRecord { name, value } in record.response_headers {
+        response = response.header(name, value);
+    }
+
+    Ok(Some(
+        response.body(boxed(Full::from(record.response_body)))?,
+    ))
+}
+
+/// Store `response` against the claimed idempotency key and commit the
+/// transaction returned by [`try_processing`].
+#[tracing::instrument(name = "Save response", skip(transaction, response))]
+pub async fn save_response(
+    mut transaction: Transaction<'static, Postgres>,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    response: Response,
+) -> Result<Response, anyhow::Error> {
+    let (parts, body) = response.into_parts();
+    let body = hyper::body::to_bytes(body)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to read response body: {e}"))?;
+    let status_code = parts.status.as_u16() as i16;
+    let headers = parts
+        .headers
+        .iter()
+        .map(|(name, value)| HeaderPairRecord {
+            name: name.as_str().to_owned(),
+            value: value.as_bytes().to_owned(),
+        })
+        .collect::<Vec<_>>();
+
+    sqlx::query_unchecked!(
+        r#"
+        UPDATE idempotency
+        SET
+            response_status_code = $3,
+            response_headers = $4,
+            response_body = $5
+        WHERE
+            user_id = $1 AND
+            idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        status_code,
+        headers,
+        body.as_ref()
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to save response")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit idempotency transaction")?;
+
+    Ok(Response::from_parts(parts, boxed(Full::from(body))))
+}
@@ -0,0 +1,550 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Context;
+use chrono::Utc;
+use rand::Rng;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::{
+    configuration::{IssueDeliverySettings, Settings},
+    confirmation_email_queue::try_send_confirmation_email,
+    domain::{PreferencesToken, SubscriberEmail, UnsubscribeToken},
+    email_client::{DynEmailClient, EmailMessage, RateLimitedEmailClient, SendEmailError},
+    email_templates::NewsletterEmail,
+    personalization::{personalize_html, personalize_text, Recipient},
+    startup::get_connection_pool,
+    tracking::add_tracking,
+};
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+    /// Nothing can be sent for this long, e.g. because the daily send quota
+    /// has been used up.
+    Paused(Duration),
+}
+
+/// Send queued confirmation emails and deliver queued newsletter issues until
+/// the process is stopped.
+///
+/// The emails are sent within the limits of `email_client` settings, counting
+/// the ones sent in the last day before the worker started.
+pub async fn run_worker_until_stopped(
+    config: Settings,
+    email_client: DynEmailClient,
+) -> Result<(), anyhow::Error> {
+    let db_pool = get_connection_pool(&config.database);
+    let (n_sent, window_age) = count_recent_sends(&db_pool).await?;
+    let email_client: DynEmailClient = Arc::new(RateLimitedEmailClient::from_settings(
+        email_client,
+        &config.email_client,
+        n_sent,
+        window_age,
+    ));
+    worker_loop(db_pool, email_client, config).await
+}
+
+/// How many emails the worker sent in the last 24 hours, and how long ago the
+/// first of them was sent.
+pub async fn count_recent_sends(pool: &PgPool) -> Result<(u32, Duration), anyhow::Error> {
+    let sends = sqlx::query!(
+        r#"
+        SELECT count(*) AS "n_sent!", min(sent_at) AS first_sent_at
+        FROM (
+            SELECT sent_at FROM issue_delivery_queue
+            WHERE sent_at > now() - interval '1 day'
+            UNION ALL
+            SELECT sent_at FROM confirmation_email_queue
+            WHERE sent_at > now() - interval '1 day'
+        ) AS sends
+        "#
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to count the emails sent in the last day")?;
+    let window_age = sends
+        .first_sent_at
+        .and_then(|first_sent_at| (Utc::now() - first_sent_at).to_std().ok())
+        .unwrap_or_default();
+
+    Ok((sends.n_sent.try_into().unwrap_or(u32::MAX), window_age))
+}
+
+async fn worker_loop(
+    db_pool: PgPool,
+    email_client: DynEmailClient,
+    config: Settings,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_execute_next_task(&db_pool, &email_client, &config).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Ok(ExecutionOutcome::Paused(delay)) => {
+                tokio::time::sleep(delay).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+/// Send a single queued email that is due. Confirmation emails go first, since
+/// someone may be waiting for them, then newsletter issues.
+pub async fn try_execute_next_task(
+    pool: &PgPool,
+    email_client: &DynEmailClient,
+    config: &Settings,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    match try_send_confirmation_email(pool, email_client, config).await? {
+        ExecutionOutcome::EmptyQueue => try_execute_task(pool, email_client, config).await,
+        outcome => Ok(outcome),
+    }
+}
+
+/// Dequeue a single delivery task that is due and try to send its email.
+///
+/// The task row stays locked for the duration of the send, so several workers
+/// can drain the queue concurrently without sending the same email twice.
+/// Successful deliveries stay in the table as `sent` for reporting. Transient
+/// failures are rescheduled with exponential backoff until
+/// `issue_delivery.max_attempts` is reached, after which the task is
+/// dead-lettered, as is a task whose email can't be put together, e.g. because
+/// the template fails to render. Tasks for subscribers who are no longer confirmed for the
+/// issue's list are dropped without sending anything. When the email client
+/// reports that its send quota is used up, the task is put back without
+/// counting the attempt and the worker pauses. With `tracking.enabled`, links
+/// and a tracking pixel are added to the HTML body of every email. Placeholders
+/// in the content are filled in for each subscriber.
+#[tracing::instrument(
+    skip_all,
+    fields(
+        newsletter_issue_id=tracing::field::Empty,
+        subscriber_email=tracing::field::Empty
+    ),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &DynEmailClient,
+    config: &Settings,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some((transaction, task)) = dequeue_task(pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    Span::current()
+        .record("newsletter_issue_id", display(task.newsletter_issue_id))
+        .record("subscriber_email", display(&task.subscriber_email));
+
+    let (email, message) = match build_email(pool, &task, config).await {
+        Ok(Some(email)) => email,
+        Ok(None) => {
+            tracing::info!("Skipping a subscriber who is no longer confirmed");
+            delete_task(transaction, &task).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+        Err(failure) => {
+            record_failure(transaction, &task, failure, &config.issue_delivery).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+    let result = match email_client.send_email(&email, &message).await {
+        Err(SendEmailError::RateLimited(retry_after)) => {
+            tracing::warn!(
+                retry_in_seconds = retry_after.as_secs(),
+                "The send quota has been reached. Pausing deliveries."
+            );
+            postpone_task(transaction, &task, retry_after).await?;
+            return Ok(ExecutionOutcome::Paused(retry_after));
+        }
+        result => result.map_err(|e| DeliveryFailure {
+            is_transient: e.is_transient(),
+            message: e.to_string(),
+        }),
+    };
+
+    match result {
+        Ok(()) => mark_task_sent(transaction, &task).await?,
+        Err(failure) => record_failure(transaction, &task, failure, &config.issue_delivery).await?,
+    }
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// The email of a delivery task, or `None` if the subscriber is no longer
+/// confirmed for the issue's list. Failures are permanent: the issue, the
+/// subscriber or the template would be just as broken on the next attempt.
+async fn build_email(
+    pool: &PgPool,
+    task: &DeliveryTask,
+    config: &Settings,
+) -> Result<Option<(SubscriberEmail, EmailMessage)>, DeliveryFailure> {
+    let permanent = |e: anyhow::Error| DeliveryFailure {
+        is_transient: false,
+        message: format!("{e:#}"),
+    };
+    let issue = get_issue(pool, task.newsletter_issue_id)
+        .await
+        .map_err(permanent)?;
+    let Some(subscriber) = get_confirmed_subscriber(pool, &task.subscriber_email, issue.list_id)
+        .await
+        .map_err(permanent)?
+    else {
+        return Ok(None);
+    };
+    let email =
+        SubscriberEmail::parse(task.subscriber_email.clone()).map_err(|e| DeliveryFailure {
+            is_transient: false,
+            message: e,
+        })?;
+
+    let unsubscribe_token = UnsubscribeToken::generate(
+        subscriber.id,
+        issue.list_id,
+        &config.application.hmac_secret,
+    );
+    let unsubscribe_url = format!(
+        "{}/subscriptions/unsubscribe?token={}",
+        config.application.base_url,
+        unsubscribe_token.as_ref()
+    );
+    let preferences_url = format!(
+        "{}/subscriptions/preferences?token={}",
+        config.application.base_url,
+        PreferencesToken::generate(subscriber.id, &config.application.hmac_secret).as_ref()
+    );
+    let recipient = Recipient {
+        name: &subscriber.name,
+        email: email.as_ref(),
+        unsubscribe_url: &unsubscribe_url,
+        preferences_url: &preferences_url,
+    };
+    // Placeholders are filled in first so that links containing them are
+    // tracked with the subscriber's actual URL.
+    let html_content = personalize_html(&issue.html_content, &recipient);
+    let html_content = if config.tracking.enabled {
+        add_tracking(
+            &html_content,
+            task.newsletter_issue_id,
+            subscriber.id,
+            &config.application.base_url,
+            &config.application.hmac_secret,
+        )
+    } else {
+        html_content
+    };
+    let message = NewsletterEmail {
+        title: &issue.title,
+        html_content: &html_content,
+        text_content: &personalize_text(&issue.text_content, &recipient),
+        unsubscribe_url: &unsubscribe_url,
+        preferences_url: &preferences_url,
+    }
+    .render()
+    .context("Failed to render the newsletter email")
+    .map_err(permanent)?;
+
+    Ok(Some((email, message)))
+}
+
+struct DeliveryTask {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_attempts: i16,
+}
+
+struct DeliveryFailure {
+    is_transient: bool,
+    message: String,
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(
+    pool: &PgPool,
+) -> Result<Option<(PgTransaction, DeliveryTask)>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let task = sqlx::query_as!(
+        DeliveryTask,
+        r#"
+        SELECT newsletter_issue_id, subscriber_email, n_attempts
+        FROM issue_delivery_queue
+        WHERE
+            status = 'pending' AND
+            execute_after <= now()
+        ORDER BY execute_after
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to dequeue a delivery task")?;
+
+    Ok(task.map(|task| (transaction, task)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn mark_task_sent(
+    mut transaction: PgTransaction,
+    task: &DeliveryTask,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            status = 'sent',
+            n_attempts = n_attempts + 1,
+            sent_at = now(),
+            last_attempted_at = now()
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to mark a delivery task as sent")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the delivery task transaction")?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    mut transaction: PgTransaction,
+    task: &DeliveryTask,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete a delivery task")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the delivery task transaction")?;
+
+    Ok(())
+}
+
+/// Put a task back in the queue without counting it as a failed attempt.
+#[tracing::instrument(skip_all)]
+async fn postpone_task(
+    mut transaction: PgTransaction,
+    task: &DeliveryTask,
+    delay: Duration,
+) -> Result<(), anyhow::Error> {
+    let execute_after =
+        Utc::now() + chrono::Duration::from_std(delay).context("Postponement is too long")?;
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET execute_after = $3
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        execute_after
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to postpone a delivery task")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the delivery task transaction")?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn record_failure(
+    mut transaction: PgTransaction,
+    task: &DeliveryTask,
+    failure: DeliveryFailure,
+    settings: &IssueDeliverySettings,
+) -> Result<(), anyhow::Error> {
+    let n_attempts = task.n_attempts + 1;
+    let (status, execute_after) = if failure.is_transient && n_attempts < settings.max_attempts {
+        let delay = retry_delay(settings, n_attempts);
+        tracing::warn!(
+            error.message = %failure.message,
+            n_attempts,
+            retry_in_seconds = delay.as_secs(),
+            "Failed to deliver issue to a confirmed subscriber. Retrying later.",
+        );
+        (
+            "pending",
+            Utc::now() + chrono::Duration::from_std(delay).context("Retry delay is too long")?,
+        )
+    } else {
+        tracing::error!(
+            error.message = %failure.message,
+            n_attempts,
+            "Failed to deliver issue to a confirmed subscriber. Moving it to the dead letters.",
+        );
+        ("dead_letter", Utc::now())
+    };
+
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            status = $3,
+            n_attempts = $4,
+            execute_after = $5,
+            last_error = $6,
+            last_attempted_at = now()
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        status,
+        n_attempts,
+        execute_after,
+        failure.message
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to record a failed delivery attempt")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the delivery task transaction")?;
+
+    Ok(())
+}
+
+/// Exponential backoff with jitter: the delay after the `n_attempts`th attempt
+/// is somewhere between half and all of `base * 2^(n_attempts - 1)`.
+pub(crate) fn retry_delay(settings: &IssueDeliverySettings, n_attempts: i16) -> Duration {
+    let exponent = n_attempts.clamp(1, 16) as u32 - 1;
+    let max_delay = Duration::from_secs(settings.retry_base_delay_seconds) * 2u32.pow(exponent);
+    let jitter = rand::thread_rng().gen_range(0.0..=0.5);
+
+    max_delay.mul_f64(1.0 - jitter)
+}
+
+struct ConfirmedSubscriber {
+    id: Uuid,
+    name: String,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_confirmed_subscriber(
+    pool: &PgPool,
+    email: &str,
+    list_id: Uuid,
+) -> Result<Option<ConfirmedSubscriber>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        ConfirmedSubscriber,
+        r#"
+        SELECT id, name
+        FROM subscriptions
+        JOIN list_subscriptions ON list_subscriptions.subscriber_id = subscriptions.id
+        WHERE
+            email = $1 AND
+            subscriptions.status = 'confirmed' AND
+            list_subscriptions.list_id = $2 AND
+            list_subscriptions.status = 'confirmed'
+        "#,
+        email,
+        list_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the subscriber")?;
+
+    Ok(subscriber)
+}
+
+struct NewsletterIssue {
+    list_id: Uuid,
+    title: String,
+    html_content: String,
+    text_content: String,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT list_id, title, html_content, text_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to retrieve the newsletter issue")?;
+
+    Ok(issue)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::retry_delay;
+    use crate::configuration::IssueDeliverySettings;
+
+    fn settings() -> IssueDeliverySettings {
+        IssueDeliverySettings {
+            max_attempts: 5,
+            retry_base_delay_seconds: 10,
+        }
+    }
+
+    #[test]
+    fn the_first_retry_waits_at_most_the_base_delay() {
+        for _ in 0..100 {
+            let delay = retry_delay(&settings(), 1);
+            assert!(delay >= Duration::from_secs(5));
+            assert!(delay <= Duration::from_secs(10));
+        }
+    }
+
+    #[test]
+    fn the_delay_doubles_with_every_attempt() {
+        for _ in 0..100 {
+            let delay = retry_delay(&settings(), 4);
+            assert!(delay >= Duration::from_secs(40));
+            assert!(delay <= Duration::from_secs(80));
+        }
+    }
+
+    #[test]
+    fn the_delay_stops_growing_after_many_attempts() {
+        let delay = retry_delay(&settings(), i16::MAX);
+        assert!(delay <= Duration::from_secs(10 * 2u64.pow(15)));
+    }
+}
@@ -1,10 +1,21 @@
 pub mod app_error;
 pub mod app_state;
 pub mod authentication;
+pub mod challenge;
 pub mod configuration;
+pub mod confirmation_email_queue;
 pub mod domain;
 pub mod email_client;
+pub mod email_templates;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod newsletter_scheduler;
+pub mod personalization;
 pub mod routes;
+pub mod segments;
 pub mod session_state;
+pub mod spreadsheet;
 pub mod startup;
+pub mod subscriber_import;
 pub mod telemetry;
+pub mod tracking;
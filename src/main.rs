@@ -1,31 +1,190 @@
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
 
 use async_fred_session::RedisSessionStore;
 use fred::{pool::RedisPool, prelude::*};
 use lettre::{transport::smtp::authentication::Credentials, AsyncSmtpTransport, Tokio1Executor};
 
+use anyhow::Context;
 use secrecy::ExposeSecret;
+use tokio::task::JoinError;
 use zero2prod::{
     configuration::{get_configuration, Settings},
     domain::SubscriberEmail,
-    email_client::SmtpEmailClient,
-    startup::Application,
+    email_client::{DynEmailClient, SmtpEmailClient},
+    issue_delivery_worker::run_worker_until_stopped,
+    newsletter_scheduler::run_scheduler_until_stopped,
+    routes::find_active_list,
+    startup::{get_connection_pool, Application},
+    subscriber_import::{
+        get_import, get_rejected_rows, import_subscribers, write_rejection_report, ImportStatus,
+    },
     telemetry,
 };
 
+const IMPORT_USAGE: &str = "\
+Usage: zero2prod import-subscribers [OPTIONS] <FILE>
+
+Import the subscribers of a CSV file with `email` and `name` columns.
+
+Options:
+  --list <LIST>                The id or slug of the list [default: the default list]
+  --status <STATUS>            `pending_confirmation` queues confirmation emails for the
+                               background worker, `confirmed` doesn't
+                               [default: pending_confirmation]
+  --consent-source <SOURCE>    Where confirmed subscribers agreed to receive the list
+  --report <PATH>              Where to write the rejected rows [default: rejected-rows.csv]";
+
 #[tokio::main]
 async fn main() {
     let subscriber = telemetry::get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
     telemetry::init_subscriber(subscriber);
 
     let config = get_configuration().expect("Failed to read configuration");
-    let email_client = setup_email_client(&config);
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("import-subscribers") {
+        let args = match ImportArgs::parse(&args[1..]) {
+            Ok(args) => args,
+            Err(e) => {
+                eprintln!("{e}\n\n{IMPORT_USAGE}");
+                std::process::exit(2);
+            }
+        };
+        if let Err(e) = import_subscribers_from_file(&config, args).await {
+            eprintln!("Error: {e:?}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Only the emails sent by the worker, i.e. newsletter issues and the
+    // confirmation emails of imported subscribers, are rate limited. The
+    // emails sent while a request waits for them, e.g. the confirmation link
+    // of someone subscribing on the website, go out immediately so that they
+    // don't queue up behind newsletters or get cut off by the quota.
+    let email_client: DynEmailClient = Arc::new(setup_email_client(&config));
+
     let session_store = setup_redis_session_store(&config).await;
 
-    let application = Application::build(config, Arc::new(email_client), session_store)
+    let application = Application::build(config.clone(), email_client.clone(), session_store)
         .await
         .unwrap();
-    application.run_until_stopped().await.unwrap();
+    let application_task = tokio::spawn(application.run_until_stopped());
+    let scheduler_task = tokio::spawn(run_scheduler_until_stopped(config.clone()));
+    let worker_task = tokio::spawn(run_worker_until_stopped(config, email_client));
+
+    tokio::select! {
+        o = application_task => report_exit("API", o),
+        o = worker_task => report_exit("Background worker", o),
+        o = scheduler_task => report_exit("Newsletter scheduler", o),
+    };
+}
+
+fn report_exit(task_name: &str, outcome: Result<Result<(), anyhow::Error>, JoinError>) {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name)
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            )
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} task failed to complete",
+                task_name
+            )
+        }
+    }
+}
+
+struct ImportArgs {
+    file: PathBuf,
+    list: Option<String>,
+    status: ImportStatus,
+    report: PathBuf,
+}
+
+impl ImportArgs {
+    fn parse(args: &[String]) -> Result<ImportArgs, String> {
+        let mut file = None;
+        let mut list = None;
+        let mut status = "pending_confirmation".to_owned();
+        let mut consent_source = None;
+        let mut report = PathBuf::from("rejected-rows.csv");
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| format!("{arg} needs a value"))
+            };
+            match arg.as_str() {
+                "--list" => list = Some(value()?),
+                "--status" => status = value()?,
+                "--consent-source" => consent_source = Some(value()?),
+                "--report" => report = value()?.into(),
+                _ if arg.starts_with("--") || file.is_some() => {
+                    return Err(format!("Unexpected argument {arg}"))
+                }
+                _ => file = Some(PathBuf::from(arg)),
+            }
+        }
+
+        Ok(ImportArgs {
+            file: file.ok_or("Missing the file to import")?,
+            list,
+            status: ImportStatus::parse(&status, consent_source.as_deref())?,
+            report,
+        })
+    }
+}
+
+async fn import_subscribers_from_file(
+    config: &Settings,
+    args: ImportArgs,
+) -> Result<(), anyhow::Error> {
+    let pool = get_connection_pool(&config.database);
+    let list = find_active_list(&pool, args.list.as_deref())
+        .await?
+        .context("There is no such mailing list")?;
+    let file = tokio::fs::File::open(&args.file)
+        .await
+        .with_context(|| format!("Failed to open {}", args.file.display()))?;
+    let file_name = args.file.file_name().unwrap_or_default().to_string_lossy();
+
+    let import_id = import_subscribers(
+        &pool,
+        &list,
+        &args.status,
+        &file_name,
+        file,
+        &config.application.hmac_secret,
+    )
+    .await?;
+
+    let import = get_import(&pool, import_id)
+        .await?
+        .context("The import has disappeared")?;
+    println!(
+        "Imported {} subscribers into {}, skipped {} duplicates and rejected {} rows.",
+        import.n_imported, list.name, import.n_duplicates, import.n_rejected
+    );
+    if import.n_rejected > 0 {
+        let rejected = get_rejected_rows(&pool, import_id, None).await?;
+        let report = std::fs::File::create(&args.report)
+            .with_context(|| format!("Failed to create {}", args.report.display()))?;
+        write_rejection_report(&rejected, report)?;
+        println!("The rejected rows are listed in {}.", args.report.display());
+    }
+
+    Ok(())
 }
 
 fn setup_email_client(config: &Settings) -> SmtpEmailClient {
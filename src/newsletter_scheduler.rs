@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use sqlx::PgPool;
+use tracing::{field::display, Span};
+
+use crate::{configuration::Settings, routes::publish_issue, startup::get_connection_pool};
+
+/// The outcome of [`try_publish_due_issue`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum PublishOutcome {
+    Published,
+    NothingDue,
+}
+
+/// Publish scheduled issues as they become due until the process is stopped.
+pub async fn run_scheduler_until_stopped(config: Settings) -> Result<(), anyhow::Error> {
+    let db_pool = get_connection_pool(&config.database);
+    scheduler_loop(db_pool).await
+}
+
+async fn scheduler_loop(db_pool: PgPool) -> Result<(), anyhow::Error> {
+    loop {
+        match try_publish_due_issue(&db_pool).await {
+            Ok(PublishOutcome::NothingDue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(PublishOutcome::Published) => {}
+        }
+    }
+}
+
+/// Publish a single scheduled issue whose `send_at` time has passed, queueing
+/// it for delivery to every confirmed subscriber.
+///
+/// The issue row stays locked until it has been marked as sent, so replicas
+/// running the scheduler against the same database never publish it twice.
+#[tracing::instrument(skip_all, fields(newsletter_issue_id=tracing::field::Empty), err)]
+pub async fn try_publish_due_issue(pool: &PgPool) -> Result<PublishOutcome, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let issue = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id
+        FROM newsletter_issues
+        WHERE
+            status = 'scheduled' AND
+            send_at <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look for due newsletter issues")?;
+    let Some(issue) = issue else {
+        return Ok(PublishOutcome::NothingDue);
+    };
+    Span::current().record("newsletter_issue_id", display(issue.newsletter_issue_id));
+
+    publish_issue(pool, &mut transaction, issue.newsletter_issue_id).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the scheduled issue transaction")?;
+    tracing::info!("Published a scheduled newsletter issue");
+
+    Ok(PublishOutcome::Published)
+}
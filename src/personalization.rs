@@ -0,0 +1,136 @@
+use std::sync::OnceLock;
+
+use askama::{Html, MarkupDisplay};
+use regex::{Captures, Regex};
+
+/// The placeholders that can be used in the content of a newsletter issue.
+pub const PLACEHOLDERS: [&str; 4] = ["name", "email", "unsubscribe_url", "preferences_url"];
+
+/// The subscriber an issue is being rendered for.
+pub struct Recipient<'a> {
+    pub name: &'a str,
+    pub email: &'a str,
+    pub unsubscribe_url: &'a str,
+    pub preferences_url: &'a str,
+}
+
+impl Recipient<'_> {
+    fn value(&self, placeholder: &str) -> Option<&str> {
+        match placeholder {
+            "name" => Some(self.name),
+            "email" => Some(self.email),
+            "unsubscribe_url" => Some(self.unsubscribe_url),
+            "preferences_url" => Some(self.preferences_url),
+            _ => None,
+        }
+    }
+}
+
+fn placeholder_pattern() -> &'static Regex {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    PLACEHOLDER.get_or_init(|| {
+        Regex::new(r"\{\{\s*([^{}]*?)\s*\}\}").expect("The placeholder pattern is valid")
+    })
+}
+
+/// Check that `content` only uses known placeholders, so that it can be
+/// rendered for every recipient.
+pub fn validate_placeholders(content: &str) -> Result<(), String> {
+    let unknown: Vec<_> = placeholder_pattern()
+        .captures_iter(content)
+        .map(|caps| caps.get(1).unwrap().as_str())
+        .filter(|name| !PLACEHOLDERS.contains(name))
+        .collect();
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    Err(format!(
+        "Unknown placeholder(s): {}. The available placeholders are {}.",
+        list_placeholders(&unknown),
+        list_placeholders(&PLACEHOLDERS),
+    ))
+}
+
+fn list_placeholders(names: &[&str]) -> String {
+    names
+        .iter()
+        .map(|name| format!("{{{{ {name} }}}}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Fill in the placeholders of an HTML body, escaping the values.
+pub fn personalize_html(content: &str, recipient: &Recipient) -> String {
+    personalize(content, recipient, |value| {
+        MarkupDisplay::new_unsafe(value, Html).to_string()
+    })
+}
+
+/// Fill in the placeholders of a plain text body.
+pub fn personalize_text(content: &str, recipient: &Recipient) -> String {
+    personalize(content, recipient, str::to_owned)
+}
+
+/// Unknown placeholders are left as they are. They are rejected when an issue
+/// is published, see [`validate_placeholders`].
+fn personalize(content: &str, recipient: &Recipient, escape: impl Fn(&str) -> String) -> String {
+    placeholder_pattern()
+        .replace_all(content, |caps: &Captures| {
+            match recipient.value(caps.get(1).unwrap().as_str()) {
+                Some(value) => escape(value),
+                None => caps[0].to_owned(),
+            }
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+
+    use super::{personalize_html, personalize_text, validate_placeholders, Recipient};
+
+    const RECIPIENT: Recipient = Recipient {
+        name: "Ursula <Le Guin>",
+        email: "ursula@example.com",
+        unsubscribe_url: "https://example.com/unsubscribe?token=abc&x=1",
+        preferences_url: "https://example.com/preferences?token=abc",
+    };
+
+    #[test]
+    fn placeholders_are_filled_in() {
+        assert_eq!(
+            personalize_text(
+                "Hi {{ name }} ({{email}}), leave at {{  unsubscribe_url }}",
+                &RECIPIENT
+            ),
+            "Hi Ursula <Le Guin> (ursula@example.com), leave at https://example.com/unsubscribe?token=abc&x=1"
+        );
+    }
+
+    #[test]
+    fn values_are_escaped_in_html() {
+        assert_eq!(
+            personalize_html(
+                r#"<p>Hi {{ name }}</p><a href="{{ unsubscribe_url }}">Leave</a>"#,
+                &RECIPIENT
+            ),
+            r#"<p>Hi Ursula &lt;Le Guin&gt;</p><a href="https://example.com/unsubscribe?token=abc&amp;x=1">Leave</a>"#
+        );
+    }
+
+    #[test]
+    fn known_placeholders_are_accepted() {
+        assert_ok!(validate_placeholders(
+            "{{ name }} {{email}} {{ unsubscribe_url }} {{preferences_url}} { name } {name}"
+        ));
+    }
+
+    #[test]
+    fn unknown_placeholders_are_rejected() {
+        for content in ["Hi {{ first_name }}", "{{ }}", "{{ name | upper }}"] {
+            let error = assert_err!(validate_placeholders(content));
+            assert!(error.starts_with("Unknown placeholder"), "{error}");
+        }
+    }
+}
@@ -1,21 +1,30 @@
 use askama::Template;
 use axum::{extract::State, response::Html, Extension};
+use axum_flash::IncomingFlashes;
 
 use crate::{app_error::AppError, app_state::AppState, authentication::UserId};
 
-use super::get_username;
+use super::{deliveries::get_dead_letters, get_username, DeadLetter};
 
 #[derive(Template)]
 #[template(path = "admin_dashboard.html")]
 struct AdminDashboardTemplate {
+    flashes: IncomingFlashes,
     username: String,
+    dead_letters: Vec<DeadLetter>,
 }
 
 pub async fn admin_dashboard(
+    flashes: IncomingFlashes,
     State(state): State<AppState>,
     Extension(user_id): Extension<UserId>,
-) -> Result<Html<String>, AppError> {
+) -> Result<(IncomingFlashes, Html<String>), AppError> {
     let username = get_username(*user_id, &state.db_pool).await?;
-    let template = AdminDashboardTemplate { username };
-    Ok(Html(template.render().unwrap()))
+    let dead_letters = get_dead_letters(&state.db_pool).await?;
+    let template = AdminDashboardTemplate {
+        flashes: flashes.clone(),
+        username,
+        dead_letters,
+    };
+    Ok((flashes, Html(template.render().unwrap())))
 }
@@ -0,0 +1,103 @@
+use anyhow::Context;
+use axum::{extract::State, response::Redirect, Form};
+use axum_flash::Flash;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{app_error::AppError, app_state::AppState};
+
+/// A delivery that failed too many times and is no longer retried.
+pub struct DeadLetter {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub subscriber_email: String,
+    pub n_attempts: i16,
+    pub last_error: Option<String>,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(name = "Get dead-lettered deliveries", skip(pool))]
+pub async fn get_dead_letters(pool: &PgPool) -> Result<Vec<DeadLetter>, anyhow::Error> {
+    let dead_letters = sqlx::query_as!(
+        DeadLetter,
+        r#"
+        SELECT
+            q.newsletter_issue_id,
+            i.title,
+            q.subscriber_email,
+            q.n_attempts,
+            q.last_error,
+            q.last_attempted_at as last_attempt_at
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        WHERE q.status = 'dead_letter'
+        ORDER BY q.last_attempted_at DESC NULLS LAST
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve dead-lettered deliveries")?;
+
+    Ok(dead_letters)
+}
+
+#[derive(Deserialize)]
+pub struct RequeueFormData {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+}
+
+pub async fn requeue_delivery(
+    flash: Flash,
+    State(state): State<AppState>,
+    Form(form): Form<RequeueFormData>,
+) -> Result<(Flash, Redirect), AppError> {
+    let requeued = requeue_dead_letter(
+        &state.db_pool,
+        form.newsletter_issue_id,
+        &form.subscriber_email,
+    )
+    .await?;
+
+    let flash = if requeued {
+        flash.info(format!(
+            "The delivery to {} has been re-queued.",
+            form.subscriber_email
+        ))
+    } else {
+        flash.error("The delivery is no longer dead-lettered.")
+    };
+
+    Ok((flash, Redirect::to("/admin/dashboard")))
+}
+
+#[tracing::instrument(name = "Re-queue a dead-lettered delivery", skip(pool))]
+async fn requeue_dead_letter(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    subscriber_email: &str,
+) -> Result<bool, anyhow::Error> {
+    let n_updated_rows = sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            status = 'pending',
+            n_attempts = 0,
+            execute_after = now()
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2 AND
+            status = 'dead_letter'
+        "#,
+        newsletter_issue_id,
+        subscriber_email
+    )
+    .execute(pool)
+    .await
+    .context("Failed to re-queue a dead-lettered delivery")?
+    .rows_affected();
+
+    Ok(n_updated_rows > 0)
+}
@@ -0,0 +1,105 @@
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    response::{Html, IntoResponse, Redirect, Response},
+    Form,
+};
+use axum_flash::{Flash, IncomingFlashes};
+use hyper::StatusCode;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    app_error::AppError,
+    app_state::AppState,
+    routes::{
+        archive_list, count_recipients, get_list, list_segments, rename_list, MailingList,
+        SegmentSummary,
+    },
+};
+
+use super::validate_list_name;
+
+#[derive(Template)]
+#[template(path = "admin_list.html")]
+struct MailingListTemplate {
+    flashes: IncomingFlashes,
+    list: MailingList,
+    /// The segments of the list along with how many subscribers are in them.
+    segments: Vec<SegmentSummary>,
+}
+
+/// Show a single list with its segments and forms to rename or archive it.
+pub async fn mailing_list_form(
+    flashes: IncomingFlashes,
+    State(state): State<AppState>,
+    Path(list_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let Some(list) = get_list(&state.db_pool, list_id).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let mut segments = Vec::new();
+    for segment in list_segments(&state.db_pool, Some(list_id)).await? {
+        let recipients =
+            count_recipients(&state.db_pool, list_id, Some(&segment.definition)).await?;
+        segments.push(SegmentSummary {
+            segment,
+            recipients,
+        });
+    }
+    let template = MailingListTemplate {
+        flashes: flashes.clone(),
+        list,
+        segments,
+    };
+
+    Ok((flashes, Html(template.render()?)).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct FormData {
+    name: String,
+}
+
+/// Rename a list. Its slug stays the same, so existing signup forms keep
+/// working.
+#[tracing::instrument(name = "Rename a mailing list", skip(flash, state, form))]
+pub async fn update_mailing_list(
+    flash: Flash,
+    State(state): State<AppState>,
+    Path(list_id): Path<Uuid>,
+    Form(form): Form<FormData>,
+) -> Result<Response, AppError> {
+    let list_url = format!("/admin/lists/{list_id}");
+    let name = match validate_list_name(&form.name) {
+        Ok(name) => name,
+        Err(e) => return Ok((flash.error(e), Redirect::to(&list_url)).into_response()),
+    };
+
+    if !rename_list(&state.db_pool, list_id, name).await? {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+
+    Ok((
+        flash.info("The list has been renamed."),
+        Redirect::to(&list_url),
+    )
+        .into_response())
+}
+
+/// Archive a list, so that nobody can subscribe to it or be sent new issues
+/// from it anymore.
+#[tracing::instrument(name = "Archive a mailing list", skip(flash, state))]
+pub async fn archive_mailing_list(
+    flash: Flash,
+    State(state): State<AppState>,
+    Path(list_id): Path<Uuid>,
+) -> Result<(Flash, Redirect), AppError> {
+    let flash = if archive_list(&state.db_pool, list_id).await? {
+        flash.info("The list has been archived.")
+    } else {
+        flash.error("There is no such list, or it is already archived.")
+    };
+
+    Ok((flash, Redirect::to("/admin/lists")))
+}
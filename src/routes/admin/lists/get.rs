@@ -0,0 +1,57 @@
+use askama::Template;
+use axum::{extract::State, response::Html};
+use axum_flash::IncomingFlashes;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::{app_error::AppError, app_state::AppState};
+
+struct ListSummary {
+    list_id: Uuid,
+    slug: String,
+    name: String,
+    archived_at: Option<DateTime<Utc>>,
+    confirmed_subscribers: i64,
+}
+
+#[derive(Template)]
+#[template(path = "admin_lists.html")]
+struct MailingListsTemplate {
+    flashes: IncomingFlashes,
+    lists: Vec<ListSummary>,
+}
+
+/// List the mailing lists next to the form to create a new one.
+pub async fn mailing_lists_form(
+    flashes: IncomingFlashes,
+    State(state): State<AppState>,
+) -> Result<(IncomingFlashes, Html<String>), AppError> {
+    let lists = sqlx::query_as!(
+        ListSummary,
+        r#"
+        SELECT
+            lists.list_id,
+            slug,
+            lists.name,
+            archived_at,
+            count(subscriptions.id) AS "confirmed_subscribers!"
+        FROM lists
+        LEFT JOIN list_subscriptions
+            ON list_subscriptions.list_id = lists.list_id
+            AND list_subscriptions.status = 'confirmed'
+        LEFT JOIN subscriptions
+            ON subscriptions.id = list_subscriptions.subscriber_id
+            AND subscriptions.status = 'confirmed'
+        GROUP BY lists.list_id
+        ORDER BY lists.created_at, slug
+        "#,
+    )
+    .fetch_all(&state.db_pool)
+    .await?;
+    let template = MailingListsTemplate {
+        flashes: flashes.clone(),
+        lists,
+    };
+
+    Ok((flashes, Html(template.render()?)))
+}
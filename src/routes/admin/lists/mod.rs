@@ -0,0 +1,24 @@
+mod edit;
+mod get;
+mod post;
+mod segments;
+
+pub use edit::{archive_mailing_list, mailing_list_form, update_mailing_list};
+pub use get::mailing_lists_form;
+pub use post::create_mailing_list;
+pub use segments::create_list_segment;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Check a list name entered in the admin forms, returning a message suitable
+/// for a flash on failure.
+fn validate_list_name(name: &str) -> Result<&str, &'static str> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("The list needs a name.");
+    }
+    if name.graphemes(true).count() > 256 {
+        return Err("The list name must be at most 256 characters long.");
+    }
+    Ok(name)
+}
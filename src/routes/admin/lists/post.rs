@@ -0,0 +1,40 @@
+use axum::{extract::State, response::Redirect, Form};
+use axum_flash::Flash;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{app_error::AppError, app_state::AppState, domain::ListSlug, routes::insert_list};
+
+use super::validate_list_name;
+
+#[derive(Deserialize)]
+pub struct FormData {
+    name: String,
+    slug: String,
+}
+
+#[tracing::instrument(name = "Create a mailing list", skip(flash, state, form))]
+pub async fn create_mailing_list(
+    flash: Flash,
+    State(state): State<AppState>,
+    Form(form): Form<FormData>,
+) -> Result<(Flash, Redirect), AppError> {
+    let redirect = Redirect::to("/admin/lists");
+    let name = match validate_list_name(&form.name) {
+        Ok(name) => name,
+        Err(e) => return Ok((flash.error(e), redirect)),
+    };
+    let slug = match ListSlug::parse(form.slug.trim().to_owned()) {
+        Ok(slug) => slug,
+        Err(e) => return Ok((flash.error(e), redirect)),
+    };
+
+    let created = insert_list(&state.db_pool, Uuid::new_v4(), slug.as_ref(), name).await?;
+    let flash = if created {
+        flash.info(format!("The list {name} has been created."))
+    } else {
+        flash.error(format!("There is already a list called {}.", slug.as_ref()))
+    };
+
+    Ok((flash, redirect))
+}
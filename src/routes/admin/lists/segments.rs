@@ -0,0 +1,66 @@
+use axum::{
+    extract::{Path, State},
+    response::Redirect,
+    Form,
+};
+use axum_flash::Flash;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    app_error::AppError,
+    app_state::AppState,
+    routes::{get_list, insert_segment, validate_segment_name},
+    segments::SegmentDefinition,
+};
+
+#[derive(Deserialize)]
+pub struct FormData {
+    name: String,
+    /// The definition of the segment as JSON.
+    definition: String,
+}
+
+/// Create a segment of a list from its JSON definition.
+#[tracing::instrument(
+    name = "Create a segment from the admin form",
+    skip(flash, state, form)
+)]
+pub async fn create_list_segment(
+    flash: Flash,
+    State(state): State<AppState>,
+    Path(list_id): Path<Uuid>,
+    Form(form): Form<FormData>,
+) -> Result<(Flash, Redirect), AppError> {
+    let redirect = Redirect::to(&format!("/admin/lists/{list_id}"));
+    let name = match validate_segment_name(&form.name) {
+        Ok(name) => name,
+        Err(e) => return Ok((flash.error(format!("{e}.")), redirect)),
+    };
+    let definition = match serde_json::from_str::<SegmentDefinition>(&form.definition) {
+        Ok(definition) => definition,
+        Err(e) => {
+            return Ok((
+                flash.error(format!("The segment definition is not valid: {e}.")),
+                redirect,
+            ))
+        }
+    };
+    if let Err(e) = definition.validate() {
+        return Ok((flash.error(format!("{e}.")), redirect));
+    }
+    match get_list(&state.db_pool, list_id).await? {
+        Some(list) if !list.is_archived() => {}
+        _ => return Ok((flash.error("The list is archived."), redirect)),
+    }
+
+    let created =
+        insert_segment(&state.db_pool, Uuid::new_v4(), list_id, name, &definition).await?;
+    let flash = if created {
+        flash.info(format!("The segment {name} has been created."))
+    } else {
+        flash.error(format!("There is already a segment called {name}."))
+    };
+
+    Ok((flash, redirect))
+}
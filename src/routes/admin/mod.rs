@@ -1,10 +1,18 @@
 mod dashboard;
+mod deliveries;
+mod lists;
 mod logout;
+mod newsletters;
 mod password;
+mod subscribers;
 
 pub use dashboard::admin_dashboard;
+pub use deliveries::{requeue_delivery, DeadLetter};
+pub use lists::*;
 pub use logout::log_out;
+pub use newsletters::*;
 pub use password::*;
+pub use subscribers::*;
 
 use anyhow::Context;
 use sqlx::PgPool;
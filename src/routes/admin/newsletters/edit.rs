@@ -0,0 +1,191 @@
+use anyhow::Context;
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    response::{Html, IntoResponse, Redirect, Response},
+    Form,
+};
+use axum_flash::{Flash, IncomingFlashes};
+use hyper::StatusCode;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    app_error::AppError,
+    app_state::AppState,
+    routes::{
+        count_recipients, find_active_list, find_segment, get_delivery_report,
+        get_newsletter_issue, get_segment, list_mailing_lists, list_segments, publish_issue,
+        schedule_issue, update_draft_issue, DeliveryReport, MailingList, NewsletterIssue, Segment,
+    },
+};
+
+use super::{
+    scheduled_message, text_content_or_default, validate_issue, FormAction, IssueAction,
+    NO_SUCH_LIST, NO_SUCH_SEGMENT, PUBLISHED_MESSAGE,
+};
+
+#[derive(Template)]
+#[template(path = "admin_newsletter_issue.html")]
+struct NewsletterIssueTemplate {
+    flashes: IncomingFlashes,
+    issue: NewsletterIssue,
+    /// The lists a draft can be moved to.
+    lists: Vec<MailingList>,
+    /// The segments of those lists.
+    segments: Vec<Segment>,
+    /// How many subscribers the issue would go to if it was sent now. Only set
+    /// until the issue has been sent.
+    recipients: Option<i64>,
+    /// Only set once the issue has been sent.
+    report: Option<DeliveryReport>,
+}
+
+/// Show a single issue. Drafts can be edited, everything else is read-only.
+/// Sent issues come with a report on how their delivery is going, the others
+/// with how many subscribers they would currently go to.
+pub async fn newsletter_issue_form(
+    flashes: IncomingFlashes,
+    State(state): State<AppState>,
+    Path(issue_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let Some(issue) = get_newsletter_issue(&state.db_pool, issue_id).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let (report, recipients) = if issue.status == "sent" {
+        (get_delivery_report(&state.db_pool, issue_id).await?, None)
+    } else {
+        let segment = match issue.segment_id {
+            Some(segment_id) => get_segment(&state.db_pool, segment_id).await?,
+            None => None,
+        };
+        let recipients = count_recipients(
+            &state.db_pool,
+            issue.list_id,
+            segment.as_ref().map(|s| &s.definition),
+        )
+        .await?;
+        (None, Some(recipients))
+    };
+    let lists: Vec<MailingList> = list_mailing_lists(&state.db_pool)
+        .await?
+        .into_iter()
+        .filter(|list| !list.is_archived() || list.list_id == issue.list_id)
+        .collect();
+    let segments = list_segments(&state.db_pool, None)
+        .await?
+        .into_iter()
+        .filter(|segment| lists.iter().any(|list| list.list_id == segment.list_id))
+        .collect();
+    let template = NewsletterIssueTemplate {
+        flashes: flashes.clone(),
+        issue,
+        lists,
+        segments,
+        recipients,
+        report,
+    };
+
+    Ok((flashes, Html(template.render()?)).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct FormData {
+    title: String,
+    html_content: String,
+    /// Generated from `html_content` when left empty.
+    text_content: String,
+    /// The id of the list to send the issue to.
+    list: Option<String>,
+    /// The id of a segment of the list. Empty to send the issue to the whole
+    /// list.
+    segment: Option<String>,
+    action: FormAction,
+    /// Only used when scheduling the issue.
+    #[serde(default)]
+    send_at: String,
+}
+
+/// Save changes to a draft, and publish or schedule it if asked to.
+#[tracing::instrument(name = "Update a draft newsletter issue", skip(flash, state, form))]
+pub async fn update_newsletter_issue(
+    flash: Flash,
+    State(state): State<AppState>,
+    Path(issue_id): Path<Uuid>,
+    Form(form): Form<FormData>,
+) -> Result<Response, AppError> {
+    let issue_url = format!("/admin/newsletters/{issue_id}");
+    if let Err(e) = validate_issue(&form.title, &form.html_content, &form.text_content) {
+        return Ok((flash.error(e), Redirect::to(&issue_url)).into_response());
+    }
+    let action = match form.action.validate(&form.send_at) {
+        Ok(action) => action,
+        Err(e) => return Ok((flash.error(e), Redirect::to(&issue_url)).into_response()),
+    };
+    let Some(list) = find_active_list(&state.db_pool, form.list.as_deref()).await? else {
+        return Ok((flash.error(NO_SUCH_LIST), Redirect::to(&issue_url)).into_response());
+    };
+    let segment_id = match form.segment.as_deref().filter(|s| !s.is_empty()) {
+        Some(segment) => match find_segment(&state.db_pool, list.list_id, segment).await? {
+            Some(segment) => Some(segment.segment_id),
+            None => {
+                return Ok((flash.error(NO_SUCH_SEGMENT), Redirect::to(&issue_url)).into_response())
+            }
+        },
+        None => None,
+    };
+
+    let mut transaction = state
+        .db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let text_content = text_content_or_default(&form.html_content, form.text_content);
+    let updated = update_draft_issue(
+        &mut transaction,
+        issue_id,
+        list.list_id,
+        segment_id,
+        &form.title,
+        &form.html_content,
+        &text_content,
+    )
+    .await
+    .context("Failed to update the draft")?;
+    if !updated {
+        let flash = flash.error("Only drafts can be edited.");
+        return Ok((flash, Redirect::to(&issue_url)).into_response());
+    }
+
+    let response = match action {
+        IssueAction::SaveDraft => (
+            flash.info("The draft has been saved."),
+            Redirect::to(&issue_url),
+        )
+            .into_response(),
+        IssueAction::Schedule(send_at) => {
+            schedule_issue(&mut transaction, issue_id, send_at)
+                .await
+                .context("Failed to schedule the draft")?;
+            (
+                flash.info(scheduled_message(send_at)),
+                Redirect::to(&issue_url),
+            )
+                .into_response()
+        }
+        IssueAction::Publish => {
+            publish_issue(&state.db_pool, &mut transaction, issue_id).await?;
+            (
+                flash.info(PUBLISHED_MESSAGE),
+                Redirect::to("/admin/newsletters"),
+            )
+                .into_response()
+        }
+    };
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the draft changes")?;
+
+    Ok(response)
+}
@@ -0,0 +1,52 @@
+use askama::Template;
+use axum::{extract::State, response::Html};
+use axum_flash::IncomingFlashes;
+use uuid::Uuid;
+
+use crate::{
+    app_error::AppError,
+    app_state::AppState,
+    routes::{
+        list_mailing_lists, list_newsletter_issues, list_segments, MailingList, NewsletterIssue,
+        Segment,
+    },
+};
+
+#[derive(Template)]
+#[template(path = "admin_newsletters.html")]
+struct PublishNewsletterTemplate {
+    flashes: IncomingFlashes,
+    issues: Vec<NewsletterIssue>,
+    /// The lists a new issue can be sent to.
+    lists: Vec<MailingList>,
+    /// The segments of those lists.
+    segments: Vec<Segment>,
+    idempotency_key: String,
+}
+
+/// List the existing issues next to the form to compose a new one.
+pub async fn publish_newsletter_form(
+    flashes: IncomingFlashes,
+    State(state): State<AppState>,
+) -> Result<(IncomingFlashes, Html<String>), AppError> {
+    let issues = list_newsletter_issues(&state.db_pool).await?;
+    let lists: Vec<MailingList> = list_mailing_lists(&state.db_pool)
+        .await?
+        .into_iter()
+        .filter(|list| !list.is_archived())
+        .collect();
+    let segments = list_segments(&state.db_pool, None)
+        .await?
+        .into_iter()
+        .filter(|segment| lists.iter().any(|list| list.list_id == segment.list_id))
+        .collect();
+    let template = PublishNewsletterTemplate {
+        flashes: flashes.clone(),
+        issues,
+        lists,
+        segments,
+        idempotency_key: Uuid::new_v4().to_string(),
+    };
+
+    Ok((flashes, Html(template.render()?)))
+}
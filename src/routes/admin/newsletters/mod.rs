@@ -0,0 +1,111 @@
+mod edit;
+mod get;
+mod post;
+mod preview;
+mod schedule;
+mod test_send;
+
+pub use edit::{newsletter_issue_form, update_newsletter_issue};
+pub use get::publish_newsletter_form;
+pub use post::publish_newsletter_from_form;
+pub use preview::preview_newsletter_issue;
+pub use schedule::{cancel_newsletter_issue, reschedule_newsletter_issue};
+pub use test_send::send_test_newsletter_issue;
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use serde::Deserialize;
+
+use crate::{
+    email_client::html_to_text,
+    personalization::{validate_placeholders, Recipient},
+};
+
+/// What to do with the issue once the form has been submitted.
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum FormAction {
+    #[default]
+    Publish,
+    SaveDraft,
+    Schedule,
+}
+
+/// Check the fields shared by the compose and edit forms, returning a message
+/// suitable for a flash on failure.
+fn validate_issue(title: &str, html_content: &str, text_content: &str) -> Result<(), String> {
+    if title.trim().is_empty() {
+        return Err("The newsletter issue needs a title.".into());
+    }
+    if html_content.trim().is_empty() {
+        return Err("The newsletter issue needs an HTML body.".into());
+    }
+    validate_placeholders(html_content)?;
+    validate_placeholders(text_content)
+}
+
+/// The plain text body, generated from the HTML one when left empty.
+fn text_content_or_default(html_content: &str, text_content: String) -> String {
+    if text_content.trim().is_empty() {
+        html_to_text(html_content)
+    } else {
+        text_content
+    }
+}
+
+/// A validated [`FormAction`].
+enum IssueAction {
+    Publish,
+    SaveDraft,
+    Schedule(DateTime<Utc>),
+}
+
+impl FormAction {
+    fn validate(self, send_at: &str) -> Result<IssueAction, &'static str> {
+        Ok(match self {
+            FormAction::Publish => IssueAction::Publish,
+            FormAction::SaveDraft => IssueAction::SaveDraft,
+            FormAction::Schedule => IssueAction::Schedule(parse_send_at(send_at)?),
+        })
+    }
+}
+
+/// Parse the value of a `datetime-local` input, which is interpreted as UTC.
+fn parse_send_at(value: &str) -> Result<DateTime<Utc>, &'static str> {
+    let send_at = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S"))
+        .map_err(|_| "Please pick the date and time to send the issue at.")?;
+    let send_at = Utc.from_utc_datetime(&send_at);
+    if send_at <= Utc::now() {
+        return Err("The send time must be in the future.");
+    }
+    Ok(send_at)
+}
+
+fn scheduled_message(send_at: DateTime<Utc>) -> String {
+    format!(
+        "The newsletter issue will be sent at {}.",
+        send_at.format("%Y-%m-%d %H:%M UTC")
+    )
+}
+
+/// Used for the unsubscribe and preferences links of previews and test copies,
+/// which are not tied to an actual subscriber.
+const PREVIEW_LINK: &str = "#";
+
+/// Stands in for a subscriber when filling in placeholders for previews and
+/// test copies.
+fn sample_recipient(email: &str) -> Recipient<'_> {
+    Recipient {
+        name: "Subscriber",
+        email,
+        unsubscribe_url: PREVIEW_LINK,
+        preferences_url: PREVIEW_LINK,
+    }
+}
+
+const NO_SUCH_LIST: &str = "Please pick a mailing list that isn't archived.";
+
+const NO_SUCH_SEGMENT: &str = "Please pick a segment of the list the issue is sent to.";
+
+const PUBLISHED_MESSAGE: &str =
+    "The newsletter issue has been accepted - emails will go out shortly.";
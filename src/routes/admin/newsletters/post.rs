@@ -0,0 +1,137 @@
+use axum::{
+    extract::State,
+    response::{IntoResponse, Redirect, Response},
+    Extension, Form,
+};
+use axum_flash::Flash;
+use serde::Deserialize;
+
+use crate::{
+    app_error::AppError,
+    app_state::AppState,
+    authentication::UserId,
+    idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    routes::{
+        find_active_list, find_segment, insert_draft_issue, queue_newsletter_issue, schedule_issue,
+    },
+};
+
+use super::{
+    scheduled_message, text_content_or_default, validate_issue, FormAction, IssueAction,
+    NO_SUCH_LIST, NO_SUCH_SEGMENT, PUBLISHED_MESSAGE,
+};
+
+#[derive(Deserialize)]
+pub struct FormData {
+    title: String,
+    html_content: String,
+    /// Generated from `html_content` when left empty.
+    text_content: String,
+    idempotency_key: String,
+    /// The id of the list to send the issue to. Defaults to the main newsletter.
+    list: Option<String>,
+    /// The id of a segment of the list. Empty to send the issue to the whole
+    /// list.
+    segment: Option<String>,
+    #[serde(default)]
+    action: FormAction,
+    /// Only used when scheduling the issue.
+    #[serde(default)]
+    send_at: String,
+}
+
+/// Create a newsletter issue from the admin form, either queueing it for
+/// delivery right away, scheduling it for later or saving it as a draft.
+///
+/// The form embeds an idempotency key, so submitting it twice only creates
+/// the issue once.
+#[tracing::instrument(name = "Create a newsletter issue from the admin form", skip_all)]
+pub async fn publish_newsletter_from_form(
+    flash: Flash,
+    Extension(user_id): Extension<UserId>,
+    State(state): State<AppState>,
+    Form(form): Form<FormData>,
+) -> Result<Response, AppError> {
+    if let Err(e) = validate_issue(&form.title, &form.html_content, &form.text_content) {
+        return Ok((flash.error(e), Redirect::to("/admin/newsletters")).into_response());
+    }
+    let action = match form.action.validate(&form.send_at) {
+        Ok(action) => action,
+        Err(e) => return Ok((flash.error(e), Redirect::to("/admin/newsletters")).into_response()),
+    };
+    let Some(list) = find_active_list(&state.db_pool, form.list.as_deref()).await? else {
+        return Ok((
+            flash.error(NO_SUCH_LIST),
+            Redirect::to("/admin/newsletters"),
+        )
+            .into_response());
+    };
+    let segment_id = match form.segment.as_deref().filter(|s| !s.is_empty()) {
+        Some(segment) => match find_segment(&state.db_pool, list.list_id, segment).await? {
+            Some(segment) => Some(segment.segment_id),
+            None => {
+                return Ok((
+                    flash.error(NO_SUCH_SEGMENT),
+                    Redirect::to("/admin/newsletters"),
+                )
+                    .into_response())
+            }
+        },
+        None => None,
+    };
+    let idempotency_key: IdempotencyKey = match form.idempotency_key.try_into() {
+        Ok(key) => key,
+        Err(e) => return Ok((flash.error(e), Redirect::to("/admin/newsletters")).into_response()),
+    };
+
+    let mut transaction = match try_processing(&state.db_pool, &idempotency_key, *user_id).await? {
+        NextAction::StartProcessing(transaction) => transaction,
+        NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+    };
+
+    let text_content = text_content_or_default(&form.html_content, form.text_content);
+    let response = match action {
+        IssueAction::Publish => {
+            queue_newsletter_issue(
+                &state.db_pool,
+                &mut transaction,
+                list.list_id,
+                segment_id,
+                &form.title,
+                &form.html_content,
+                &text_content,
+            )
+            .await?;
+            (
+                flash.info(PUBLISHED_MESSAGE),
+                Redirect::to("/admin/newsletters"),
+            )
+                .into_response()
+        }
+        IssueAction::SaveDraft | IssueAction::Schedule(_) => {
+            let issue_id = insert_draft_issue(
+                &mut transaction,
+                list.list_id,
+                segment_id,
+                &form.title,
+                &form.html_content,
+                &text_content,
+            )
+            .await?;
+            let flash = if let IssueAction::Schedule(send_at) = action {
+                schedule_issue(&mut transaction, issue_id, send_at).await?;
+                flash.info(scheduled_message(send_at))
+            } else {
+                flash.info("The draft has been saved.")
+            };
+            (
+                flash,
+                Redirect::to(&format!("/admin/newsletters/{issue_id}")),
+            )
+                .into_response()
+        }
+    };
+    let response = save_response(transaction, &idempotency_key, *user_id, response).await?;
+
+    Ok(response)
+}
@@ -0,0 +1,60 @@
+use axum::{
+    extract::{Path, Query, State},
+    response::{Html, IntoResponse, Response},
+};
+use hyper::StatusCode;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    app_error::AppError,
+    app_state::AppState,
+    email_templates::NewsletterEmail,
+    personalization::{personalize_html, personalize_text},
+    routes::get_newsletter_issue,
+};
+
+use super::{sample_recipient, PREVIEW_LINK};
+
+const PREVIEW_EMAIL: &str = "subscriber@example.com";
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum PreviewFormat {
+    #[default]
+    Html,
+    Text,
+}
+
+#[derive(Deserialize)]
+pub struct PreviewParams {
+    #[serde(default)]
+    format: PreviewFormat,
+}
+
+/// Render an issue exactly as subscribers will receive it, with placeholders
+/// filled in for a sample subscriber.
+pub async fn preview_newsletter_issue(
+    State(state): State<AppState>,
+    Path(issue_id): Path<Uuid>,
+    Query(params): Query<PreviewParams>,
+) -> Result<Response, AppError> {
+    let Some(issue) = get_newsletter_issue(&state.db_pool, issue_id).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let recipient = sample_recipient(PREVIEW_EMAIL);
+    let message = NewsletterEmail {
+        title: &issue.title,
+        html_content: &personalize_html(&issue.html_content, &recipient),
+        text_content: &personalize_text(&issue.text_content, &recipient),
+        unsubscribe_url: PREVIEW_LINK,
+        preferences_url: PREVIEW_LINK,
+    }
+    .render()?;
+
+    let response = match params.format {
+        PreviewFormat::Html => Html(message.html_content).into_response(),
+        PreviewFormat::Text => message.text_content.into_response(),
+    };
+    Ok(response)
+}
@@ -0,0 +1,79 @@
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    response::Redirect,
+    Form,
+};
+use axum_flash::Flash;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    app_error::AppError,
+    app_state::AppState,
+    routes::{schedule_issue, unschedule_issue},
+};
+
+use super::{parse_send_at, scheduled_message};
+
+#[derive(Deserialize)]
+pub struct FormData {
+    send_at: String,
+}
+
+/// Move a scheduled issue to a different send time.
+#[tracing::instrument(name = "Reschedule a newsletter issue", skip(flash, state, form))]
+pub async fn reschedule_newsletter_issue(
+    flash: Flash,
+    State(state): State<AppState>,
+    Path(issue_id): Path<Uuid>,
+    Form(form): Form<FormData>,
+) -> Result<(Flash, Redirect), AppError> {
+    let issue_url = format!("/admin/newsletters/{issue_id}");
+    let send_at = match parse_send_at(&form.send_at) {
+        Ok(send_at) => send_at,
+        Err(e) => return Ok((flash.error(e), Redirect::to(&issue_url))),
+    };
+
+    let mut transaction = state
+        .db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let scheduled = schedule_issue(&mut transaction, issue_id, send_at)
+        .await
+        .context("Failed to reschedule the issue")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the new send time")?;
+
+    let flash = if scheduled {
+        flash.info(scheduled_message(send_at))
+    } else {
+        flash.error("This issue has already been sent.")
+    };
+    Ok((flash, Redirect::to(&issue_url)))
+}
+
+/// Cancel the delivery of a scheduled issue, turning it back into a draft.
+#[tracing::instrument(name = "Cancel a scheduled newsletter issue", skip(flash, state))]
+pub async fn cancel_newsletter_issue(
+    flash: Flash,
+    State(state): State<AppState>,
+    Path(issue_id): Path<Uuid>,
+) -> Result<(Flash, Redirect), AppError> {
+    let cancelled = unschedule_issue(&state.db_pool, issue_id)
+        .await
+        .context("Failed to cancel the scheduled delivery")?;
+
+    let flash = if cancelled {
+        flash.info("The scheduled delivery has been cancelled. The issue is a draft again.")
+    } else {
+        flash.error("This issue is not scheduled.")
+    };
+    Ok((
+        flash,
+        Redirect::to(&format!("/admin/newsletters/{issue_id}")),
+    ))
+}
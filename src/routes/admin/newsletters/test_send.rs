@@ -0,0 +1,73 @@
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Redirect, Response},
+    Form,
+};
+use axum_flash::Flash;
+use hyper::StatusCode;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    app_error::AppError,
+    app_state::AppState,
+    domain::SubscriberEmail,
+    email_templates::NewsletterEmail,
+    personalization::{personalize_html, personalize_text},
+    routes::get_newsletter_issue,
+};
+
+use super::{sample_recipient, PREVIEW_LINK};
+
+#[derive(Deserialize)]
+pub struct FormData {
+    email: String,
+}
+
+/// Send a single copy of an issue to any address, e.g. an editor's own inbox.
+///
+/// Nothing is queued and the recipient doesn't need to be a subscriber.
+#[tracing::instrument(
+    name = "Send a test copy of a newsletter issue",
+    skip(flash, state, form),
+    fields(recipient = %form.email)
+)]
+pub async fn send_test_newsletter_issue(
+    flash: Flash,
+    State(state): State<AppState>,
+    Path(issue_id): Path<Uuid>,
+    Form(form): Form<FormData>,
+) -> Result<Response, AppError> {
+    let issue_url = format!("/admin/newsletters/{issue_id}");
+    let recipient = match SubscriberEmail::parse(form.email) {
+        Ok(email) => email,
+        Err(e) => return Ok((flash.error(e), Redirect::to(&issue_url)).into_response()),
+    };
+    let Some(issue) = get_newsletter_issue(&state.db_pool, issue_id).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    let sample = sample_recipient(recipient.as_ref());
+    let mut message = NewsletterEmail {
+        title: &issue.title,
+        html_content: &personalize_html(&issue.html_content, &sample),
+        text_content: &personalize_text(&issue.text_content, &sample),
+        unsubscribe_url: PREVIEW_LINK,
+        preferences_url: PREVIEW_LINK,
+    }
+    .render()?;
+    message.unsubscribe_url = None;
+
+    let flash = match state.email_client.send_email(&recipient, &message).await {
+        Ok(()) => flash.info(format!(
+            "A test copy has been sent to {}.",
+            recipient.as_ref()
+        )),
+        Err(e) => {
+            tracing::warn!(error.message = %e, "Failed to send a test copy");
+            flash.error(format!("Failed to send the test copy: {e}"))
+        }
+    };
+
+    Ok((flash, Redirect::to(&issue_url)).into_response())
+}
@@ -0,0 +1,223 @@
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    response::Redirect,
+};
+use axum_flash::Flash;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    app_error::AppError,
+    app_state::AppState,
+    routes::{erase_subscriber, record_change},
+};
+
+enum Confirmation {
+    Confirmed,
+    /// The address bounced or complained, which a confirmation must not undo.
+    Suppressed,
+    NoSuchSubscriber,
+}
+
+/// Confirm the address of a subscriber along with every list they are still
+/// waiting to confirm, e.g. when their confirmation email got lost. Every
+/// change is recorded in `preference_changes`.
+#[tracing::instrument(name = "Manually confirm a subscriber", skip(flash, state))]
+pub async fn manually_confirm_subscriber(
+    flash: Flash,
+    State(state): State<AppState>,
+    Path(subscriber_id): Path<Uuid>,
+) -> Result<(Flash, Redirect), AppError> {
+    let flash = match confirm_all(&state.db_pool, subscriber_id).await? {
+        Confirmation::Confirmed => flash.info("The subscriber has been confirmed."),
+        Confirmation::Suppressed => {
+            flash.error("The subscriber can't be confirmed, their address bounced or complained.")
+        }
+        Confirmation::NoSuchSubscriber => flash.error("There is no such subscriber."),
+    };
+
+    Ok((
+        flash,
+        Redirect::to(&format!("/admin/subscribers/{subscriber_id}")),
+    ))
+}
+
+/// Only pending subscribers and list subscriptions are confirmed, so that
+/// suppressed addresses stay suppressed.
+async fn confirm_all(pool: &PgPool, subscriber_id: Uuid) -> Result<Confirmation, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let status = sqlx::query_scalar!(
+        "SELECT status FROM subscriptions WHERE id = $1 FOR UPDATE",
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to retrieve the status of the subscriber")?;
+    match status.as_deref() {
+        None => return Ok(Confirmation::NoSuchSubscriber),
+        Some("bounced" | "complained") => return Ok(Confirmation::Suppressed),
+        Some("pending_confirmation") => {
+            sqlx::query!(
+                r#"
+                UPDATE subscriptions
+                SET status = 'confirmed', confirmed_at = COALESCE(confirmed_at, now())
+                WHERE id = $1 AND status = 'pending_confirmation'
+                "#,
+                subscriber_id
+            )
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to confirm the subscriber")?;
+            record_change(
+                &mut transaction,
+                subscriber_id,
+                "status",
+                Some("pending_confirmation"),
+                Some("confirmed"),
+                "admin",
+            )
+            .await
+            .context("Failed to record the confirmation")?;
+        }
+        Some(_) => {}
+    }
+    set_list_statuses(
+        &mut transaction,
+        subscriber_id,
+        &["pending_confirmation"],
+        "confirmed",
+    )
+    .await
+    .context("Failed to confirm the list subscriptions")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the confirmation")?;
+
+    Ok(Confirmation::Confirmed)
+}
+
+/// Move the list subscriptions of a subscriber from any of `from` to `to`,
+/// recording each change as made by an admin.
+async fn set_list_statuses(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    from: &[&str],
+    to: &str,
+) -> Result<(), sqlx::Error> {
+    let from: Vec<String> = from.iter().map(|status| status.to_string()).collect();
+    let changed = sqlx::query!(
+        r#"
+        WITH previous AS (
+            SELECT list_id, status
+            FROM list_subscriptions
+            WHERE subscriber_id = $1 AND status = ANY($2)
+            FOR UPDATE
+        )
+        UPDATE list_subscriptions
+        SET status = $3
+        FROM previous JOIN lists USING (list_id)
+        WHERE list_subscriptions.subscriber_id = $1 AND list_subscriptions.list_id = previous.list_id
+        RETURNING lists.slug, previous.status AS previous_status
+        "#,
+        subscriber_id,
+        &from,
+        to
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+    for list in changed {
+        record_change(
+            transaction,
+            subscriber_id,
+            &format!("list:{}", list.slug),
+            Some(&list.previous_status),
+            Some(to),
+            "admin",
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Unsubscribe a subscriber from every list. The subscriber is kept, so they
+/// can still subscribe again. Every change is recorded in
+/// `preference_changes`.
+#[tracing::instrument(name = "Manually unsubscribe a subscriber", skip(flash, state))]
+pub async fn manually_unsubscribe_subscriber(
+    flash: Flash,
+    State(state): State<AppState>,
+    Path(subscriber_id): Path<Uuid>,
+) -> Result<(Flash, Redirect), AppError> {
+    let mut transaction = state
+        .db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    set_list_statuses(
+        &mut transaction,
+        subscriber_id,
+        &["pending_confirmation", "confirmed"],
+        "unsubscribed",
+    )
+    .await
+    .context("Failed to unsubscribe the subscriber")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the unsubscription")?;
+
+    Ok((
+        flash.info("The subscriber has been unsubscribed from every list."),
+        Redirect::to(&format!("/admin/subscribers/{subscriber_id}")),
+    ))
+}
+
+/// Delete a subscriber along with their list subscriptions, tags, tokens and
+/// recorded events. Unlike an erased subscriber, they can be imported again.
+#[tracing::instrument(name = "Delete a subscriber", skip(flash, state))]
+pub async fn delete_subscriber(
+    flash: Flash,
+    State(state): State<AppState>,
+    Path(subscriber_id): Path<Uuid>,
+) -> Result<(Flash, Redirect), AppError> {
+    let flash = match delete(&state.db_pool, subscriber_id).await? {
+        Some(email) => flash.info(format!("{email} has been deleted.")),
+        None => flash.error("There is no such subscriber."),
+    };
+
+    Ok((flash, Redirect::to("/admin/subscribers")))
+}
+
+/// Erase a subscriber who asked us to by other means than the link we email,
+/// leaving a hash of their address so that it isn't imported again.
+#[tracing::instrument(name = "Erase a subscriber", skip(flash, state))]
+pub async fn erase_subscriber_data(
+    flash: Flash,
+    State(state): State<AppState>,
+    Path(subscriber_id): Path<Uuid>,
+) -> Result<(Flash, Redirect), AppError> {
+    let flash = match erase_subscriber(&state.db_pool, subscriber_id, &state.hmac_secret.0).await? {
+        Some(email) => flash.info(format!("The data of {email} has been erased.")),
+        None => flash.error("There is no such subscriber."),
+    };
+
+    Ok((flash, Redirect::to("/admin/subscribers")))
+}
+
+/// Returns the email of the deleted subscriber, or `None` if there was no
+/// such subscriber.
+async fn delete(pool: &PgPool, subscriber_id: Uuid) -> Result<Option<String>, anyhow::Error> {
+    sqlx::query_scalar!(
+        "DELETE FROM subscriptions WHERE id = $1 RETURNING email",
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to delete the subscriber")
+}
@@ -0,0 +1,246 @@
+use std::cmp::Reverse;
+
+use anyhow::Context;
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    response::{Html, IntoResponse, Response},
+};
+use axum_flash::IncomingFlashes;
+use chrono::{DateTime, Utc};
+use hyper::StatusCode;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{app_error::AppError, app_state::AppState};
+
+pub struct SubscriberDetails {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub frequency: String,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+pub struct ListMembership {
+    pub list_id: Uuid,
+    pub list_name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub tags: Vec<String>,
+}
+
+pub struct ConfirmationToken {
+    pub list_name: String,
+    pub created_at: DateTime<Utc>,
+    pub expired: bool,
+}
+
+pub struct Delivery {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub status: String,
+    pub n_attempts: i16,
+    pub sent_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+/// Something that happened to the subscriber.
+pub struct HistoryEntry {
+    pub at: DateTime<Utc>,
+    pub description: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin_subscriber.html")]
+struct SubscriberTemplate {
+    flashes: IncomingFlashes,
+    subscriber: SubscriberDetails,
+    lists: Vec<ListMembership>,
+    tokens: Vec<ConfirmationToken>,
+    deliveries: Vec<Delivery>,
+    /// Most recent first.
+    history: Vec<HistoryEntry>,
+}
+
+/// Show everything we know about a subscriber, with buttons to confirm,
+/// unsubscribe or delete them.
+pub async fn subscriber_page(
+    flashes: IncomingFlashes,
+    State(state): State<AppState>,
+    Path(subscriber_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let pool = &state.db_pool;
+    let Some(subscriber) = get_subscriber(pool, subscriber_id).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let lists = get_list_memberships(pool, subscriber_id).await?;
+    let tokens = get_confirmation_tokens(pool, subscriber_id)
+        .await?
+        .into_iter()
+        .map(|(list_name, created_at)| ConfirmationToken {
+            list_name,
+            created_at,
+            expired: created_at + state.subscription_token_ttl < Utc::now(),
+        })
+        .collect();
+    let deliveries = get_deliveries(pool, &subscriber.email).await?;
+    let history = get_history(pool, &subscriber, &lists).await?;
+    let template = SubscriberTemplate {
+        flashes: flashes.clone(),
+        subscriber,
+        lists,
+        tokens,
+        deliveries,
+        history,
+    };
+
+    Ok((flashes, Html(template.render()?)).into_response())
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberDetails>, sqlx::Error> {
+    sqlx::query_as!(
+        SubscriberDetails,
+        r#"
+        SELECT id, email, name, status, frequency, subscribed_at
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_list_memberships(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<ListMembership>, sqlx::Error> {
+    sqlx::query_as!(
+        ListMembership,
+        r#"
+        SELECT
+            lists.list_id,
+            lists.name AS list_name,
+            list_subscriptions.status,
+            list_subscriptions.subscribed_at,
+            COALESCE(
+                array_agg(subscriber_tags.tag ORDER BY subscriber_tags.tag)
+                    FILTER (WHERE subscriber_tags.tag IS NOT NULL),
+                '{}'
+            ) AS "tags!"
+        FROM list_subscriptions
+        JOIN lists ON lists.list_id = list_subscriptions.list_id
+        LEFT JOIN subscriber_tags ON
+            subscriber_tags.list_id = list_subscriptions.list_id AND
+            subscriber_tags.subscriber_id = list_subscriptions.subscriber_id
+        WHERE list_subscriptions.subscriber_id = $1
+        GROUP BY lists.list_id, list_subscriptions.status, list_subscriptions.subscribed_at
+        ORDER BY lists.created_at, lists.slug
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// The confirmation tokens sent to the subscriber, newest first. The tokens
+/// themselves are secrets and are not shown.
+#[tracing::instrument(skip(pool))]
+async fn get_confirmation_tokens(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<(String, DateTime<Utc>)>, sqlx::Error> {
+    let tokens = sqlx::query!(
+        r#"
+        SELECT lists.name, subscription_tokens.created_at
+        FROM subscription_tokens
+        JOIN lists ON lists.list_id = subscription_tokens.list_id
+        WHERE subscriber_id = $1
+        ORDER BY subscription_tokens.created_at DESC
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|r| (r.name, r.created_at))
+    .collect();
+
+    Ok(tokens)
+}
+
+/// The latest deliveries of newsletter issues to the subscriber's address.
+#[tracing::instrument(skip(pool))]
+async fn get_deliveries(pool: &PgPool, email: &str) -> Result<Vec<Delivery>, sqlx::Error> {
+    sqlx::query_as!(
+        Delivery,
+        r#"
+        SELECT
+            q.newsletter_issue_id,
+            i.title,
+            CASE q.status WHEN 'dead_letter' THEN 'failed' ELSE q.status END AS "status!",
+            q.n_attempts,
+            q.sent_at,
+            q.last_error
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        WHERE q.subscriber_email = $1
+        ORDER BY i.published_at DESC NULLS LAST
+        LIMIT 100
+        "#,
+        email
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn get_history(
+    pool: &PgPool,
+    subscriber: &SubscriberDetails,
+    lists: &[ListMembership],
+) -> Result<Vec<HistoryEntry>, anyhow::Error> {
+    let changes = sqlx::query!(
+        r#"
+        SELECT field, old_value, new_value, changed_by, changed_at
+        FROM preference_changes
+        WHERE subscriber_id = $1
+        "#,
+        subscriber.id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the preference changes of the subscriber")?;
+
+    let mut history = vec![HistoryEntry {
+        at: subscriber.subscribed_at,
+        description: "Signed up".into(),
+    }];
+    history.extend(lists.iter().map(|list| HistoryEntry {
+        at: list.subscribed_at,
+        description: format!("Joined {}", list.list_name),
+    }));
+    history.extend(changes.into_iter().map(|change| HistoryEntry {
+        at: change.changed_at,
+        description: format!(
+            "Changed {} from {} to {} {}",
+            change.field,
+            change.old_value.as_deref().unwrap_or("nothing"),
+            change.new_value.as_deref().unwrap_or("nothing"),
+            if change.changed_by == "admin" {
+                "by an admin"
+            } else {
+                "in the preference center"
+            },
+        ),
+    }));
+    history.sort_by_key(|entry| Reverse(entry.at));
+
+    Ok(history)
+}
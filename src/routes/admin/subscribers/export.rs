@@ -0,0 +1,339 @@
+use std::{borrow::Cow, io};
+
+use axum::{
+    body::{Bytes, StreamBody},
+    extract::{Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use futures_util::{stream, TryStreamExt};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::{app_error::AppError, app_state::AppState, routes::get_list, spreadsheet};
+
+use super::STATUSES;
+
+/// How many subscribers are written per chunk of the response.
+const CHUNK_SIZE: usize = 500;
+
+#[derive(Deserialize)]
+pub struct ExportParams {
+    #[serde(default)]
+    format: String,
+    /// Only subscribers with this address status.
+    #[serde(default)]
+    status: String,
+    /// Only subscribers of the list with this id.
+    #[serde(default)]
+    list: String,
+}
+
+#[derive(Clone, Copy)]
+enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    fn parse(s: &str) -> Result<ExportFormat, String> {
+        match s {
+            "" | "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            _ => Err(format!("{s} is not a supported export format")),
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Json => "application/json",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ExportedSubscriber {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+}
+
+/// A subscriber as written to the CSV export, with what they typed in
+/// [escaped](spreadsheet::escape_cell).
+#[derive(Serialize)]
+struct CsvSubscriber<'a> {
+    id: Uuid,
+    email: Cow<'a, str>,
+    name: Cow<'a, str>,
+    status: &'a str,
+    subscribed_at: DateTime<Utc>,
+    confirmed_at: Option<DateTime<Utc>>,
+}
+
+impl<'a> From<&'a ExportedSubscriber> for CsvSubscriber<'a> {
+    fn from(subscriber: &'a ExportedSubscriber) -> Self {
+        Self {
+            id: subscriber.id,
+            email: spreadsheet::escape_cell(&subscriber.email),
+            name: spreadsheet::escape_cell(&subscriber.name),
+            status: &subscriber.status,
+            subscribed_at: subscriber.subscribed_at,
+            confirmed_at: subscriber.confirmed_at,
+        }
+    }
+}
+
+/// Download every subscriber as CSV or JSON, oldest first, optionally only
+/// those with a given status or on a given list. Subscribers who left the list
+/// are not part of the export of the list. Subscribers who had their data
+/// erased are not part of any export: only a hash of their address is kept,
+/// outside of `subscriptions`.
+///
+/// Subscribers are streamed from the database into the response a chunk at a
+/// time, so that the table never has to fit in memory.
+#[tracing::instrument(name = "Export subscribers", skip(state, params))]
+pub async fn export_subscribers(
+    State(state): State<AppState>,
+    Query(params): Query<ExportParams>,
+) -> Result<Response, AppError> {
+    let format = match ExportFormat::parse(&params.format) {
+        Ok(format) => format,
+        Err(e) => return Ok((StatusCode::BAD_REQUEST, e).into_response()),
+    };
+    let status = match params.status.as_str() {
+        "" => None,
+        status if STATUSES.contains(&status) => Some(status.to_owned()),
+        status => {
+            let message = format!("{status} is not a valid subscriber status");
+            return Ok((StatusCode::BAD_REQUEST, message).into_response());
+        }
+    };
+    let list_id = match params.list.as_str() {
+        "" => None,
+        list => match Uuid::try_parse(list) {
+            Ok(list_id) if get_list(&state.db_pool, list_id).await?.is_some() => Some(list_id),
+            _ => return Ok((StatusCode::NOT_FOUND, "There is no such list").into_response()),
+        },
+    };
+
+    // The rows borrow the pool while they are streamed, so they are read in a
+    // task of their own and handed over to the response through a channel.
+    let (sender, receiver) = mpsc::channel(4);
+    tokio::spawn(write_export(
+        state.db_pool.clone(),
+        format,
+        status,
+        list_id,
+        sender,
+    ));
+    let body = stream::unfold(receiver, |mut receiver| async {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"subscribers.{}\"",
+                    format.extension()
+                ),
+            ),
+        ],
+        StreamBody::new(body),
+    )
+        .into_response())
+}
+
+/// Write the export in chunks of [`CHUNK_SIZE`] subscribers. A failure is
+/// passed on as an error, which cuts the response short. Stops as soon as
+/// the response is dropped, e.g. because the client went away.
+async fn write_export(
+    pool: PgPool,
+    format: ExportFormat,
+    status: Option<String>,
+    list_id: Option<Uuid>,
+    chunks: mpsc::Sender<io::Result<Bytes>>,
+) {
+    let mut subscribers = sqlx::query_as!(
+        ExportedSubscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at, confirmed_at
+        FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR status = $1) AND
+            ($2::uuid IS NULL OR EXISTS (
+                SELECT 1 FROM list_subscriptions
+                WHERE
+                    list_subscriptions.subscriber_id = subscriptions.id AND
+                    list_subscriptions.list_id = $2 AND
+                    list_subscriptions.status <> 'unsubscribed'
+            ))
+        ORDER BY subscribed_at, id
+        "#,
+        status,
+        list_id
+    )
+    .fetch(&pool)
+    .try_chunks(CHUNK_SIZE);
+
+    let mut is_first = true;
+    loop {
+        let (chunk, is_last) = match subscribers.try_next().await {
+            Ok(Some(subscribers)) => (encode(format, &subscribers, is_first), false),
+            Ok(None) => (finish(format, is_first), true),
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e.1,
+                    error.message = %e.1,
+                    "Failed to read the subscribers to export"
+                );
+                (Err(io::Error::other(e.1)), true)
+            }
+        };
+        if chunks.send(chunk.map(Bytes::from)).await.is_err() || is_last {
+            break;
+        }
+        is_first = false;
+    }
+}
+
+/// Encode a chunk of subscribers. The first chunk starts the file: the CSV
+/// header or the opening bracket of the JSON array.
+fn encode(
+    format: ExportFormat,
+    subscribers: &[ExportedSubscriber],
+    is_first: bool,
+) -> io::Result<Vec<u8>> {
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(vec![]);
+            if is_first {
+                writer.write_record([
+                    "id",
+                    "email",
+                    "name",
+                    "status",
+                    "subscribed_at",
+                    "confirmed_at",
+                ])?;
+            }
+            for subscriber in subscribers {
+                writer.serialize(CsvSubscriber::from(subscriber))?;
+            }
+            writer
+                .into_inner()
+                .map_err(|e| io::Error::other(e.to_string()))
+        }
+        ExportFormat::Json => {
+            let mut buffer = vec![if is_first { b'[' } else { b',' }];
+            for (i, subscriber) in subscribers.iter().enumerate() {
+                if i > 0 {
+                    buffer.push(b',');
+                }
+                serde_json::to_writer(&mut buffer, subscriber)?;
+            }
+            Ok(buffer)
+        }
+    }
+}
+
+/// The end of the file, once every subscriber has been written.
+fn finish(format: ExportFormat, is_first: bool) -> io::Result<Vec<u8>> {
+    match format {
+        ExportFormat::Csv if is_first => encode(format, &[], true),
+        ExportFormat::Csv => Ok(vec![]),
+        ExportFormat::Json if is_first => Ok(b"[]".to_vec()),
+        ExportFormat::Json => Ok(b"]".to_vec()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, finish, ExportFormat, ExportedSubscriber};
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    fn subscriber() -> ExportedSubscriber {
+        ExportedSubscriber {
+            id: Uuid::nil(),
+            email: "ursula@example.com".into(),
+            name: "Le Guin, Ursula".into(),
+            status: "confirmed".into(),
+            subscribed_at: Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap(),
+            confirmed_at: None,
+        }
+    }
+
+    #[test]
+    fn only_the_first_csv_chunk_has_a_header() {
+        let first = encode(ExportFormat::Csv, &[subscriber()], true).unwrap();
+        let next = encode(ExportFormat::Csv, &[subscriber()], false).unwrap();
+
+        let row = "00000000-0000-0000-0000-000000000000,ursula@example.com,\
+            \"Le Guin, Ursula\",confirmed,2023-01-01T00:00:00Z,\n";
+        assert_eq!(
+            String::from_utf8(first).unwrap(),
+            format!("id,email,name,status,subscribed_at,confirmed_at\n{row}")
+        );
+        assert_eq!(String::from_utf8(next).unwrap(), row);
+    }
+
+    #[test]
+    fn formulas_are_escaped_in_csv_but_not_in_json() {
+        let subscriber = ExportedSubscriber {
+            name: "=HYPERLINK(\"https://evil.example\")".into(),
+            ..subscriber()
+        };
+        let csv = encode(ExportFormat::Csv, std::slice::from_ref(&subscriber), false).unwrap();
+        let json = encode(ExportFormat::Json, &[subscriber], false).unwrap();
+
+        assert!(String::from_utf8(csv)
+            .unwrap()
+            .contains(r#""'=HYPERLINK(""https://evil.example"")""#));
+        let json: serde_json::Value = serde_json::from_slice(&json[1..]).unwrap();
+        assert_eq!(json["name"], "=HYPERLINK(\"https://evil.example\")");
+    }
+
+    #[test]
+    fn json_chunks_make_up_an_array() {
+        let mut export = encode(ExportFormat::Json, &[subscriber(), subscriber()], true).unwrap();
+        export.extend(encode(ExportFormat::Json, &[subscriber()], false).unwrap());
+        export.extend(finish(ExportFormat::Json, false).unwrap());
+
+        let export: serde_json::Value = serde_json::from_slice(&export).unwrap();
+        assert_eq!(export.as_array().unwrap().len(), 3);
+        assert_eq!(export[0]["email"], "ursula@example.com");
+        assert_eq!(export[0]["confirmed_at"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn empty_exports_are_still_valid() {
+        let csv = finish(ExportFormat::Csv, true).unwrap();
+        let json = finish(ExportFormat::Json, true).unwrap();
+
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "id,email,name,status,subscribed_at,confirmed_at\n"
+        );
+        assert_eq!(String::from_utf8(json).unwrap(), "[]");
+    }
+}
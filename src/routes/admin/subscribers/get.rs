@@ -0,0 +1,143 @@
+use askama::Template;
+use axum::{
+    extract::{Query, State},
+    response::Html,
+};
+use axum_flash::IncomingFlashes;
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{app_error::AppError, app_state::AppState};
+
+use super::{Cursor, STATUSES};
+
+const PAGE_SIZE: i64 = 50;
+
+#[derive(Deserialize)]
+pub struct SubscriberFilters {
+    /// Part of the email or name to look for.
+    #[serde(default)]
+    q: String,
+    #[serde(default)]
+    status: String,
+    /// The first day subscribers joined on, as `YYYY-MM-DD`.
+    #[serde(default)]
+    from: String,
+    /// The last day subscribers joined on, as `YYYY-MM-DD`.
+    #[serde(default)]
+    to: String,
+    /// Where the page starts, see [`Cursor`].
+    after: Option<String>,
+}
+
+pub struct SubscriberRow {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+#[derive(Template)]
+#[template(path = "admin_subscribers.html")]
+struct SubscribersTemplate {
+    flashes: IncomingFlashes,
+    filters: SubscriberFilters,
+    /// Every status along with whether it is the one being filtered by.
+    statuses: Vec<(&'static str, bool)>,
+    subscribers: Vec<SubscriberRow>,
+    /// Set when there are more subscribers after this page.
+    next_cursor: Option<String>,
+}
+
+/// List subscribers newest first, a page at a time, optionally searching by
+/// email or name and filtering by status and subscription date.
+pub async fn subscribers_page(
+    flashes: IncomingFlashes,
+    State(state): State<AppState>,
+    Query(filters): Query<SubscriberFilters>,
+) -> Result<(IncomingFlashes, Html<String>), AppError> {
+    let mut subscribers = search_subscribers(&state.db_pool, &filters).await?;
+    let next_cursor = if subscribers.len() as i64 > PAGE_SIZE {
+        subscribers.truncate(PAGE_SIZE as usize);
+        subscribers.last().map(|last| {
+            Cursor {
+                subscribed_at: last.subscribed_at,
+                id: last.id,
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+    let statuses = STATUSES
+        .into_iter()
+        .map(|status| (status, status == filters.status))
+        .collect();
+    let template = SubscribersTemplate {
+        flashes: flashes.clone(),
+        filters,
+        statuses,
+        subscribers,
+        next_cursor,
+    };
+
+    Ok((flashes, Html(template.render()?)))
+}
+
+/// Fetch one more subscriber than fits on a page, to know whether there is a
+/// next one. Filters that are empty or can't be parsed are ignored.
+#[tracing::instrument(skip_all)]
+async fn search_subscribers(
+    pool: &PgPool,
+    filters: &SubscriberFilters,
+) -> Result<Vec<SubscriberRow>, sqlx::Error> {
+    let pattern = match filters.q.trim() {
+        "" => None,
+        q => Some(format!("%{}%", escape_like(q))),
+    };
+    let status = Some(filters.status.as_str()).filter(|s| STATUSES.contains(s));
+    let from = parse_day(&filters.from);
+    let until = parse_day(&filters.to).map(|to| to + Duration::days(1));
+    let cursor = filters.after.as_deref().and_then(Cursor::parse);
+
+    sqlx::query_as!(
+        SubscriberRow,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR email ILIKE $1 OR name ILIKE $1) AND
+            ($2::text IS NULL OR status = $2) AND
+            ($3::timestamptz IS NULL OR subscribed_at >= $3) AND
+            ($4::timestamptz IS NULL OR subscribed_at < $4) AND
+            ($5::timestamptz IS NULL OR (subscribed_at, id) < ($5, $6))
+        ORDER BY subscribed_at DESC, id DESC
+        LIMIT $7
+        "#,
+        pattern,
+        status,
+        from,
+        until,
+        cursor.as_ref().map(|c| c.subscribed_at),
+        cursor.as_ref().map(|c| c.id),
+        PAGE_SIZE + 1
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Match `%`, `_` and `\` literally in a `LIKE` pattern.
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// The start of a day given as `YYYY-MM-DD`, in UTC.
+fn parse_day(s: &str) -> Option<DateTime<Utc>> {
+    let day = NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d").ok()?;
+    Some(Utc.from_utc_datetime(&day.and_hms_opt(0, 0, 0)?))
+}
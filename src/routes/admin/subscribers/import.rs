@@ -0,0 +1,234 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use askama::Template;
+use axum::{
+    extract::{multipart::Field, Multipart, Path, State},
+    http::header,
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use axum_flash::{Flash, IncomingFlashes};
+use hyper::StatusCode;
+use secrecy::Secret;
+use sqlx::PgPool;
+use tokio::io::AsyncWriteExt;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::{
+    app_error::AppError,
+    app_state::AppState,
+    routes::{find_active_list, list_mailing_lists, MailingList},
+    subscriber_import::{
+        count_rejected_rows, create_import, finish_import, get_import, get_rejected_rows,
+        recent_imports, run_import, upload_path, write_rejection_report, ImportError, ImportStatus,
+        RejectedRow, SubscriberImport,
+    },
+};
+
+/// The largest file that can be uploaded, about a million subscribers.
+pub const MAX_IMPORT_FILE_SIZE: usize = 64 * 1024 * 1024;
+
+/// How many rejected rows are shown on the page of an import. The report has
+/// all of them.
+const SHOWN_REJECTIONS: i64 = 100;
+
+#[derive(Template)]
+#[template(path = "admin_subscriber_import.html")]
+struct ImportFormTemplate {
+    flashes: IncomingFlashes,
+    lists: Vec<MailingList>,
+    imports: Vec<SubscriberImport>,
+}
+
+/// Show the form to upload a CSV file of subscribers, along with the most
+/// recent imports.
+pub async fn import_subscribers_form(
+    flashes: IncomingFlashes,
+    State(state): State<AppState>,
+) -> Result<(IncomingFlashes, Html<String>), AppError> {
+    let lists = list_mailing_lists(&state.db_pool)
+        .await?
+        .into_iter()
+        .filter(|list| !list.is_archived())
+        .collect();
+    let template = ImportFormTemplate {
+        flashes: flashes.clone(),
+        lists,
+        imports: recent_imports(&state.db_pool).await?,
+    };
+
+    Ok((flashes, Html(template.render()?)))
+}
+
+/// Start importing the subscribers of an uploaded CSV file, then redirect to
+/// the page of the import, which shows its progress. The file is saved to a
+/// temporary file as it arrives, so it must be the last field of the form, and
+/// imported in the background so that large files don't hold up the request.
+#[tracing::instrument(
+    name = "Import subscribers from an upload",
+    skip(flash, state, multipart)
+)]
+pub async fn upload_subscribers(
+    flash: Flash,
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<(Flash, Redirect), AppError> {
+    let back = Redirect::to("/admin/subscribers/import");
+    let mut list = None;
+    let mut status = String::new();
+    let mut consent_source = None;
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .context("Failed to read the upload")?
+    {
+        match field.name().unwrap_or_default() {
+            "list" => list = Some(field.text().await.context("Failed to read the upload")?),
+            "status" => status = field.text().await.context("Failed to read the upload")?,
+            "consent_source" => {
+                consent_source = Some(field.text().await.context("Failed to read the upload")?)
+            }
+            "file" => {
+                let Some(list) = find_active_list(&state.db_pool, list.as_deref()).await? else {
+                    return Ok((flash.error("There is no such mailing list."), back));
+                };
+                let status = match ImportStatus::parse(&status, consent_source.as_deref()) {
+                    Ok(status) => status,
+                    Err(e) => return Ok((flash.error(format!("{e}.")), back)),
+                };
+                let file_name = field.file_name().unwrap_or("upload.csv").to_owned();
+
+                let import_id = create_import(&state.db_pool, &list, &status, &file_name).await?;
+                let path = upload_path(import_id);
+                if let Err(e) = save_upload(field, &path).await {
+                    let _ = tokio::fs::remove_file(&path).await;
+                    finish_import(&state.db_pool, import_id, Some("The upload failed")).await?;
+                    return Err(e.into());
+                }
+                tokio::spawn(
+                    import_uploaded_file(
+                        state.db_pool.clone(),
+                        import_id,
+                        list,
+                        status,
+                        path,
+                        state.hmac_secret.0.clone(),
+                    )
+                    .instrument(tracing::Span::current()),
+                );
+
+                return Ok((
+                    flash.info("The file is being imported."),
+                    Redirect::to(&format!("/admin/subscribers/imports/{import_id}")),
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    Ok((flash.error("Please choose a file to import."), back))
+}
+
+async fn save_upload(mut field: Field<'_>, path: &std::path::Path) -> Result<(), anyhow::Error> {
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .context("Failed to create a file for the upload")?;
+    while let Some(chunk) = field.chunk().await.context("Failed to read the upload")? {
+        file.write_all(&chunk)
+            .await
+            .context("Failed to save the upload")?;
+    }
+    file.flush().await.context("Failed to save the upload")?;
+
+    Ok(())
+}
+
+/// Import a saved upload, then delete it. The outcome is recorded on the
+/// import, so failures are only logged here.
+async fn import_uploaded_file(
+    pool: PgPool,
+    import_id: Uuid,
+    list: MailingList,
+    status: ImportStatus,
+    path: PathBuf,
+    hmac_secret: Secret<String>,
+) {
+    let outcome = match tokio::fs::File::open(&path).await {
+        Ok(file) => run_import(&pool, import_id, &list, &status, file, &hmac_secret).await,
+        Err(e) => {
+            let _ = finish_import(&pool, import_id, Some("The upload could not be read")).await;
+            Err(ImportError::Unexpected(
+                anyhow::Error::new(e).context("Failed to open the upload"),
+            ))
+        }
+    };
+    // Invalid files are reported on the page of the import.
+    if let Err(ImportError::Unexpected(e)) = outcome {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to import an uploaded file"
+        );
+    }
+    if let Err(e) = tokio::fs::remove_file(&path).await {
+        tracing::warn!(error.message = %e, "Failed to delete an imported upload");
+    }
+}
+
+#[derive(Template)]
+#[template(path = "admin_subscriber_import_report.html")]
+struct ImportReportTemplate {
+    flashes: IncomingFlashes,
+    import: SubscriberImport,
+    rejected: Vec<RejectedRow>,
+    /// Whether some of the rejected rows aren't shown.
+    truncated: bool,
+}
+
+/// Show how an import went, with the first rows it rejected.
+pub async fn subscriber_import_page(
+    flashes: IncomingFlashes,
+    State(state): State<AppState>,
+    Path(import_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let Some(import) = get_import(&state.db_pool, import_id).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let rejected = get_rejected_rows(&state.db_pool, import_id, Some(SHOWN_REJECTIONS)).await?;
+    let truncated = count_rejected_rows(&state.db_pool, import_id).await? > SHOWN_REJECTIONS;
+    let template = ImportReportTemplate {
+        flashes: flashes.clone(),
+        import,
+        rejected,
+        truncated,
+    };
+
+    Ok((flashes, Html(template.render()?)).into_response())
+}
+
+/// Download every row an import rejected, and why, as a CSV file.
+pub async fn download_rejection_report(
+    State(state): State<AppState>,
+    Path(import_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    if get_import(&state.db_pool, import_id).await?.is_none() {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    let rejected = get_rejected_rows(&state.db_pool, import_id, None).await?;
+    let mut report = vec![];
+    write_rejection_report(&rejected, &mut report)
+        .context("Failed to write the rejection report")?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"rejected-rows-{import_id}.csv\""),
+            ),
+        ],
+        report,
+    )
+        .into_response())
+}
@@ -0,0 +1,86 @@
+mod actions;
+mod detail;
+mod export;
+mod get;
+mod import;
+
+pub use actions::{
+    delete_subscriber, erase_subscriber_data, manually_confirm_subscriber,
+    manually_unsubscribe_subscriber,
+};
+pub use detail::subscriber_page;
+pub use export::export_subscribers;
+pub use get::subscribers_page;
+pub use import::{
+    download_rejection_report, import_subscribers_form, subscriber_import_page, upload_subscribers,
+    MAX_IMPORT_FILE_SIZE,
+};
+
+use chrono::{DateTime, TimeZone, Utc};
+use uuid::Uuid;
+
+/// The address statuses subscribers can be filtered by.
+const STATUSES: [&str; 4] = ["pending_confirmation", "confirmed", "bounced", "complained"];
+
+/// Where a page of subscribers starts when paging through them newest first:
+/// right after the subscriber who subscribed at `subscribed_at` with id `id`.
+///
+/// Unlike an offset, this stays correct while people subscribe, and the
+/// database can jump straight to it using an index.
+#[derive(Debug, PartialEq)]
+struct Cursor {
+    subscribed_at: DateTime<Utc>,
+    id: Uuid,
+}
+
+impl Cursor {
+    fn parse(s: &str) -> Option<Cursor> {
+        let (micros, id) = s.split_once('_')?;
+        let micros: i64 = micros.parse().ok()?;
+        let nanos = (micros.rem_euclid(1_000_000) * 1_000) as u32;
+        Some(Cursor {
+            subscribed_at: Utc
+                .timestamp_opt(micros.div_euclid(1_000_000), nanos)
+                .single()?,
+            id: Uuid::try_parse(id).ok()?,
+        })
+    }
+
+    fn encode(&self) -> String {
+        format!(
+            "{}_{}",
+            self.subscribed_at.timestamp_micros(),
+            self.id.simple()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cursor;
+    use chrono::{TimeZone, Utc};
+    use claims::{assert_none, assert_some_eq};
+    use uuid::Uuid;
+
+    #[test]
+    fn an_encoded_cursor_is_parsed_back() {
+        let cursor = Cursor {
+            subscribed_at: Utc.timestamp_opt(1_700_000_000, 123_456_000).unwrap(),
+            id: Uuid::new_v4(),
+        };
+        assert_some_eq!(Cursor::parse(&cursor.encode()), cursor);
+    }
+
+    #[test]
+    fn malformed_cursors_are_ignored() {
+        for cursor in [
+            "",
+            "abc",
+            "123",
+            "abc_def",
+            &format!("x_{}", Uuid::new_v4()),
+        ] {
+            assert_none!(Cursor::parse(cursor));
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// The list used when a request doesn't name one. It is created by the
+/// migration that introduced mailing lists and holds everyone who subscribed
+/// before then.
+pub const DEFAULT_LIST_SLUG: &str = "newsletter";
+
+pub struct MailingList {
+    pub list_id: Uuid,
+    pub slug: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    /// Archived lists keep their subscribers and issues, but no longer accept
+    /// new subscriptions or issues.
+    pub archived_at: Option<DateTime<Utc>>,
+}
+
+impl MailingList {
+    pub fn is_archived(&self) -> bool {
+        self.archived_at.is_some()
+    }
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_list(pool: &PgPool, list_id: Uuid) -> Result<Option<MailingList>, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        r#"
+        SELECT list_id, slug, name, created_at, archived_at
+        FROM lists
+        WHERE list_id = $1
+        "#,
+        list_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Look up a list that isn't archived by its id or its slug, falling back to
+/// the default list when `list` is `None`.
+#[tracing::instrument(skip(pool))]
+pub async fn find_active_list(
+    pool: &PgPool,
+    list: Option<&str>,
+) -> Result<Option<MailingList>, sqlx::Error> {
+    let list = list.unwrap_or(DEFAULT_LIST_SLUG);
+    sqlx::query_as!(
+        MailingList,
+        r#"
+        SELECT list_id, slug, name, created_at, archived_at
+        FROM lists
+        WHERE (list_id = $1 OR slug = $2) AND archived_at IS NULL
+        "#,
+        Uuid::try_parse(list).ok(),
+        list
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// All lists, oldest first, so the default list comes first.
+#[tracing::instrument(skip(pool))]
+pub async fn list_mailing_lists(pool: &PgPool) -> Result<Vec<MailingList>, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        r#"
+        SELECT list_id, slug, name, created_at, archived_at
+        FROM lists
+        ORDER BY created_at, slug
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns `false` if the slug is already taken.
+#[tracing::instrument(skip(pool))]
+pub async fn insert_list(
+    pool: &PgPool,
+    list_id: Uuid,
+    slug: &str,
+    name: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO lists (list_id, slug, name)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (slug) DO NOTHING
+        "#,
+        list_id,
+        slug,
+        name
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Returns `false` if there is no such list.
+#[tracing::instrument(skip(pool))]
+pub async fn rename_list(pool: &PgPool, list_id: Uuid, name: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE lists
+        SET name = $2
+        WHERE list_id = $1
+        "#,
+        list_id,
+        name
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Returns `false` if there is no such list or it is already archived.
+#[tracing::instrument(skip(pool))]
+pub async fn archive_list(pool: &PgPool, list_id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE lists
+        SET archived_at = now()
+        WHERE list_id = $1 AND archived_at IS NULL
+        "#,
+        list_id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
@@ -77,7 +77,7 @@ async fn try_login(
     db_pool: &PgPool,
     mut session: TypedSession,
 ) -> Result<(), LoginError> {
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
 
     let user_id = validate_credentials(credentials, db_pool).await?;
     session.regenerate();
@@ -1,15 +1,31 @@
 mod admin;
 mod health_check;
 mod home;
+mod lists;
 mod login;
+mod newsletter_deliveries;
 mod newsletters;
+mod segments;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_data;
+mod subscriptions_preferences;
+mod subscriptions_unsubscribe;
+mod tracking;
+mod webhooks;
 
 pub use admin::*;
 pub use health_check::*;
 pub use home::*;
+pub use lists::*;
 pub use login::*;
+pub use newsletter_deliveries::*;
 pub use newsletters::*;
+pub use segments::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_data::*;
+pub use subscriptions_preferences::*;
+pub use subscriptions_unsubscribe::*;
+pub use tracking::*;
+pub use webhooks::*;
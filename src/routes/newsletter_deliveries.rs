@@ -0,0 +1,228 @@
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    headers::{authorization::Basic, Authorization},
+    response::{IntoResponse, Response},
+    Json, TypedHeader,
+};
+use chrono::{DateTime, Utc};
+use hyper::StatusCode;
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState,
+    authentication::{authenticate_api_user, ApiAuthError},
+    session_state::TypedSession,
+};
+
+/// How the delivery of a newsletter issue is going.
+#[derive(Serialize)]
+pub struct DeliveryReport {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub status: String,
+    pub published_at: Option<DateTime<Utc>>,
+    pub last_sent_at: Option<DateTime<Utc>>,
+    pub counts: DeliveryCounts,
+    /// Recipients whose delivery failed for good or bounced.
+    pub failures: Vec<FailedDelivery>,
+    /// Opens and clicks recorded while tracking is enabled.
+    pub engagement: Engagement,
+}
+
+#[derive(Serialize)]
+pub struct DeliveryCounts {
+    /// Waiting to be sent, including deliveries waiting for a retry.
+    pub queued: i64,
+    pub sent: i64,
+    pub failed: i64,
+    pub bounced: i64,
+}
+
+#[derive(Serialize)]
+pub struct Engagement {
+    pub opens: i64,
+    /// Subscribers who opened the issue at least once.
+    pub unique_opens: i64,
+    pub clicks: i64,
+    /// Subscribers who clicked at least one link.
+    pub unique_clicks: i64,
+    /// Clicks per link, most clicked first.
+    pub links: Vec<LinkClicks>,
+}
+
+#[derive(Serialize)]
+pub struct LinkClicks {
+    pub url: String,
+    pub clicks: i64,
+}
+
+#[derive(Serialize)]
+pub struct FailedDelivery {
+    pub subscriber_email: String,
+    /// Either `failed` or `bounced`.
+    pub status: String,
+    pub n_attempts: i16,
+    pub error: Option<String>,
+    /// When the delivery was last sent or failed to send.
+    pub last_attempt_at: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(name = "Get the delivery report of an issue", skip(pool))]
+pub async fn get_delivery_report(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Option<DeliveryReport>, anyhow::Error> {
+    let Some(issue) = sqlx::query!(
+        r#"
+        SELECT title, status, published_at
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the newsletter issue")?
+    else {
+        return Ok(None);
+    };
+
+    let summary = sqlx::query!(
+        r#"
+        SELECT
+            count(*) FILTER (WHERE status = 'pending') AS "queued!",
+            count(*) FILTER (WHERE status = 'sent') AS "sent!",
+            count(*) FILTER (WHERE status = 'dead_letter') AS "failed!",
+            count(*) FILTER (WHERE status = 'bounced') AS "bounced!",
+            max(sent_at) AS last_sent_at
+        FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to count the deliveries of the issue")?;
+
+    let failures = sqlx::query_as!(
+        FailedDelivery,
+        r#"
+        SELECT
+            subscriber_email,
+            CASE status WHEN 'dead_letter' THEN 'failed' ELSE status END AS "status!",
+            n_attempts,
+            last_error AS error,
+            last_attempted_at AS last_attempt_at
+        FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1 AND status IN ('dead_letter', 'bounced')
+        ORDER BY subscriber_email
+        "#,
+        issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the failed deliveries of the issue")?;
+
+    let engagement = sqlx::query!(
+        r#"
+        SELECT
+            count(*) FILTER (WHERE kind = 'open') AS "opens!",
+            count(DISTINCT subscriber_id) FILTER (WHERE kind = 'open') AS "unique_opens!",
+            count(*) FILTER (WHERE kind = 'click') AS "clicks!",
+            count(DISTINCT subscriber_id) FILTER (WHERE kind = 'click') AS "unique_clicks!"
+        FROM tracking_events
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to count the opens and clicks of the issue")?;
+
+    let links = sqlx::query_as!(
+        LinkClicks,
+        r#"
+        SELECT url AS "url!", count(*) AS "clicks!"
+        FROM tracking_events
+        WHERE newsletter_issue_id = $1 AND kind = 'click'
+        GROUP BY url
+        ORDER BY count(*) DESC, url
+        "#,
+        issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to count the clicks per link of the issue")?;
+
+    Ok(Some(DeliveryReport {
+        newsletter_issue_id: issue_id,
+        title: issue.title,
+        status: issue.status,
+        published_at: issue.published_at,
+        last_sent_at: summary.last_sent_at,
+        counts: DeliveryCounts {
+            queued: summary.queued,
+            sent: summary.sent,
+            failed: summary.failed,
+            bounced: summary.bounced,
+        },
+        failures,
+        engagement: Engagement {
+            opens: engagement.opens,
+            unique_opens: engagement.unique_opens,
+            clicks: engagement.clicks,
+            unique_clicks: engagement.unique_clicks,
+            links,
+        },
+    }))
+}
+
+/// This is the error type returned by the `newsletter_delivery_report` handler.
+#[derive(Debug, thiserror::Error)]
+pub enum DeliveryReportError {
+    #[error(transparent)]
+    Auth(#[from] ApiAuthError),
+    #[error("There is no such newsletter issue")]
+    NotFound,
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+impl IntoResponse for DeliveryReportError {
+    fn into_response(self) -> Response {
+        let status_code = match self {
+            Self::Auth(e) => return e.into_response(),
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Unexpected(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        (status_code, self.to_string()).into_response()
+    }
+}
+
+/// Return the delivery report of an issue as JSON, for monitoring.
+///
+/// Like `publish_newsletter`, this accepts an admin session or HTTP Basic
+/// credentials.
+#[tracing::instrument(
+    name = "Report on the delivery of a newsletter issue",
+    skip(state, session, authorization),
+    fields(username=tracing::field::Empty)
+)]
+pub async fn newsletter_delivery_report(
+    State(state): State<AppState>,
+    session: TypedSession,
+    authorization: Option<TypedHeader<Authorization<Basic>>>,
+    Path(issue_id): Path<Uuid>,
+) -> Result<Json<DeliveryReport>, DeliveryReportError> {
+    authenticate_api_user(&state.db_pool, &session, authorization).await?;
+
+    let report = get_delivery_report(&state.db_pool, issue_id)
+        .await?
+        .ok_or(DeliveryReportError::NotFound)?;
+
+    Ok(Json(report))
+}
@@ -1,24 +1,55 @@
 use anyhow::Context;
-use axum::{extract::State, response::IntoResponse, Json};
+use axum::{
+    extract::State,
+    headers::{authorization::Basic, Authorization},
+    response::{IntoResponse, Response},
+    Json, TypedHeader,
+};
+use chrono::{DateTime, Utc};
 use hyper::StatusCode;
 use serde::Deserialize;
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
 use tracing::warn;
+use uuid::Uuid;
 
-use crate::{app_state::AppState, domain::SubscriberEmail};
+use crate::{
+    app_state::AppState,
+    authentication::{authenticate_api_user, ApiAuthError},
+    domain::SubscriberEmail,
+    email_client::html_to_text,
+    idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    personalization::validate_placeholders,
+    routes::{find_active_list, find_segment, get_segment},
+    segments::recipients_query,
+    session_state::TypedSession,
+};
 
 /// This is the body of the request to the `publish_newsletter` handler.
 #[derive(Deserialize)]
 pub struct BodyData {
     title: String,
-    content: String,
+    #[serde(alias = "content")]
+    html_content: String,
+    /// Generated from `html_content` when missing.
+    text_content: Option<String>,
+    /// Deliver the issue at this time instead of right away.
+    send_at: Option<DateTime<Utc>>,
+    /// The id or slug of the list to send the issue to. Defaults to the main
+    /// newsletter.
+    list: Option<String>,
+    /// The id or name of a segment of the list to send the issue to instead
+    /// of the whole list.
+    segment: Option<String>,
+    /// Retrying a request with the same key returns the original response
+    /// instead of publishing the issue again.
+    idempotency_key: String,
 }
 
 /// This is the error type returned by the `publish_newsletter` handler.
 #[derive(Debug, thiserror::Error)]
 pub enum PublishError {
-    #[error("Authentication failed")]
-    Auth(#[source] anyhow::Error),
+    #[error(transparent)]
+    Auth(#[from] ApiAuthError),
     #[error("{0}")]
     Validation(String),
     #[error(transparent)]
@@ -28,7 +59,7 @@ pub enum PublishError {
 impl IntoResponse for PublishError {
     fn into_response(self) -> axum::response::Response {
         let status_code = match self {
-            Self::Auth(_) => StatusCode::UNAUTHORIZED,
+            Self::Auth(e) => return e.into_response(),
             Self::Validation(_) => StatusCode::BAD_REQUEST,
             Self::Unexpected(_) => StatusCode::INTERNAL_SERVER_ERROR,
         };
@@ -37,44 +68,466 @@ impl IntoResponse for PublishError {
     }
 }
 
-/// This handler publishes a newsletter to all confirmed subscribers.
+/// This handler publishes a newsletter to all confirmed subscribers of a list,
+/// or only to those in one of its segments.
+///
+/// The issue is only queued for delivery here. Emails are sent in the
+/// background by the issue delivery worker. Issues with a `send_at` time are
+/// stored as scheduled and queued by the scheduler once that time has come.
+///
+/// The content may use the placeholders listed in
+/// [`crate::personalization::PLACEHOLDERS`], e.g. `{{ name }}`, which are
+/// filled in for each subscriber. Content with any other placeholder is
+/// rejected.
+///
+/// The caller must either be logged in as an admin or provide valid
+/// credentials using HTTP Basic authentication.
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(state, session, authorization, body),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
 pub async fn publish_newsletter(
     State(state): State<AppState>,
-    body: Json<BodyData>,
-) -> Result<StatusCode, PublishError> {
-    let subscribers = get_confirmed_subscribers(&state.db_pool).await?;
-    for subscriber in subscribers {
-        state
-            .email_client
-            .send_email(&subscriber.email, &body.title, &body.content)
+    session: TypedSession,
+    authorization: Option<TypedHeader<Authorization<Basic>>>,
+    Json(body): Json<BodyData>,
+) -> Result<Response, PublishError> {
+    let user_id = authenticate_api_user(&state.db_pool, &session, authorization).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let idempotency_key: IdempotencyKey = body
+        .idempotency_key
+        .try_into()
+        .map_err(PublishError::Validation)?;
+    validate_placeholders(&body.html_content).map_err(PublishError::Validation)?;
+    if let Some(text_content) = &body.text_content {
+        validate_placeholders(text_content).map_err(PublishError::Validation)?;
+    }
+    let mut transaction = match try_processing(&state.db_pool, &idempotency_key, user_id).await? {
+        NextAction::StartProcessing(transaction) => transaction,
+        NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+    };
+
+    // These checks depend on the time and on the state of the lists, so they
+    // only apply to the first attempt: a retry gets the saved response even
+    // once `send_at` has passed or the list has been archived. Failing here
+    // rolls back the transaction, which frees up the idempotency key.
+    if body.send_at.is_some_and(|send_at| send_at <= Utc::now()) {
+        return Err(PublishError::Validation(
+            "send_at must be in the future".into(),
+        ));
+    }
+    let list = find_active_list(&state.db_pool, body.list.as_deref())
+        .await
+        .context("Failed to look up the mailing list")?
+        .ok_or_else(|| PublishError::Validation("There is no such mailing list".into()))?;
+    let segment_id = match &body.segment {
+        Some(segment) => {
+            let segment = find_segment(&state.db_pool, list.list_id, segment)
+                .await
+                .context("Failed to look up the segment")?
+                .ok_or_else(|| PublishError::Validation("The list has no such segment".into()))?;
+            Some(segment.segment_id)
+        }
+        None => None,
+    };
+
+    let text_content = body
+        .text_content
+        .unwrap_or_else(|| html_to_text(&body.html_content));
+    match body.send_at {
+        Some(send_at) => {
+            let issue_id = insert_draft_issue(
+                &mut transaction,
+                list.list_id,
+                segment_id,
+                &body.title,
+                &body.html_content,
+                &text_content,
+            )
             .await
-            .with_context(|| format!("Failed to send newsletter issues to {}", subscriber.email))?;
+            .context("Failed to store newsletter issue details")?;
+            schedule_issue(&mut transaction, issue_id, send_at)
+                .await
+                .context("Failed to schedule the newsletter issue")?;
+        }
+        None => {
+            queue_newsletter_issue(
+                &state.db_pool,
+                &mut transaction,
+                list.list_id,
+                segment_id,
+                &body.title,
+                &body.html_content,
+                &text_content,
+            )
+            .await?;
+        }
     }
 
-    Ok(StatusCode::OK)
+    let response = StatusCode::ACCEPTED.into_response();
+    let response = save_response(transaction, &idempotency_key, user_id, response).await?;
+
+    Ok(response)
 }
 
-pub struct ConfirmedSubscriber {
-    email: SubscriberEmail,
+/// Store a new issue and queue it for delivery to every confirmed subscriber
+/// of the list, or of the segment if there is one.
+pub async fn queue_newsletter_issue(
+    pool: &PgPool,
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    segment_id: Option<Uuid>,
+    title: &str,
+    html_content: &str,
+    text_content: &str,
+) -> Result<Uuid, anyhow::Error> {
+    let issue_id = insert_newsletter_issue(
+        transaction,
+        list_id,
+        segment_id,
+        title,
+        html_content,
+        text_content,
+    )
+    .await
+    .context("Failed to store newsletter issue details")?;
+    let subscribers = get_confirmed_subscribers(pool, list_id, segment_id).await?;
+    enqueue_delivery_tasks(transaction, issue_id, &subscribers)
+        .await
+        .context("Failed to enqueue delivery tasks")?;
+
+    Ok(issue_id)
 }
 
-#[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
-pub async fn get_confirmed_subscribers(
+#[tracing::instrument(skip_all)]
+async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    segment_id: Option<Uuid>,
+    title: &str,
+    html_content: &str,
+    text_content: &str,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            list_id,
+            segment_id,
+            title,
+            html_content,
+            text_content,
+            status,
+            published_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, 'sent', now())
+        "#,
+        newsletter_issue_id,
+        list_id,
+        segment_id,
+        title,
+        html_content,
+        text_content
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(newsletter_issue_id)
+}
+
+/// Publish an existing draft or scheduled issue and queue it for delivery.
+///
+/// Returns `false` without queueing anything if the issue has already been
+/// published.
+pub async fn publish_issue(
     pool: &PgPool,
-) -> Result<Vec<ConfirmedSubscriber>, anyhow::Error> {
-    let rows = sqlx::query!(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let published = sqlx::query!(
         r#"
-        SELECT email
-        FROM subscriptions
-        WHERE status = 'confirmed'
+        UPDATE newsletter_issues
+        SET status = 'sent', published_at = now()
+        WHERE newsletter_issue_id = $1 AND status IN ('draft', 'scheduled')
+        RETURNING list_id, segment_id
+        "#,
+        issue_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to mark the issue as sent")?;
+    let Some(published) = published else {
+        return Ok(false);
+    };
+
+    let subscribers =
+        get_confirmed_subscribers(pool, published.list_id, published.segment_id).await?;
+    enqueue_delivery_tasks(transaction, issue_id, &subscribers)
+        .await
+        .context("Failed to enqueue delivery tasks")?;
+
+    Ok(true)
+}
+
+/// Schedule a draft for delivery at `send_at`, or move an already scheduled
+/// issue to a new time. Returns `false` if the issue has already been sent.
+#[tracing::instrument(skip(transaction))]
+pub async fn schedule_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    send_at: DateTime<Utc>,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'scheduled', send_at = $2
+        WHERE newsletter_issue_id = $1 AND status IN ('draft', 'scheduled')
+        "#,
+        issue_id,
+        send_at
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Turn a scheduled issue back into a draft. Returns `false` if the issue
+/// wasn't scheduled.
+#[tracing::instrument(skip(pool))]
+pub async fn unschedule_issue(pool: &PgPool, issue_id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'draft', send_at = NULL
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
+        "#,
+        issue_id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn insert_draft_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    segment_id: Option<Uuid>,
+    title: &str,
+    html_content: &str,
+    text_content: &str,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            list_id,
+            segment_id,
+            title,
+            html_content,
+            text_content,
+            status
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, 'draft')
+        "#,
+        newsletter_issue_id,
+        list_id,
+        segment_id,
+        title,
+        html_content,
+        text_content
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(newsletter_issue_id)
+}
+
+/// Overwrite the content of a draft. Returns `false` if the issue is not a
+/// draft anymore.
+#[tracing::instrument(skip_all, fields(issue_id=%issue_id))]
+pub async fn update_draft_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    list_id: Uuid,
+    segment_id: Option<Uuid>,
+    title: &str,
+    html_content: &str,
+    text_content: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET list_id = $2, segment_id = $3, title = $4, html_content = $5, text_content = $6
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        "#,
+        issue_id,
+        list_id,
+        segment_id,
+        title,
+        html_content,
+        text_content
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub struct NewsletterIssue {
+    pub newsletter_issue_id: Uuid,
+    pub list_id: Uuid,
+    pub list_name: String,
+    /// Issues without a segment go to the whole list.
+    pub segment_id: Option<Uuid>,
+    pub segment_name: Option<String>,
+    pub title: String,
+    pub html_content: String,
+    pub text_content: String,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    pub send_at: Option<DateTime<Utc>>,
+    pub published_at: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_newsletter_issue(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Option<NewsletterIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT
+            newsletter_issue_id,
+            newsletter_issues.list_id,
+            lists.name AS list_name,
+            newsletter_issues.segment_id,
+            segments.name AS "segment_name?",
+            title,
+            html_content,
+            text_content,
+            status,
+            newsletter_issues.created_at,
+            send_at,
+            published_at
+        FROM newsletter_issues
+        JOIN lists ON lists.list_id = newsletter_issues.list_id
+        LEFT JOIN segments ON segments.segment_id = newsletter_issues.segment_id
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// All issues, most recent first.
+#[tracing::instrument(skip(pool))]
+pub async fn list_newsletter_issues(pool: &PgPool) -> Result<Vec<NewsletterIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT
+            newsletter_issue_id,
+            newsletter_issues.list_id,
+            lists.name AS list_name,
+            newsletter_issues.segment_id,
+            segments.name AS "segment_name?",
+            title,
+            html_content,
+            text_content,
+            status,
+            newsletter_issues.created_at,
+            send_at,
+            published_at
+        FROM newsletter_issues
+        JOIN lists ON lists.list_id = newsletter_issues.list_id
+        LEFT JOIN segments ON segments.segment_id = newsletter_issues.segment_id
+        ORDER BY newsletter_issues.created_at DESC
         "#,
     )
     .fetch_all(pool)
+    .await
+}
+
+#[tracing::instrument(skip_all)]
+async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    subscribers: &[ConfirmedSubscriber],
+) -> Result<(), sqlx::Error> {
+    let emails: Vec<String> = subscribers
+        .iter()
+        .map(|s| s.email.as_ref().to_owned())
+        .collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+        SELECT $1, email FROM UNNEST($2::text[]) AS email
+        "#,
+        newsletter_issue_id,
+        &emails
+    )
+    .execute(&mut **transaction)
     .await?;
 
-    let confirmed_subscribers = rows
+    Ok(())
+}
+
+pub struct ConfirmedSubscriber {
+    email: SubscriberEmail,
+}
+
+/// Leaves out the subscribers who asked to hear from us at most once a week
+/// or a month and already have an issue waiting to be sent, or were sent one
+/// in that time.
+///
+/// Those subscribers get the first issue published in their window and none
+/// of the others: issues are not held back for a digest, so the ones they
+/// skip are only available on the website.
+const DUE_FOR_DELIVERY: &str = " AND NOT EXISTS (SELECT 1 FROM issue_delivery_queue \
+    WHERE issue_delivery_queue.subscriber_email = subscriptions.email \
+    AND (issue_delivery_queue.status = 'pending' \
+        OR issue_delivery_queue.sent_at > now() - CASE subscriptions.frequency \
+            WHEN 'weekly' THEN interval '7 days' \
+            WHEN 'monthly' THEN interval '1 month' \
+        END))";
+
+/// The subscribers who confirmed their subscription to the list and whose
+/// address can still be mailed, limited to the members of the segment if
+/// there is one and to those due for an issue given their delivery frequency.
+#[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
+pub async fn get_confirmed_subscribers(
+    pool: &PgPool,
+    list_id: Uuid,
+    segment_id: Option<Uuid>,
+) -> Result<Vec<ConfirmedSubscriber>, anyhow::Error> {
+    let segment = match segment_id {
+        Some(segment_id) => Some(
+            get_segment(pool, segment_id)
+                .await
+                .context("Failed to retrieve the segment")?
+                .context("The segment of the issue is missing")?,
+        ),
+        None => None,
+    };
+    let emails: Vec<String> =
+        recipients_query("email", list_id, segment.as_ref().map(|s| &s.definition))
+            .push(DUE_FOR_DELIVERY)
+            .build_query_scalar()
+            .fetch_all(pool)
+            .await?;
+
+    let confirmed_subscribers = emails
         .into_iter()
-        .filter_map(|r| match SubscriberEmail::parse(r.email) {
+        .filter_map(|email| match SubscriberEmail::parse(email) {
             Ok(email) => Some(ConfirmedSubscriber { email }),
             Err(error) => {
                 warn!(
@@ -0,0 +1,447 @@
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    headers::{authorization::Basic, Authorization},
+    response::{IntoResponse, Response},
+    Json, TypedHeader,
+};
+use chrono::{DateTime, Utc};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Json as SqlJson, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState,
+    authentication::{authenticate_api_user, ApiAuthError},
+    domain::SubscriberTag,
+    routes::{find_active_list, MailingList},
+    segments::{recipients_query, SegmentDefinition},
+    session_state::TypedSession,
+};
+
+/// A named group of subscribers of a list that issues can be sent to.
+#[derive(Serialize)]
+pub struct Segment {
+    pub segment_id: Uuid,
+    pub list_id: Uuid,
+    pub name: String,
+    pub definition: SegmentDefinition,
+    pub created_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_segment(pool: &PgPool, segment_id: Uuid) -> Result<Option<Segment>, sqlx::Error> {
+    let segment = sqlx::query!(
+        r#"
+        SELECT
+            segment_id,
+            list_id,
+            name,
+            definition AS "definition: SqlJson<SegmentDefinition>",
+            created_at
+        FROM segments
+        WHERE segment_id = $1
+        "#,
+        segment_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .map(|r| Segment {
+        segment_id: r.segment_id,
+        list_id: r.list_id,
+        name: r.name,
+        definition: r.definition.0,
+        created_at: r.created_at,
+    });
+
+    Ok(segment)
+}
+
+/// Look up a segment of a list by its id or its name.
+#[tracing::instrument(skip(pool))]
+pub async fn find_segment(
+    pool: &PgPool,
+    list_id: Uuid,
+    segment: &str,
+) -> Result<Option<Segment>, sqlx::Error> {
+    let segment = sqlx::query!(
+        r#"
+        SELECT
+            segment_id,
+            list_id,
+            name,
+            definition AS "definition: SqlJson<SegmentDefinition>",
+            created_at
+        FROM segments
+        WHERE list_id = $1 AND (segment_id = $2 OR name = $3)
+        "#,
+        list_id,
+        Uuid::try_parse(segment).ok(),
+        segment
+    )
+    .fetch_optional(pool)
+    .await?
+    .map(|r| Segment {
+        segment_id: r.segment_id,
+        list_id: r.list_id,
+        name: r.name,
+        definition: r.definition.0,
+        created_at: r.created_at,
+    });
+
+    Ok(segment)
+}
+
+/// The segments of a list, or of all lists when `list_id` is `None`, by name.
+#[tracing::instrument(skip(pool))]
+pub async fn list_segments(
+    pool: &PgPool,
+    list_id: Option<Uuid>,
+) -> Result<Vec<Segment>, sqlx::Error> {
+    let segments = sqlx::query!(
+        r#"
+        SELECT
+            segment_id,
+            list_id,
+            name,
+            definition AS "definition: SqlJson<SegmentDefinition>",
+            created_at
+        FROM segments
+        WHERE $1::uuid IS NULL OR list_id = $1
+        ORDER BY name
+        "#,
+        list_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|r| Segment {
+        segment_id: r.segment_id,
+        list_id: r.list_id,
+        name: r.name,
+        definition: r.definition.0,
+        created_at: r.created_at,
+    })
+    .collect();
+
+    Ok(segments)
+}
+
+/// Returns `false` if the list already has a segment with this name.
+#[tracing::instrument(skip(pool))]
+pub async fn insert_segment(
+    pool: &PgPool,
+    segment_id: Uuid,
+    list_id: Uuid,
+    name: &str,
+    definition: &SegmentDefinition,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO segments (segment_id, list_id, name, definition)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (list_id, name) DO NOTHING
+        "#,
+        segment_id,
+        list_id,
+        name,
+        SqlJson(definition) as _
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// How many subscribers an issue sent to the list, or to a segment of it,
+/// would go to right now.
+#[tracing::instrument(skip(pool))]
+pub async fn count_recipients(
+    pool: &PgPool,
+    list_id: Uuid,
+    segment: Option<&SegmentDefinition>,
+) -> Result<i64, sqlx::Error> {
+    recipients_query("count(*)", list_id, segment)
+        .build_query_scalar()
+        .fetch_one(pool)
+        .await
+}
+
+/// Tag the subscribers of a list with the given emails. Addresses that are
+/// not on the list are ignored. Returns how many subscribers got the tag.
+#[tracing::instrument(skip(pool, emails))]
+pub async fn tag_subscribers(
+    pool: &PgPool,
+    list_id: Uuid,
+    tag: &SubscriberTag,
+    emails: &[String],
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO subscriber_tags (list_id, subscriber_id, tag)
+        SELECT list_subscriptions.list_id, list_subscriptions.subscriber_id, $2
+        FROM list_subscriptions
+        JOIN subscriptions ON subscriptions.id = list_subscriptions.subscriber_id
+        WHERE list_subscriptions.list_id = $1 AND subscriptions.email = ANY($3)
+        ON CONFLICT DO NOTHING
+        "#,
+        list_id,
+        tag.as_ref(),
+        emails
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Remove a tag from the subscribers of a list with the given emails. Returns
+/// how many subscribers lost the tag.
+#[tracing::instrument(skip(pool, emails))]
+pub async fn untag_subscribers(
+    pool: &PgPool,
+    list_id: Uuid,
+    tag: &SubscriberTag,
+    emails: &[String],
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM subscriber_tags
+        USING subscriptions
+        WHERE
+            subscriber_tags.subscriber_id = subscriptions.id AND
+            subscriber_tags.list_id = $1 AND
+            subscriber_tags.tag = $2 AND
+            subscriptions.email = ANY($3)
+        "#,
+        list_id,
+        tag.as_ref(),
+        emails
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// This is the error type returned by the segment and tag handlers.
+#[derive(Debug, thiserror::Error)]
+pub enum SegmentError {
+    #[error(transparent)]
+    Auth(#[from] ApiAuthError),
+    #[error("There is no such mailing list")]
+    NoSuchList,
+    #[error("{0}")]
+    Validation(String),
+    #[error("The list already has a segment with this name")]
+    DuplicateName,
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+impl IntoResponse for SegmentError {
+    fn into_response(self) -> Response {
+        let status_code = match self {
+            Self::Auth(e) => return e.into_response(),
+            Self::NoSuchList => StatusCode::NOT_FOUND,
+            Self::Validation(_) => StatusCode::BAD_REQUEST,
+            Self::DuplicateName => StatusCode::CONFLICT,
+            Self::Unexpected(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        (status_code, self.to_string()).into_response()
+    }
+}
+
+async fn find_list(pool: &PgPool, list: &str) -> Result<MailingList, SegmentError> {
+    find_active_list(pool, Some(list))
+        .await
+        .context("Failed to look up the mailing list")?
+        .ok_or(SegmentError::NoSuchList)
+}
+
+/// Check a segment name, returning it without surrounding whitespace.
+pub fn validate_segment_name(name: &str) -> Result<&str, &'static str> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("The segment needs a name");
+    }
+    if name.chars().count() > 256 {
+        return Err("The segment name must be at most 256 characters long");
+    }
+    Ok(name)
+}
+
+/// A segment along with how many subscribers it currently holds.
+#[derive(Serialize)]
+pub struct SegmentSummary {
+    #[serde(flatten)]
+    pub segment: Segment,
+    pub recipients: i64,
+}
+
+#[derive(Deserialize)]
+pub struct NewSegment {
+    name: String,
+    definition: SegmentDefinition,
+}
+
+/// Create a segment of a list, named by its id or slug.
+///
+/// Like `publish_newsletter`, this accepts an admin session or HTTP Basic
+/// credentials.
+#[tracing::instrument(
+    name = "Create a segment",
+    skip(state, session, authorization, body),
+    fields(username=tracing::field::Empty)
+)]
+pub async fn create_segment(
+    State(state): State<AppState>,
+    session: TypedSession,
+    authorization: Option<TypedHeader<Authorization<Basic>>>,
+    Path(list): Path<String>,
+    Json(body): Json<NewSegment>,
+) -> Result<(StatusCode, Json<SegmentSummary>), SegmentError> {
+    authenticate_api_user(&state.db_pool, &session, authorization).await?;
+    let list = find_list(&state.db_pool, &list).await?;
+    let name = validate_segment_name(&body.name).map_err(|e| SegmentError::Validation(e.into()))?;
+    body.definition
+        .validate()
+        .map_err(SegmentError::Validation)?;
+
+    let segment_id = Uuid::new_v4();
+    if !insert_segment(
+        &state.db_pool,
+        segment_id,
+        list.list_id,
+        name,
+        &body.definition,
+    )
+    .await
+    .context("Failed to store the segment")?
+    {
+        return Err(SegmentError::DuplicateName);
+    }
+    let segment = get_segment(&state.db_pool, segment_id)
+        .await
+        .context("Failed to retrieve the new segment")?
+        .context("The new segment is missing")?;
+    let recipients = count_recipients(&state.db_pool, list.list_id, Some(&segment.definition))
+        .await
+        .context("Failed to count the members of the segment")?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(SegmentSummary {
+            segment,
+            recipients,
+        }),
+    ))
+}
+
+/// List the segments of a list along with their current size.
+#[tracing::instrument(
+    name = "List the segments of a list",
+    skip(state, session, authorization),
+    fields(username=tracing::field::Empty)
+)]
+pub async fn list_segments_of_list(
+    State(state): State<AppState>,
+    session: TypedSession,
+    authorization: Option<TypedHeader<Authorization<Basic>>>,
+    Path(list): Path<String>,
+) -> Result<Json<Vec<SegmentSummary>>, SegmentError> {
+    authenticate_api_user(&state.db_pool, &session, authorization).await?;
+    let list = find_list(&state.db_pool, &list).await?;
+
+    let segments = list_segments(&state.db_pool, Some(list.list_id))
+        .await
+        .context("Failed to retrieve the segments of the list")?;
+    let mut summaries = Vec::with_capacity(segments.len());
+    for segment in segments {
+        let recipients = count_recipients(&state.db_pool, list.list_id, Some(&segment.definition))
+            .await
+            .context("Failed to count the members of a segment")?;
+        summaries.push(SegmentSummary {
+            segment,
+            recipients,
+        });
+    }
+
+    Ok(Json(summaries))
+}
+
+#[derive(Serialize)]
+pub struct SegmentPreview {
+    pub recipients: i64,
+}
+
+/// Count how many subscribers of a list a segment definition would pick,
+/// without storing it.
+#[tracing::instrument(
+    name = "Preview a segment",
+    skip(state, session, authorization, definition),
+    fields(username=tracing::field::Empty)
+)]
+pub async fn preview_segment(
+    State(state): State<AppState>,
+    session: TypedSession,
+    authorization: Option<TypedHeader<Authorization<Basic>>>,
+    Path(list): Path<String>,
+    Json(definition): Json<SegmentDefinition>,
+) -> Result<Json<SegmentPreview>, SegmentError> {
+    authenticate_api_user(&state.db_pool, &session, authorization).await?;
+    let list = find_list(&state.db_pool, &list).await?;
+    definition.validate().map_err(SegmentError::Validation)?;
+
+    let recipients = count_recipients(&state.db_pool, list.list_id, Some(&definition))
+        .await
+        .context("Failed to count the members of the segment")?;
+
+    Ok(Json(SegmentPreview { recipients }))
+}
+
+#[derive(Deserialize)]
+pub struct TagChanges {
+    tag: String,
+    /// The emails of the subscribers to tag.
+    #[serde(default)]
+    add: Vec<String>,
+    /// The emails of the subscribers to untag.
+    #[serde(default)]
+    remove: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct TaggedSubscribers {
+    pub added: u64,
+    pub removed: u64,
+}
+
+/// Add a tag to some subscribers of a list and remove it from others.
+#[tracing::instrument(
+    name = "Tag subscribers",
+    skip(state, session, authorization, body),
+    fields(username=tracing::field::Empty)
+)]
+pub async fn tag_list_subscribers(
+    State(state): State<AppState>,
+    session: TypedSession,
+    authorization: Option<TypedHeader<Authorization<Basic>>>,
+    Path(list): Path<String>,
+    Json(body): Json<TagChanges>,
+) -> Result<Json<TaggedSubscribers>, SegmentError> {
+    authenticate_api_user(&state.db_pool, &session, authorization).await?;
+    let list = find_list(&state.db_pool, &list).await?;
+    let tag = SubscriberTag::parse(body.tag).map_err(SegmentError::Validation)?;
+
+    let added = tag_subscribers(&state.db_pool, list.list_id, &tag, &body.add)
+        .await
+        .context("Failed to tag subscribers")?;
+    let removed = untag_subscribers(&state.db_pool, list.list_id, &tag, &body.remove)
+        .await
+        .context("Failed to untag subscribers")?;
+
+    Ok(Json(TaggedSubscribers { added, removed }))
+}
@@ -1,89 +1,317 @@
 use anyhow::Context;
+use askama::Template;
 use axum::{
-    extract::State,
-    response::{IntoResponse, Response},
-    Form,
+    async_trait,
+    body::HttpBody,
+    extract::{rejection::JsonRejection, FromRequest, State},
+    http::{header, HeaderMap, Request},
+    response::{Html, IntoResponse, Response},
+    BoxError, Form, Json,
 };
 use chrono::Utc;
 use hyper::StatusCode;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use sqlx::{Postgres, Transaction};
 use uuid::Uuid;
 
 use crate::{
+    app_error::AppError,
     app_state::AppState,
-    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
-    email_client::{self, DynEmailClient},
+    challenge::{Challenge, ChallengeError},
+    domain::{NewSubscriber, SubscribeFormToken, SubscriberEmail, SubscriberName},
+    email_client::{DynEmailClient, EmailMessage},
+    email_templates::ConfirmationEmail,
+    routes::find_active_list,
 };
 
 #[derive(Deserialize)]
 pub struct SubscriptionFormData {
     email: String,
     name: String,
+    /// The id or slug of the list to subscribe to. Defaults to the main
+    /// newsletter.
+    list: Option<String>,
+    #[serde(flatten)]
+    bot_check: BotCheck,
+}
+
+/// What the subscription and data request forms send along to tell people
+/// from bots.
+#[derive(Default, Deserialize)]
+pub(crate) struct BotCheck {
+    /// A honeypot: the field is hidden from people, so only bots fill it in.
+    #[serde(default)]
+    website: String,
+    /// The [`SubscribeFormToken`] the form was served with.
+    #[serde(default)]
+    form_token: String,
+    /// The `challenge` field of the [`Challenge`] the form was served with,
+    /// if it has one.
+    #[serde(default)]
+    challenge: String,
+    #[serde(default)]
+    challenge_solution: String,
+}
+
+/// The outcome of [`check_for_bots`].
+pub(crate) enum Verdict {
+    Human,
+    Bot,
 }
 
 impl TryFrom<SubscriptionFormData> for NewSubscriber {
-    type Error = String;
+    type Error = SubscribeError;
 
     fn try_from(value: SubscriptionFormData) -> Result<Self, Self::Error> {
         Ok(Self {
-            name: SubscriberName::parse(value.name)?,
-            email: SubscriberEmail::parse(value.email)?,
+            name: SubscriberName::parse(value.name)
+                .map_err(|e| SubscribeError::invalid("name", e))?,
+            email: SubscriberEmail::parse(value.email)
+                .map_err(|e| SubscribeError::invalid("email", e))?,
         })
     }
 }
 
+/// The format of the body of the request to, or the response of, the
+/// `subscribe` handler.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubscriptionFormat {
+    /// Form data in, plain text out, for the subscription form of the site.
+    Form,
+    /// JSON in and out, for the apps.
+    Json,
+}
+
+impl SubscriptionFormat {
+    /// Respond in the format the `Accept` header prefers, between JSON and
+    /// text. Without a preference, respond in the format of the request.
+    fn negotiate(headers: &HeaderMap, request_format: SubscriptionFormat) -> Self {
+        let mut json_quality = 0.0;
+        let mut text_quality = 0.0;
+        let accepted = headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+        for media_range in accepted {
+            let mut params = media_range.split(';').map(str::trim);
+            let media_type = params.next().unwrap_or_default().to_ascii_lowercase();
+            let quality = params
+                .find_map(|param| param.strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            let best = match media_type.as_str() {
+                "application/json" => &mut json_quality,
+                "text/*" | "text/plain" | "text/html" => &mut text_quality,
+                _ => continue,
+            };
+            *best = f32::max(*best, quality);
+        }
+
+        if json_quality > text_quality {
+            SubscriptionFormat::Json
+        } else if text_quality > json_quality {
+            SubscriptionFormat::Form
+        } else {
+            request_format
+        }
+    }
+}
+
+/// A subscription request, sent either as form data or as JSON depending on
+/// its `Content-Type`, along with the format to respond in.
+pub struct SubscriptionRequest {
+    data: SubscriptionFormData,
+    response_format: SubscriptionFormat,
+}
+
+#[async_trait]
+impl<S, B> FromRequest<S, B> for SubscriptionRequest
+where
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(request: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let is_json = request
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.split(';').next().unwrap_or_default().trim())
+            .is_some_and(|mime| {
+                let mime = mime.to_ascii_lowercase();
+                mime == "application/json"
+                    || (mime.starts_with("application/") && mime.ends_with("+json"))
+            });
+        let request_format = if is_json {
+            SubscriptionFormat::Json
+        } else {
+            SubscriptionFormat::Form
+        };
+        let response_format = SubscriptionFormat::negotiate(request.headers(), request_format);
+
+        let data = match request_format {
+            SubscriptionFormat::Json => Json::from_request(request, state)
+                .await
+                .map(|Json(data)| data)
+                .map_err(|rejection| rejection_response(rejection, response_format))?,
+            SubscriptionFormat::Form => Form::from_request(request, state)
+                .await
+                .map(|Form(data)| data)
+                .map_err(IntoResponse::into_response)?,
+        };
+
+        Ok(Self {
+            data,
+            response_format,
+        })
+    }
+}
+
+/// JSON bodies that can't be read are reported in the format of the response,
+/// like any other error.
+fn rejection_response(rejection: JsonRejection, format: SubscriptionFormat) -> Response {
+    match format {
+        SubscriptionFormat::Json => {
+            let body = ErrorBody {
+                field: None,
+                message: rejection.body_text(),
+            };
+            (rejection.status(), Json(body)).into_response()
+        }
+        SubscriptionFormat::Form => rejection.into_response(),
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum SubscribeError {
-    #[error("{0}")]
-    Validation(String),
+    #[error("{message}")]
+    Validation {
+        /// The field of the request that is invalid.
+        field: &'static str,
+        message: String,
+    },
     #[error(transparent)]
     Unexpected(#[from] anyhow::Error),
 }
 
-impl IntoResponse for SubscribeError {
-    fn into_response(self) -> Response {
+impl SubscribeError {
+    fn invalid(field: &'static str, message: impl Into<String>) -> Self {
+        Self::Validation {
+            field,
+            message: message.into(),
+        }
+    }
+
+    fn into_response(self, format: SubscriptionFormat) -> Response {
         let status_code = match self {
-            SubscribeError::Validation(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::Validation { .. } => StatusCode::BAD_REQUEST,
             SubscribeError::Unexpected(_) => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
-        (status_code, self.to_string()).into_response()
+        match format {
+            SubscriptionFormat::Form => (status_code, self.to_string()).into_response(),
+            SubscriptionFormat::Json => {
+                let body = ErrorBody {
+                    field: match self {
+                        SubscribeError::Validation { field, .. } => Some(field),
+                        SubscribeError::Unexpected(_) => None,
+                    },
+                    message: self.to_string(),
+                };
+                (status_code, Json(body)).into_response()
+            }
+        }
     }
 }
 
-impl From<String> for SubscribeError {
-    fn from(e: String) -> Self {
-        Self::Validation(e)
-    }
+/// The body of JSON error responses.
+#[derive(Serialize)]
+struct ErrorBody {
+    /// The field of the request the error is about, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field: Option<&'static str>,
+    message: String,
 }
 
+/// Subscribing to a list sends a confirmation link for that list, and each list
+/// is confirmed separately. Subscribing again with an address that is still
+/// waiting for confirmation sends a fresh confirmation link. Addresses that are
+/// already confirmed for the list get the same response without any email, so
+/// the endpoint can't be used to find out who is subscribed.
+///
+/// The request can be sent as form data or as JSON. The response is plain text
+/// or JSON depending on the `Accept` header, or else on the format of the
+/// request. JSON errors name the field they are about, if any, e.g.
+/// `{"field": "email", "message": "..."}`.
+///
+/// The request has to carry the form token and the solved challenge of a form
+/// served by [`subscribe_form`] or [`subscription_challenge`], see
+/// [`check_for_bots`].
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(state, form),
+    skip(state, request),
     fields(
-        subscriber_email = %form.email,
-        subscriber_name = %form.name
+        subscriber_email = %request.data.email,
+        subscriber_name = %request.data.name,
+        list = ?request.data.list,
+        format = ?request.response_format
     )
 )]
-pub async fn subscribe(
-    State(state): State<AppState>,
-    Form(form): Form<SubscriptionFormData>,
-) -> Result<StatusCode, SubscribeError> {
+pub async fn subscribe(State(state): State<AppState>, request: SubscriptionRequest) -> Response {
+    let format = request.response_format;
+    match add_subscriber(state, request.data).await {
+        Ok(()) => match format {
+            SubscriptionFormat::Form => StatusCode::OK.into_response(),
+            SubscriptionFormat::Json => Json(serde_json::json!({})).into_response(),
+        },
+        Err(e) => e.into_response(format),
+    }
+}
+
+async fn add_subscriber(
+    state: AppState,
+    mut form: SubscriptionFormData,
+) -> Result<(), SubscribeError> {
+    let list = find_active_list(&state.db_pool, form.list.take().as_deref())
+        .await
+        .context("Failed to look up the mailing list")?
+        .ok_or_else(|| SubscribeError::invalid("list", "There is no such mailing list"))?;
+    let bot_check = std::mem::take(&mut form.bot_check);
     let new_subscriber: NewSubscriber = form.try_into()?;
+    if let Verdict::Bot = check_for_bots(&state, &bot_check).await? {
+        // Bots get the same response as everyone else, so that they don't
+        // learn how they were caught.
+        return Ok(());
+    }
     let mut transaction = state
         .db_pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    let subscriber_id = upsert_subscriber(&mut transaction, &new_subscriber)
         .await
         .context("Failed to insert new subscriber in the database")?;
-    let subscription_token = generate_subscription_token();
-    store_token(&mut transaction, subscriber_id, &subscription_token)
+    let status = upsert_list_subscription(&mut transaction, list.list_id, subscriber_id)
         .await
-        .context("Failed to store the confirmation token for a new subscriber")?;
+        .context("Failed to subscribe the subscriber to the list")?;
+    if status == "confirmed" {
+        tracing::info!("The subscriber is already confirmed");
+        return Ok(());
+    }
+    let subscription_token = generate_subscription_token();
+    store_token(
+        &mut transaction,
+        subscriber_id,
+        list.list_id,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to store the confirmation token for a new subscriber")?;
     transaction
         .commit()
         .await
@@ -92,13 +320,126 @@ pub async fn subscribe(
     send_confirmation_email(
         state.email_client,
         new_subscriber,
+        &list.name,
         &state.base_url,
         &subscription_token,
     )
     .await
     .context("Failed to send a confirmation email")?;
 
-    Ok(StatusCode::OK)
+    Ok(())
+}
+
+/// Check the honeypot, then that the form took long enough to fill in, then the
+/// solution to the challenge. Forms caught by the honeypot are silently
+/// dropped, while the other checks fail with an error naming the field, since
+/// people can fail them too, e.g. by submitting an autofilled form right away.
+pub(crate) async fn check_for_bots(
+    state: &AppState,
+    bot_check: &BotCheck,
+) -> Result<Verdict, SubscribeError> {
+    if !bot_check.website.is_empty() {
+        tracing::warn!(
+            bot_check = "honeypot",
+            honeypot = %bot_check.website,
+            "Rejected a form from a suspected bot"
+        );
+        return Ok(Verdict::Bot);
+    }
+
+    let timing = &state.subscribe_form_timing;
+    let issued_at = match SubscribeFormToken::parse(&bot_check.form_token, &state.hmac_secret.0) {
+        Ok(issued_at) => issued_at,
+        Err(e) => {
+            tracing::warn!(
+                bot_check = "form_token",
+                error.message = %e,
+                "Rejected a form from a suspected bot"
+            );
+            return Err(SubscribeError::invalid(
+                "form_token",
+                "The form token is missing or invalid",
+            ));
+        }
+    };
+    let time_to_submit = Utc::now() - issued_at;
+    let too_fast = time_to_submit < timing.min_time_to_submit;
+    if too_fast || time_to_submit > timing.max_age {
+        tracing::warn!(
+            bot_check = "form_token",
+            time_to_submit_seconds = time_to_submit.num_seconds(),
+            "Rejected a form from a suspected bot"
+        );
+        let message = if too_fast {
+            "The form was submitted too quickly, please try again"
+        } else {
+            "The form has expired, please reload the page"
+        };
+        return Err(SubscribeError::invalid("form_token", message));
+    }
+
+    match state
+        .challenge_verifier
+        .verify(&bot_check.challenge, &bot_check.challenge_solution)
+        .await
+    {
+        Ok(()) => Ok(Verdict::Human),
+        Err(ChallengeError::Failed(e)) => {
+            tracing::warn!(
+                bot_check = "challenge",
+                error.message = %e,
+                "Rejected a form from a suspected bot"
+            );
+            Err(SubscribeError::invalid("challenge", e))
+        }
+        Err(ChallengeError::Unexpected(e)) => {
+            Err(e.context("Failed to verify the challenge").into())
+        }
+    }
+}
+
+/// A fresh form token and challenge, as embedded in the subscription form.
+#[derive(Serialize)]
+pub struct SubscriptionChallenge {
+    pub(crate) form_token: String,
+    pub(crate) challenge: Challenge,
+}
+
+impl SubscriptionChallenge {
+    pub(crate) fn issue(state: &AppState) -> Self {
+        Self {
+            form_token: SubscribeFormToken::generate(Utc::now(), &state.hmac_secret.0)
+                .as_ref()
+                .to_owned(),
+            challenge: state.challenge_verifier.issue(),
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "subscribe.html")]
+struct SubscribeTemplate {
+    form_token: String,
+    challenge: Challenge,
+}
+
+/// The subscription form, with its form token and challenge.
+pub async fn subscribe_form(State(state): State<AppState>) -> Result<Html<String>, AppError> {
+    let SubscriptionChallenge {
+        form_token,
+        challenge,
+    } = SubscriptionChallenge::issue(&state);
+    let template = SubscribeTemplate {
+        form_token,
+        challenge,
+    };
+    Ok(Html(template.render()?))
+}
+
+/// A form token and challenge for apps that show their own subscription form,
+/// to be sent back along with the subscription.
+pub async fn subscription_challenge(State(state): State<AppState>) -> Json<SubscriptionChallenge> {
+    Json(SubscriptionChallenge::issue(&state))
 }
 
 #[tracing::instrument(
@@ -108,50 +449,90 @@ pub async fn subscribe(
 pub async fn send_confirmation_email(
     email_client: DynEmailClient,
     new_subscriber: NewSubscriber,
+    list_name: &str,
     base_url: &str,
     confirmation_token: &str,
-) -> Result<(), email_client::SendEmailError> {
+) -> Result<(), anyhow::Error> {
+    let message = confirmation_email(list_name, base_url, confirmation_token)?;
+    email_client
+        .send_email(&new_subscriber.email, &message)
+        .await?;
+
+    Ok(())
+}
+
+/// The email with the link to confirm a subscription to `list_name`.
+pub fn confirmation_email(
+    list_name: &str,
+    base_url: &str,
+    confirmation_token: &str,
+) -> Result<EmailMessage, anyhow::Error> {
     let confirmation_link =
         format!("{base_url}/subscriptions/confirm?subscription_token={confirmation_token}");
-
-    email_client
-        .send_email(
-            &new_subscriber.email,
-            "Welcome!",
-            &format!(
-                r#"
-                <h1>Welcome to our newsletter!</h1>
-                Click <a href="{}">here</a> to confirm your subscription.""
-                "#,
-                confirmation_link
-            ),
-        )
-        .await
+    ConfirmationEmail {
+        list_name,
+        confirmation_link: &confirmation_link,
+    }
+    .render()
+    .context("Failed to render the confirmation email")
 }
 
+/// Insert a new subscriber, or return the id of the existing subscriber with
+/// the same email address.
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
     skip(transaction, new_subscriber)
 )]
-pub async fn insert_subscriber(
+pub async fn upsert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
 ) -> Result<Uuid, sqlx::Error> {
-    let subscriber_id = Uuid::new_v4();
-    sqlx::query!(
+    let row = sqlx::query!(
         r#"
         INSERT INTO subscriptions (id, email, name, subscribed_at, status)
         VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        ON CONFLICT (email) DO UPDATE
+        SET email = EXCLUDED.email
+        RETURNING id
         "#,
-        subscriber_id,
+        Uuid::new_v4(),
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
         Utc::now()
     )
-    .execute(&mut **transaction)
+    .fetch_one(&mut **transaction)
     .await?;
 
-    Ok(subscriber_id)
+    Ok(row.id)
+}
+
+/// Subscribe to a list, or move a subscription that was never confirmed (or
+/// unsubscribed since) back to `pending_confirmation`. Confirmed subscriptions
+/// are left untouched. Returns the status of the subscription.
+#[tracing::instrument(skip(transaction))]
+pub async fn upsert_list_subscription(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<String, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (list_id, subscriber_id, status)
+        VALUES ($1, $2, 'pending_confirmation')
+        ON CONFLICT (list_id, subscriber_id) DO UPDATE
+        SET status = CASE
+            WHEN list_subscriptions.status = 'confirmed' THEN 'confirmed'
+            ELSE 'pending_confirmation'
+        END
+        RETURNING status
+        "#,
+        list_id,
+        subscriber_id
+    )
+    .fetch_one(&mut **transaction)
+    .await?;
+
+    Ok(row.status)
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -165,15 +546,17 @@ pub struct StoreTokenError(sqlx::Error);
 pub async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
+    list_id: Uuid,
     subscription_token: &str,
 ) -> Result<(), StoreTokenError> {
     sqlx::query!(
         r#"
-        INSERT INTO subscription_tokens (subscription_token, subscriber_id)
-        VALUES ($1, $2)
+        INSERT INTO subscription_tokens (subscription_token, subscriber_id, list_id)
+        VALUES ($1, $2, $3)
         "#,
         subscription_token,
-        subscriber_id
+        subscriber_id,
+        list_id
     )
     .execute(&mut **transaction)
     .await
@@ -182,9 +565,57 @@ pub async fn store_token(
     Ok(())
 }
 
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     (0..25)
         .map(|_| char::from(rng.sample(Alphanumeric)))
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header, HeaderMap, HeaderValue};
+
+    use super::SubscriptionFormat;
+
+    fn accepting(accept: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_static(accept));
+        headers
+    }
+
+    #[test]
+    fn without_a_preference_the_response_has_the_format_of_the_request() {
+        for headers in [HeaderMap::new(), accepting("*/*")] {
+            for format in [SubscriptionFormat::Form, SubscriptionFormat::Json] {
+                assert_eq!(SubscriptionFormat::negotiate(&headers, format), format);
+            }
+        }
+    }
+
+    #[test]
+    fn the_accept_header_wins_over_the_format_of_the_request() {
+        assert_eq!(
+            SubscriptionFormat::negotiate(&accepting("application/json"), SubscriptionFormat::Form),
+            SubscriptionFormat::Json
+        );
+        assert_eq!(
+            SubscriptionFormat::negotiate(&accepting("text/html"), SubscriptionFormat::Json),
+            SubscriptionFormat::Form
+        );
+    }
+
+    #[test]
+    fn quality_values_are_taken_into_account() {
+        let headers = accepting("text/html;q=0.8, application/json, */*;q=0.1");
+        assert_eq!(
+            SubscriptionFormat::negotiate(&headers, SubscriptionFormat::Form),
+            SubscriptionFormat::Json
+        );
+        let headers = accepting("application/json; q=0.5, text/*");
+        assert_eq!(
+            SubscriptionFormat::negotiate(&headers, SubscriptionFormat::Json),
+            SubscriptionFormat::Form
+        );
+    }
+}
@@ -1,4 +1,9 @@
-use axum::extract::{Query, State};
+use askama::Template;
+use axum::{
+    extract::{Query, State},
+    response::{Html, IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
 use hyper::StatusCode;
 use serde::Deserialize;
 use sqlx::PgPool;
@@ -11,51 +16,93 @@ pub struct SubscriptionsConfirmParams {
     subscription_token: String,
 }
 
+#[derive(Template)]
+#[template(path = "subscriptions_confirm.html")]
+struct ConfirmTemplate {
+    expired: bool,
+}
+
 #[tracing::instrument(name = "Confirm a pending subscriber", skip(state, params))]
 pub async fn confirm(
     State(state): State<AppState>,
     Query(params): Query<SubscriptionsConfirmParams>,
-) -> Result<StatusCode, AppError> {
-    let subscriber_id =
-        get_subscriber_id_from_token(&state.db_pool, &params.subscription_token).await?;
+) -> Result<Response, AppError> {
+    let token = get_subscription_token(&state.db_pool, &params.subscription_token).await?;
 
-    let subscriber_id = match subscriber_id {
-        Some(id) => id,
-        None => return Ok(StatusCode::UNAUTHORIZED),
+    let token = match token {
+        Some(token) => token,
+        None => return Ok(StatusCode::UNAUTHORIZED.into_response()),
     };
 
-    confirm_subscriber(&state.db_pool, subscriber_id).await?;
+    if token.created_at + state.subscription_token_ttl < Utc::now() {
+        tracing::info!("Rejected an expired subscription token");
+        let page = ConfirmTemplate { expired: true }.render()?;
+        return Ok((StatusCode::GONE, Html(page)).into_response());
+    }
 
-    Ok(StatusCode::OK)
+    confirm_subscriber(&state.db_pool, token.subscriber_id, token.list_id).await?;
+
+    let page = ConfirmTemplate { expired: false }.render()?;
+    Ok(Html(page).into_response())
 }
 
+/// Confirm the subscription to the list, which also proves that the address
+/// is valid.
 #[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+pub async fn confirm_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
     sqlx::query!(
         r#"
-        UPDATE subscriptions set status = 'confirmed'
+        UPDATE subscriptions
+        SET status = 'confirmed', confirmed_at = COALESCE(confirmed_at, now())
         WHERE id = $1
         "#,
         subscriber_id,
     )
-    .execute(pool)
+    .execute(&mut *transaction)
     .await
     .map_err(|e| {
         tracing::error!("Failed to update subscription status: {:?}", e);
         e
     })?;
+    sqlx::query!(
+        r#"
+        UPDATE list_subscriptions set status = 'confirmed'
+        WHERE subscriber_id = $1 AND list_id = $2
+        "#,
+        subscriber_id,
+        list_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to update list subscription status: {:?}", e);
+        e
+    })?;
+    transaction.commit().await?;
 
     Ok(())
 }
 
-#[tracing::instrument(name = "Get subscriber_id from token", skip(subscription_token, pool))]
-pub async fn get_subscriber_id_from_token(
+pub struct SubscriptionToken {
+    pub subscriber_id: Uuid,
+    pub list_id: Uuid,
+    pub created_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Get subscription token", skip(subscription_token, pool))]
+pub async fn get_subscription_token(
     pool: &PgPool,
     subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
+) -> Result<Option<SubscriptionToken>, sqlx::Error> {
+    let result = sqlx::query_as!(
+        SubscriptionToken,
         r#"
-        SELECT subscriber_id FROM subscription_tokens
+        SELECT subscriber_id, list_id, created_at FROM subscription_tokens
         WHERE subscription_token = $1
         "#,
         subscription_token
@@ -67,5 +114,5 @@ pub async fn get_subscriber_id_from_token(
         e
     })?;
 
-    Ok(result.map(|r| r.subscriber_id))
+    Ok(result)
 }
@@ -0,0 +1,520 @@
+use anyhow::Context;
+use askama::Template;
+use axum::{
+    extract::{Query, State},
+    http::header,
+    response::{Html, IntoResponse, Response},
+    Form, Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use hyper::StatusCode;
+use secrecy::Secret;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::{
+    app_error::AppError,
+    app_state::AppState,
+    challenge::Challenge,
+    domain::{signature, DataRequestToken, SubscriberEmail},
+    email_templates::DataRequestEmail,
+    routes::{check_for_bots, BotCheck, SubscribeError, SubscriptionChallenge, Verdict},
+};
+
+/// How long the links of a data request email can be used.
+const DATA_REQUEST_TOKEN_TTL_HOURS: i64 = 24;
+
+#[derive(Template)]
+#[template(path = "data_request.html")]
+struct DataRequestTemplate {
+    sent: bool,
+    error: Option<String>,
+    form_token: String,
+    challenge: Challenge,
+}
+
+impl DataRequestTemplate {
+    fn new(state: &AppState, sent: bool, error: Option<String>) -> Self {
+        let SubscriptionChallenge {
+            form_token,
+            challenge,
+        } = SubscriptionChallenge::issue(state);
+        Self {
+            sent,
+            error,
+            form_token,
+            challenge,
+        }
+    }
+}
+
+/// Show the form to ask for a copy of one's data, or for its erasure, with
+/// the same form token and challenge as the subscription form.
+pub async fn data_request_form(State(state): State<AppState>) -> Result<Html<String>, AppError> {
+    Ok(Html(
+        DataRequestTemplate::new(&state, false, None).render()?,
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct DataRequestFormData {
+    email: String,
+    #[serde(flatten)]
+    bot_check: BotCheck,
+}
+
+/// Email links to download or erase the data of a subscriber to their
+/// address. The email is looked up and sent in the background, so the
+/// response is the same, and as fast, whether or not the address is known:
+/// the form can't be used to find out who subscribed.
+///
+/// The form is checked for bots like the subscription form, see
+/// [`check_for_bots`].
+#[tracing::instrument(name = "Request a copy or the erasure of subscriber data", skip_all)]
+pub async fn request_data(
+    State(state): State<AppState>,
+    Form(form): Form<DataRequestFormData>,
+) -> Result<Response, AppError> {
+    match check_for_bots(&state, &form.bot_check).await {
+        Ok(Verdict::Human) => {
+            tokio::spawn(
+                send_data_request_email(state.clone(), form.email)
+                    .instrument(tracing::Span::current()),
+            );
+        }
+        // Bots get the same response as everyone else.
+        Ok(Verdict::Bot) => {}
+        Err(SubscribeError::Validation { message, .. }) => {
+            let page = DataRequestTemplate::new(&state, false, Some(message)).render()?;
+            return Ok((StatusCode::BAD_REQUEST, Html(page)).into_response());
+        }
+        Err(SubscribeError::Unexpected(e)) => return Err(e.into()),
+    }
+
+    Ok(Html(DataRequestTemplate::new(&state, true, None).render()?).into_response())
+}
+
+/// Failures are only logged, since the response has already been sent.
+async fn send_data_request_email(state: AppState, email: String) {
+    if let Err(e) = try_send_data_request_email(&state, email.trim()).await {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to send a data request email"
+        );
+    }
+}
+
+async fn try_send_data_request_email(state: &AppState, email: &str) -> Result<(), anyhow::Error> {
+    let subscriber = sqlx::query!(
+        "SELECT id, email FROM subscriptions WHERE email = $1",
+        email
+    )
+    .fetch_optional(&state.db_pool)
+    .await
+    .context("Failed to look up the subscriber")?;
+    let Some(subscriber) = subscriber else {
+        return Ok(());
+    };
+
+    let expires_at = Utc::now() + Duration::hours(DATA_REQUEST_TOKEN_TTL_HOURS);
+    let token = DataRequestToken::generate(subscriber.id, expires_at, &state.hmac_secret.0);
+    let token = token.as_ref();
+    let message = DataRequestEmail {
+        export_link: &format!("{}/subscriptions/data?token={token}", state.base_url),
+        erase_link: &format!("{}/subscriptions/erase?token={token}", state.base_url),
+        valid_for_hours: DATA_REQUEST_TOKEN_TTL_HOURS,
+    }
+    .render()
+    .context("Failed to render the data request email")?;
+    let recipient = SubscriberEmail::parse(subscriber.email).map_err(anyhow::Error::msg)?;
+    state
+        .email_client
+        .send_email(&recipient, &message)
+        .await
+        .context("Failed to send the data request email")?;
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct DataRequestParams {
+    token: String,
+}
+
+/// Everything we hold about a subscriber.
+#[derive(Serialize)]
+pub struct SubscriberData {
+    pub subscriber: SubscriberRecord,
+    pub lists: Vec<ListRecord>,
+    pub tokens: Vec<TokenRecord>,
+    pub deliveries: Vec<DeliveryRecord>,
+    pub engagement: Vec<EngagementRecord>,
+    pub preference_changes: Vec<PreferenceChangeRecord>,
+}
+
+#[derive(Serialize)]
+pub struct SubscriberRecord {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub frequency: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct ListRecord {
+    pub list: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub consent_source: Option<String>,
+    pub tags: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct TokenRecord {
+    pub subscription_token: String,
+    pub list: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct DeliveryRecord {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub status: String,
+    pub queued_at: DateTime<Utc>,
+    pub sent_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct EngagementRecord {
+    pub newsletter_issue_id: Uuid,
+    pub kind: String,
+    pub url: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct PreferenceChangeRecord {
+    pub field: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub changed_by: String,
+    pub changed_at: DateTime<Utc>,
+}
+
+/// Download everything we hold about the subscriber the token was issued for,
+/// as JSON.
+#[tracing::instrument(name = "Export the data of a subscriber", skip(state, params))]
+pub async fn export_subscriber_data(
+    State(state): State<AppState>,
+    Query(params): Query<DataRequestParams>,
+) -> Result<Response, AppError> {
+    let subscriber_id =
+        match DataRequestToken::parse(&params.token, &state.hmac_secret.0, Utc::now()) {
+            Ok(subscriber_id) => subscriber_id,
+            Err(e) => {
+                tracing::warn!(error.message = %e, "Rejected a data request token");
+                return Ok(StatusCode::UNAUTHORIZED.into_response());
+            }
+        };
+    let Some(data) = get_subscriber_data(&state.db_pool, subscriber_id).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    Ok((
+        [(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"my-data.json\"",
+        )],
+        Json(data),
+    )
+        .into_response())
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_subscriber_data(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberData>, sqlx::Error> {
+    let Some(subscriber) = sqlx::query_as!(
+        SubscriberRecord,
+        r#"
+        SELECT id, email, name, status, frequency, subscribed_at, confirmed_at
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await?
+    else {
+        return Ok(None);
+    };
+    let lists = sqlx::query_as!(
+        ListRecord,
+        r#"
+        SELECT
+            lists.slug AS list,
+            lists.name,
+            list_subscriptions.status,
+            list_subscriptions.subscribed_at,
+            list_subscriptions.consent_source,
+            ARRAY(
+                SELECT tag FROM subscriber_tags
+                WHERE
+                    subscriber_tags.list_id = list_subscriptions.list_id AND
+                    subscriber_tags.subscriber_id = list_subscriptions.subscriber_id
+                ORDER BY tag
+            ) AS "tags!"
+        FROM list_subscriptions
+        JOIN lists ON lists.list_id = list_subscriptions.list_id
+        WHERE list_subscriptions.subscriber_id = $1
+        ORDER BY list_subscriptions.subscribed_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await?;
+    let tokens = sqlx::query_as!(
+        TokenRecord,
+        r#"
+        SELECT subscription_token, lists.slug AS list, subscription_tokens.created_at
+        FROM subscription_tokens
+        JOIN lists ON lists.list_id = subscription_tokens.list_id
+        WHERE subscriber_id = $1
+        ORDER BY subscription_tokens.created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await?;
+    let deliveries = sqlx::query_as!(
+        DeliveryRecord,
+        r#"
+        SELECT
+            issue_delivery_queue.newsletter_issue_id,
+            newsletter_issues.title,
+            issue_delivery_queue.status,
+            issue_delivery_queue.queued_at,
+            issue_delivery_queue.sent_at,
+            issue_delivery_queue.last_error
+        FROM issue_delivery_queue
+        JOIN newsletter_issues
+            ON newsletter_issues.newsletter_issue_id = issue_delivery_queue.newsletter_issue_id
+        WHERE subscriber_email = $1
+        ORDER BY issue_delivery_queue.queued_at
+        "#,
+        subscriber.email
+    )
+    .fetch_all(pool)
+    .await?;
+    let engagement = sqlx::query_as!(
+        EngagementRecord,
+        r#"
+        SELECT newsletter_issue_id, kind, url, occurred_at
+        FROM tracking_events
+        WHERE subscriber_id = $1
+        ORDER BY occurred_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await?;
+    let preference_changes = sqlx::query_as!(
+        PreferenceChangeRecord,
+        r#"
+        SELECT field, old_value, new_value, changed_by, changed_at
+        FROM preference_changes
+        WHERE subscriber_id = $1
+        ORDER BY changed_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(Some(SubscriberData {
+        subscriber,
+        lists,
+        tokens,
+        deliveries,
+        engagement,
+        preference_changes,
+    }))
+}
+
+#[derive(Template)]
+#[template(path = "erase.html")]
+struct EraseTemplate {
+    token: String,
+    erased: bool,
+}
+
+/// Ask the subscriber to confirm, so that link scanners following the link
+/// don't erase anyone.
+#[tracing::instrument(name = "Show the erasure form", skip(state, params))]
+pub async fn erase_form(
+    State(state): State<AppState>,
+    Query(params): Query<DataRequestParams>,
+) -> Result<Response, AppError> {
+    if let Err(e) = DataRequestToken::parse(&params.token, &state.hmac_secret.0, Utc::now()) {
+        tracing::warn!(error.message = %e, "Rejected a data request token");
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+
+    let template = EraseTemplate {
+        token: params.token,
+        erased: false,
+    };
+    Ok(Html(template.render()?).into_response())
+}
+
+/// Erase the data of the subscriber the token was issued for.
+#[tracing::instrument(name = "Erase a subscriber at their request", skip(state, params))]
+pub async fn erase(
+    State(state): State<AppState>,
+    Query(params): Query<DataRequestParams>,
+) -> Result<Response, AppError> {
+    let subscriber_id =
+        match DataRequestToken::parse(&params.token, &state.hmac_secret.0, Utc::now()) {
+            Ok(subscriber_id) => subscriber_id,
+            Err(e) => {
+                tracing::warn!(error.message = %e, "Rejected a data request token");
+                return Ok(StatusCode::UNAUTHORIZED.into_response());
+            }
+        };
+    // Erasing twice, e.g. by submitting the form again, is fine.
+    erase_subscriber(&state.db_pool, subscriber_id, &state.hmac_secret.0).await?;
+
+    let template = EraseTemplate {
+        token: params.token,
+        erased: true,
+    };
+    Ok(Html(template.render()?).into_response())
+}
+
+/// Delete the subscriber along with everything that refers to them, keeping
+/// only a hash of their address in `erased_subscribers` so that it isn't
+/// imported again. Deliveries that were already made are kept for the
+/// statistics of their issue, under the hash instead of the address.
+///
+/// Returns the email of the erased subscriber, or `None` if there was no such
+/// subscriber.
+#[tracing::instrument(skip(pool, hmac_secret))]
+pub async fn erase_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    hmac_secret: &Secret<String>,
+) -> Result<Option<String>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let Some(email) = sqlx::query_scalar!(
+        "SELECT email FROM subscriptions WHERE id = $1 FOR UPDATE",
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look up the subscriber")?
+    else {
+        return Ok(None);
+    };
+    let email_hash = erased_email_hash(&email, hmac_secret);
+
+    sqlx::query!(
+        "INSERT INTO erased_subscribers (email_hash) VALUES ($1) ON CONFLICT DO NOTHING",
+        email_hash
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to record the erasure")?;
+    sqlx::query!(
+        "DELETE FROM issue_delivery_queue WHERE subscriber_email = $1 AND status = 'pending'",
+        email
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to cancel pending deliveries")?;
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET subscriber_email = $2, last_error = NULL
+        WHERE subscriber_email = $1
+        "#,
+        email,
+        format!("erased:{email_hash}")
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to anonymise past deliveries")?;
+    sqlx::query!(
+        "DELETE FROM subscriber_import_rejections WHERE lower(email) = lower($1)",
+        email
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete rejected import rows")?;
+    // Tokens, list subscriptions, tags, tracking events and preference
+    // changes go along with the subscriber.
+    sqlx::query!("DELETE FROM subscriptions WHERE id = $1", subscriber_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete the subscriber")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the erasure")?;
+
+    Ok(Some(email))
+}
+
+/// What is kept of an erased address: the [signature](signature) of its
+/// trimmed, lowercased form. It is keyed with our secret so that the address
+/// can't be found again by hashing a list of candidates.
+pub fn erased_email_hash(email: &str, hmac_secret: &Secret<String>) -> String {
+    signature::sign(
+        "erased-email",
+        &[email.trim().to_lowercase().as_bytes()],
+        hmac_secret,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::Secret;
+
+    use super::erased_email_hash;
+
+    fn secret() -> Secret<String> {
+        Secret::new("super-secret".to_string())
+    }
+
+    #[test]
+    fn the_hash_ignores_case_and_surrounding_whitespace() {
+        assert_eq!(
+            erased_email_hash(" Ursula@Example.com ", &secret()),
+            erased_email_hash("ursula@example.com", &secret())
+        );
+        assert_ne!(
+            erased_email_hash("ursula@example.com", &secret()),
+            erased_email_hash("guin@example.com", &secret())
+        );
+    }
+
+    #[test]
+    fn the_hash_depends_on_the_secret() {
+        assert_ne!(
+            erased_email_hash("ursula@example.com", &secret()),
+            erased_email_hash("ursula@example.com", &Secret::new("other".into()))
+        );
+    }
+}
@@ -0,0 +1,333 @@
+use askama::Template;
+use axum::{
+    extract::{Query, State},
+    response::{Html, IntoResponse, Response},
+    Form,
+};
+use chrono::{DateTime, Utc};
+use hyper::StatusCode;
+use serde::Deserialize;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    app_error::AppError,
+    app_state::AppState,
+    domain::{DeliveryFrequency, PreferencesToken, SubscriberName},
+};
+
+#[derive(Deserialize)]
+pub struct PreferencesParams {
+    token: String,
+}
+
+pub struct SubscriberPreferences {
+    pub name: String,
+    pub email: String,
+    pub frequency: String,
+}
+
+/// A list the subscriber can choose to receive.
+pub struct ListChoice {
+    pub list_id: Uuid,
+    pub slug: String,
+    pub name: String,
+    /// `None` if the subscriber was never on the list.
+    pub status: Option<String>,
+}
+
+impl ListChoice {
+    pub fn is_subscribed(&self) -> bool {
+        self.status.as_deref() == Some("confirmed")
+    }
+}
+
+#[derive(Template)]
+#[template(path = "preferences.html")]
+struct PreferencesTemplate {
+    token: String,
+    subscriber: SubscriberPreferences,
+    lists: Vec<ListChoice>,
+    frequencies: [DeliveryFrequency; 3],
+    last_changed_at: Option<DateTime<Utc>>,
+    message: Option<String>,
+    error: Option<String>,
+}
+
+/// Show the preference center of the subscriber the token was issued for.
+#[tracing::instrument(name = "Show the preference center", skip(state, params))]
+pub async fn preferences_form(
+    State(state): State<AppState>,
+    Query(params): Query<PreferencesParams>,
+) -> Result<Response, AppError> {
+    let subscriber_id = match PreferencesToken::parse(&params.token, &state.hmac_secret.0) {
+        Ok(subscriber_id) => subscriber_id,
+        Err(e) => {
+            tracing::warn!(error.message = %e, "Rejected a preferences token");
+            return Ok(StatusCode::UNAUTHORIZED.into_response());
+        }
+    };
+
+    render_preferences(&state.db_pool, params.token, subscriber_id, None, None).await
+}
+
+/// The fields of the preferences form. List checkboxes share the `list` name,
+/// so the form is read as a sequence of pairs rather than a struct.
+#[derive(Default)]
+struct PreferencesForm {
+    name: String,
+    frequency: String,
+    lists: Vec<Uuid>,
+    unsubscribe_all: bool,
+}
+
+impl From<Vec<(String, String)>> for PreferencesForm {
+    fn from(fields: Vec<(String, String)>) -> Self {
+        let mut form = PreferencesForm::default();
+        for (key, value) in fields {
+            match key.as_str() {
+                "name" => form.name = value,
+                "frequency" => form.frequency = value,
+                "list" => form.lists.extend(Uuid::try_parse(&value).ok()),
+                "action" => form.unsubscribe_all = value == "unsubscribe_all",
+                _ => {}
+            }
+        }
+        form
+    }
+}
+
+/// Save the changes made in the preference center, or unsubscribe from every
+/// list. Every change is recorded in `preference_changes`.
+#[tracing::instrument(name = "Update subscriber preferences", skip(state, params, fields))]
+pub async fn update_preferences(
+    State(state): State<AppState>,
+    Query(params): Query<PreferencesParams>,
+    Form(fields): Form<Vec<(String, String)>>,
+) -> Result<Response, AppError> {
+    let subscriber_id = match PreferencesToken::parse(&params.token, &state.hmac_secret.0) {
+        Ok(subscriber_id) => subscriber_id,
+        Err(e) => {
+            tracing::warn!(error.message = %e, "Rejected a preferences token");
+            return Ok(StatusCode::UNAUTHORIZED.into_response());
+        }
+    };
+    let Some(current) = get_subscriber_preferences(&state.db_pool, subscriber_id).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let form = PreferencesForm::from(fields);
+
+    let mut transaction = state.db_pool.begin().await?;
+    let message = if form.unsubscribe_all {
+        for list in list_choices(&mut *transaction, subscriber_id).await? {
+            if list.status.as_deref().is_some_and(|s| s != "unsubscribed") {
+                set_list_status(&mut transaction, subscriber_id, &list, "unsubscribed").await?;
+            }
+        }
+        "You have been unsubscribed from every list."
+    } else {
+        let name = match SubscriberName::parse(form.name) {
+            Ok(name) => name,
+            Err(e) => {
+                let page =
+                    render_preferences(&state.db_pool, params.token, subscriber_id, None, Some(e))
+                        .await?;
+                return Ok((StatusCode::BAD_REQUEST, page).into_response());
+            }
+        };
+        let frequency = match DeliveryFrequency::parse(&form.frequency) {
+            Ok(frequency) => frequency,
+            Err(e) => {
+                let page =
+                    render_preferences(&state.db_pool, params.token, subscriber_id, None, Some(e))
+                        .await?;
+                return Ok((StatusCode::BAD_REQUEST, page).into_response());
+            }
+        };
+
+        if name.as_ref() != current.name {
+            sqlx::query!(
+                "UPDATE subscriptions SET name = $2 WHERE id = $1",
+                subscriber_id,
+                name.as_ref()
+            )
+            .execute(&mut *transaction)
+            .await?;
+            record_change(
+                &mut transaction,
+                subscriber_id,
+                "name",
+                Some(&current.name),
+                Some(name.as_ref()),
+                "subscriber",
+            )
+            .await?;
+        }
+        if frequency.as_str() != current.frequency {
+            sqlx::query!(
+                "UPDATE subscriptions SET frequency = $2 WHERE id = $1",
+                subscriber_id,
+                frequency.as_str()
+            )
+            .execute(&mut *transaction)
+            .await?;
+            record_change(
+                &mut transaction,
+                subscriber_id,
+                "frequency",
+                Some(&current.frequency),
+                Some(frequency.as_str()),
+                "subscriber",
+            )
+            .await?;
+        }
+        for list in list_choices(&mut *transaction, subscriber_id).await? {
+            let wanted = form.lists.contains(&list.list_id);
+            if wanted && !list.is_subscribed() {
+                set_list_status(&mut transaction, subscriber_id, &list, "confirmed").await?;
+            } else if !wanted && list.status.as_deref().is_some_and(|s| s != "unsubscribed") {
+                set_list_status(&mut transaction, subscriber_id, &list, "unsubscribed").await?;
+            }
+        }
+        "Your preferences have been saved."
+    };
+    transaction.commit().await?;
+
+    render_preferences(
+        &state.db_pool,
+        params.token,
+        subscriber_id,
+        Some(message.into()),
+        None,
+    )
+    .await
+}
+
+async fn render_preferences(
+    pool: &PgPool,
+    token: String,
+    subscriber_id: Uuid,
+    message: Option<String>,
+    error: Option<String>,
+) -> Result<Response, AppError> {
+    let Some(subscriber) = get_subscriber_preferences(pool, subscriber_id).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let last_changed_at = sqlx::query_scalar!(
+        "SELECT max(changed_at) FROM preference_changes WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .fetch_one(pool)
+    .await?;
+    let template = PreferencesTemplate {
+        token,
+        subscriber,
+        lists: list_choices(pool, subscriber_id).await?,
+        frequencies: DeliveryFrequency::ALL,
+        last_changed_at,
+        message,
+        error,
+    };
+
+    Ok(Html(template.render()?).into_response())
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_subscriber_preferences(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberPreferences>, sqlx::Error> {
+    sqlx::query_as!(
+        SubscriberPreferences,
+        "SELECT name, email, frequency FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// The lists that aren't archived, along with the status of the subscriber on
+/// each of them.
+async fn list_choices<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    subscriber_id: Uuid,
+) -> Result<Vec<ListChoice>, sqlx::Error> {
+    sqlx::query_as!(
+        ListChoice,
+        r#"
+        SELECT
+            lists.list_id,
+            lists.slug,
+            lists.name,
+            list_subscriptions.status AS "status?"
+        FROM lists
+        LEFT JOIN list_subscriptions ON
+            list_subscriptions.list_id = lists.list_id AND
+            list_subscriptions.subscriber_id = $1
+        WHERE lists.archived_at IS NULL
+        ORDER BY lists.created_at, lists.slug
+        "#,
+        subscriber_id
+    )
+    .fetch_all(executor)
+    .await
+}
+
+/// Subscribe to or unsubscribe from a list. Choosing a list in the preference
+/// center doesn't need a confirmation, since the token already proves that
+/// the subscriber owns the address.
+async fn set_list_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list: &ListChoice,
+    status: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (list_id, subscriber_id, status)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (list_id, subscriber_id) DO UPDATE SET status = EXCLUDED.status
+        "#,
+        list.list_id,
+        subscriber_id,
+        status
+    )
+    .execute(&mut **transaction)
+    .await?;
+    record_change(
+        transaction,
+        subscriber_id,
+        &format!("list:{}", list.slug),
+        list.status.as_deref(),
+        Some(status),
+        "subscriber",
+    )
+    .await
+}
+
+/// Record a change to the preferences of a subscriber. `changed_by` is either
+/// `subscriber` or `admin`.
+pub(crate) async fn record_change(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    field: &str,
+    old_value: Option<&str>,
+    new_value: Option<&str>,
+    changed_by: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO preference_changes (subscriber_id, field, old_value, new_value, changed_by)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        subscriber_id,
+        field,
+        old_value,
+        new_value,
+        changed_by
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use askama::Template;
+use axum::{
+    extract::{Query, State},
+    response::{Html, IntoResponse, Response},
+};
+use hyper::StatusCode;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    app_error::AppError,
+    app_state::AppState,
+    domain::{UnsubscribeTarget, UnsubscribeToken},
+    routes::get_list,
+};
+
+#[derive(Deserialize)]
+pub struct UnsubscribeParams {
+    token: String,
+}
+
+#[derive(Template)]
+#[template(path = "unsubscribe.html")]
+struct UnsubscribeTemplate {
+    token: String,
+    /// `None` when unsubscribing from every list.
+    list_name: Option<String>,
+    unsubscribed: bool,
+}
+
+/// Ask the subscriber to confirm, so that link scanners following the
+/// unsubscribe link don't unsubscribe anyone.
+#[tracing::instrument(name = "Show the unsubscribe form", skip(state, params))]
+pub async fn unsubscribe_form(
+    State(state): State<AppState>,
+    Query(params): Query<UnsubscribeParams>,
+) -> Result<Response, AppError> {
+    let target = match UnsubscribeToken::parse(&params.token, &state.hmac_secret.0) {
+        Ok(target) => target,
+        Err(e) => {
+            tracing::warn!(error.message = %e, "Rejected an unsubscribe token");
+            return Ok(StatusCode::UNAUTHORIZED.into_response());
+        }
+    };
+
+    let template = UnsubscribeTemplate {
+        token: params.token,
+        list_name: get_list_name(&state.db_pool, &target).await?,
+        unsubscribed: false,
+    };
+    Ok(Html(template.render()?).into_response())
+}
+
+/// Unsubscribe the subscriber the token was issued for from its list.
+///
+/// This also serves RFC 8058 one-click requests sent by mailbox providers.
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip(state, params))]
+pub async fn unsubscribe(
+    State(state): State<AppState>,
+    Query(params): Query<UnsubscribeParams>,
+) -> Result<Response, AppError> {
+    let target = match UnsubscribeToken::parse(&params.token, &state.hmac_secret.0) {
+        Ok(target) => target,
+        Err(e) => {
+            tracing::warn!(error.message = %e, "Rejected an unsubscribe token");
+            return Ok(StatusCode::UNAUTHORIZED.into_response());
+        }
+    };
+
+    unsubscribe_subscriber(&state.db_pool, target.subscriber_id, target.list_id).await?;
+
+    let template = UnsubscribeTemplate {
+        token: params.token,
+        list_name: get_list_name(&state.db_pool, &target).await?,
+        unsubscribed: true,
+    };
+    Ok(Html(template.render()?).into_response())
+}
+
+async fn get_list_name(
+    pool: &PgPool,
+    target: &UnsubscribeTarget,
+) -> Result<Option<String>, sqlx::Error> {
+    let Some(list_id) = target.list_id else {
+        return Ok(None);
+    };
+    Ok(get_list(pool, list_id).await?.map(|list| list.name))
+}
+
+/// Unsubscribe from a single list, or from every list when `list_id` is
+/// `None`.
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip(pool))]
+pub async fn unsubscribe_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    list_id: Option<Uuid>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE list_subscriptions SET status = 'unsubscribed'
+        WHERE subscriber_id = $1 AND ($2::uuid IS NULL OR list_id = $2)
+        "#,
+        subscriber_id,
+        list_id,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
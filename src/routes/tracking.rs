@@ -0,0 +1,85 @@
+use axum::{
+    extract::{Path, State},
+    http::header,
+    response::{IntoResponse, Redirect, Response},
+};
+use hyper::StatusCode;
+use sqlx::PgPool;
+
+use crate::{
+    app_state::AppState,
+    domain::{TrackedRecipient, TrackingToken},
+};
+
+/// A transparent 1x1 GIF.
+const PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+/// Serve the tracking pixel of a newsletter email and record that it was
+/// opened.
+///
+/// The pixel is served even if the token is invalid or the event can't be
+/// stored, so that the email renders the same either way.
+#[tracing::instrument(name = "Record an open", skip_all)]
+pub async fn track_open(State(state): State<AppState>, Path(token): Path<String>) -> Response {
+    let token = token.strip_suffix(".gif").unwrap_or(&token);
+    match TrackingToken::parse(token, &state.hmac_secret.0) {
+        Ok(recipient) => {
+            if let Err(e) = record_event(&state.db_pool, &recipient, "open").await {
+                tracing::error!(error.cause_chain = ?e, "Failed to record an open");
+            }
+        }
+        Err(e) => tracing::warn!(error.message = %e, "Rejected a tracking token"),
+    }
+
+    (
+        [
+            (header::CONTENT_TYPE, "image/gif"),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        PIXEL,
+    )
+        .into_response()
+}
+
+/// Record a click on a link in a newsletter email and redirect to its target.
+#[tracing::instrument(name = "Record a click", skip_all)]
+pub async fn track_click(State(state): State<AppState>, Path(token): Path<String>) -> Response {
+    let recipient = match TrackingToken::parse(&token, &state.hmac_secret.0) {
+        Ok(TrackedRecipient { url: None, .. }) => return StatusCode::NOT_FOUND.into_response(),
+        Ok(recipient) => recipient,
+        Err(e) => {
+            tracing::warn!(error.message = %e, "Rejected a tracking token");
+            return StatusCode::NOT_FOUND.into_response();
+        }
+    };
+    if let Err(e) = record_event(&state.db_pool, &recipient, "click").await {
+        tracing::error!(error.cause_chain = ?e, "Failed to record a click");
+    }
+
+    let url = recipient.url.expect("Click tokens carry a URL");
+    Redirect::temporary(&url).into_response()
+}
+
+async fn record_event(
+    pool: &PgPool,
+    recipient: &TrackedRecipient,
+    kind: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO tracking_events (newsletter_issue_id, subscriber_id, kind, url)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        recipient.newsletter_issue_id,
+        recipient.subscriber_id,
+        kind,
+        recipient.url,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
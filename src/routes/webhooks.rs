@@ -0,0 +1,213 @@
+use anyhow::Context;
+use axum::{
+    body::Bytes,
+    extract::State,
+    response::{IntoResponse, Response},
+};
+use hyper::{HeaderMap, StatusCode};
+use serde::Deserialize;
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{app_state::AppState, domain::signature};
+
+const SIGNATURE_HEADER: &str = "X-Signature";
+
+/// The body of a request to the `receive_email_events` handler.
+#[derive(Deserialize)]
+pub struct EmailEvents {
+    events: Vec<EmailEvent>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum EmailEvent {
+    Bounce {
+        email: String,
+        bounce_type: BounceType,
+        /// The issue whose delivery bounced, if the provider knows it.
+        newsletter_issue_id: Option<Uuid>,
+        description: Option<String>,
+    },
+    Complaint {
+        email: String,
+    },
+    /// Event types we don't act on are accepted and ignored.
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum BounceType {
+    /// The address doesn't exist or permanently refuses our mail.
+    Hard,
+    /// A temporary problem, e.g. a full mailbox.
+    Soft,
+}
+
+/// This is the error type returned by the `receive_email_events` handler.
+#[derive(Debug, thiserror::Error)]
+pub enum WebhookError {
+    #[error("Invalid signature")]
+    Signature(#[source] anyhow::Error),
+    #[error("{0}")]
+    Validation(String),
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+impl IntoResponse for WebhookError {
+    fn into_response(self) -> Response {
+        let status_code = match self {
+            Self::Signature(_) => StatusCode::UNAUTHORIZED,
+            Self::Validation(_) => StatusCode::BAD_REQUEST,
+            Self::Unexpected(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        (status_code, self.to_string()).into_response()
+    }
+}
+
+/// Receive bounce and complaint notifications from our email provider.
+///
+/// The body is a JSON object with a list of events:
+///
+/// ```json
+/// {
+///   "events": [
+///     {
+///       "type": "bounce",
+///       "email": "ursula@example.com",
+///       "bounce_type": "hard",
+///       "newsletter_issue_id": "7b4c8e53-9d0c-4e4b-a3b7-0a5f6d1f4c2e",
+///       "description": "550 5.1.1 The email account does not exist"
+///     },
+///     { "type": "complaint", "email": "ursula@example.com" }
+///   ]
+/// }
+/// ```
+///
+/// `bounce_type` is either `hard` or `soft`, and `newsletter_issue_id` and
+/// `description` are optional. Events of any other type are ignored.
+///
+/// The request must carry an `X-Signature: sha256=<hex>` header holding the
+/// HMAC-SHA256 of the raw body, keyed with `email_events_webhook_secret`.
+///
+/// Hard bounces mark the subscriber as `bounced` and complaints mark them as
+/// `complained`, which stops any further mail. Soft bounces are only logged.
+#[tracing::instrument(name = "Receive email events", skip_all)]
+pub async fn receive_email_events(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode, WebhookError> {
+    verify_signature(&headers, &body, &state.webhook_secret.0).map_err(WebhookError::Signature)?;
+    let events: EmailEvents =
+        serde_json::from_slice(&body).map_err(|e| WebhookError::Validation(e.to_string()))?;
+
+    let mut transaction = state
+        .db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    for event in events.events {
+        match event {
+            EmailEvent::Bounce {
+                email,
+                bounce_type: BounceType::Hard,
+                newsletter_issue_id,
+                description,
+            } => {
+                tracing::info!(subscriber_email = %email, "Received a hard bounce");
+                set_subscriber_status(&mut transaction, &email, "bounced").await?;
+                if let Some(issue_id) = newsletter_issue_id {
+                    mark_delivery_bounced(&mut transaction, issue_id, &email, description).await?;
+                }
+            }
+            EmailEvent::Bounce {
+                email,
+                bounce_type: BounceType::Soft,
+                ..
+            } => {
+                tracing::info!(subscriber_email = %email, "Received a soft bounce");
+            }
+            EmailEvent::Complaint { email } => {
+                tracing::info!(subscriber_email = %email, "Received a complaint");
+                set_subscriber_status(&mut transaction, &email, "complained").await?;
+            }
+            EmailEvent::Other => {}
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store email events")?;
+
+    Ok(StatusCode::OK)
+}
+
+fn verify_signature(
+    headers: &HeaderMap,
+    body: &[u8],
+    secret: &secrecy::Secret<String>,
+) -> Result<(), anyhow::Error> {
+    let signature = headers
+        .get(SIGNATURE_HEADER)
+        .context("The signature header is missing")?
+        .to_str()
+        .context("The signature header is not valid UTF-8")?;
+    let signature = signature
+        .strip_prefix("sha256=")
+        .context("The signature must start with 'sha256='")?;
+    signature::verify_external(body, signature, secret)
+        .context("The signature doesn't match the body")
+}
+
+/// Stop mailing a subscriber. Complaints take precedence over bounces, so a
+/// later bounce won't overwrite `complained`.
+#[tracing::instrument(skip(transaction))]
+async fn set_subscriber_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+    status: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = $2
+        WHERE email = $1 AND status <> 'complained'
+        "#,
+        email,
+        status
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to update the subscriber status")?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn mark_delivery_bounced(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    email: &str,
+    description: Option<String>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET status = 'bounced', last_error = coalesce($3, last_error)
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        newsletter_issue_id,
+        email,
+        description
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to mark the delivery as bounced")?;
+
+    Ok(())
+}
@@ -0,0 +1,340 @@
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::domain::SubscriberTag;
+
+/// How deeply tag filters can be nested.
+const MAX_DEPTH: usize = 8;
+/// How many tags a single segment can refer to.
+const MAX_TAGS: usize = 50;
+
+/// Which subscribers of a list a segment is made of. Segments are stored as
+/// JSON, e.g.
+///
+/// ```json
+/// {
+///     "tags": { "and": [{ "tag": "beta" }, { "not": { "tag": "paid" } }] },
+///     "subscribed_after": "2023-01-01T00:00:00Z"
+/// }
+/// ```
+///
+/// A subscriber must match every condition that is set. A definition without
+/// any condition matches the whole list.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SegmentDefinition {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<TagFilter>,
+    /// Only subscribers who joined the list at or after this time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subscribed_after: Option<DateTime<Utc>>,
+    /// Only subscribers who joined the list before this time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subscribed_before: Option<DateTime<Utc>>,
+}
+
+/// A condition on the tags of a subscriber.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TagFilter {
+    /// The subscriber has this tag.
+    Tag(String),
+    /// Every filter matches. Matches everyone when empty.
+    And(Vec<TagFilter>),
+    /// At least one filter matches. Matches nobody when empty.
+    Or(Vec<TagFilter>),
+    Not(Box<TagFilter>),
+}
+
+impl SegmentDefinition {
+    pub fn validate(&self) -> Result<(), String> {
+        if let (Some(after), Some(before)) = (self.subscribed_after, self.subscribed_before) {
+            if after >= before {
+                return Err("subscribed_after must be earlier than subscribed_before".into());
+            }
+        }
+        if let Some(tags) = &self.tags {
+            tags.validate(1, &mut 0)?;
+        }
+        Ok(())
+    }
+
+    /// Append the condition selecting the members of the segment. It refers
+    /// to the `list_subscriptions` table, which must be part of the query.
+    pub fn push_condition(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        query.push("TRUE");
+        if let Some(subscribed_after) = self.subscribed_after {
+            query
+                .push(" AND list_subscriptions.subscribed_at >= ")
+                .push_bind(subscribed_after);
+        }
+        if let Some(subscribed_before) = self.subscribed_before {
+            query
+                .push(" AND list_subscriptions.subscribed_at < ")
+                .push_bind(subscribed_before);
+        }
+        if let Some(tags) = &self.tags {
+            query.push(" AND ");
+            tags.push_condition(query);
+        }
+    }
+}
+
+/// Formats the definition as JSON, the way it is stored.
+impl fmt::Display for SegmentDefinition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let json = serde_json::to_string(self).map_err(|_| fmt::Error)?;
+        f.write_str(&json)
+    }
+}
+
+impl TagFilter {
+    fn validate(&self, depth: usize, n_tags: &mut usize) -> Result<(), String> {
+        if depth > MAX_DEPTH {
+            return Err(format!(
+                "Tag filters can be nested at most {MAX_DEPTH} levels deep"
+            ));
+        }
+        match self {
+            TagFilter::Tag(tag) => {
+                *n_tags += 1;
+                if *n_tags > MAX_TAGS {
+                    return Err(format!("A segment can refer to at most {MAX_TAGS} tags"));
+                }
+                SubscriberTag::parse(tag.clone())?;
+            }
+            TagFilter::And(filters) | TagFilter::Or(filters) => {
+                for filter in filters {
+                    filter.validate(depth + 1, n_tags)?;
+                }
+            }
+            TagFilter::Not(filter) => filter.validate(depth + 1, n_tags)?,
+        }
+        Ok(())
+    }
+
+    fn push_condition(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        match self {
+            TagFilter::Tag(tag) => {
+                query
+                    .push(
+                        "EXISTS (SELECT 1 FROM subscriber_tags \
+                        WHERE subscriber_tags.list_id = list_subscriptions.list_id \
+                        AND subscriber_tags.subscriber_id = list_subscriptions.subscriber_id \
+                        AND subscriber_tags.tag = ",
+                    )
+                    .push_bind(tag.clone())
+                    .push(")");
+            }
+            TagFilter::And(filters) => push_all(query, filters, " AND ", "TRUE"),
+            TagFilter::Or(filters) => push_all(query, filters, " OR ", "FALSE"),
+            TagFilter::Not(filter) => {
+                query.push("NOT (");
+                filter.push_condition(query);
+                query.push(")");
+            }
+        }
+    }
+}
+
+fn push_all(
+    query: &mut QueryBuilder<'_, Postgres>,
+    filters: &[TagFilter],
+    separator: &str,
+    if_empty: &str,
+) {
+    if filters.is_empty() {
+        query.push(if_empty);
+        return;
+    }
+    query.push("(");
+    for (i, filter) in filters.iter().enumerate() {
+        if i > 0 {
+            query.push(separator);
+        }
+        filter.push_condition(query);
+    }
+    query.push(")");
+}
+
+/// Start a query over the subscribers of a list who confirmed their
+/// subscription and whose address can still be mailed, narrowed down to the
+/// members of `segment` if there is one. `columns` is what to select, e.g.
+/// `email` or `count(*)`.
+pub fn recipients_query<'a>(
+    columns: &str,
+    list_id: Uuid,
+    segment: Option<&SegmentDefinition>,
+) -> QueryBuilder<'a, Postgres> {
+    let mut query = QueryBuilder::new(format!(
+        "SELECT {columns} FROM subscriptions \
+        JOIN list_subscriptions ON list_subscriptions.subscriber_id = subscriptions.id \
+        WHERE list_subscriptions.list_id = "
+    ));
+    query.push_bind(list_id).push(
+        " AND list_subscriptions.status = 'confirmed' AND subscriptions.status = 'confirmed'",
+    );
+    if let Some(segment) = segment {
+        query.push(" AND ");
+        segment.push_condition(&mut query);
+    }
+    query
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SegmentDefinition, TagFilter, MAX_DEPTH, MAX_TAGS};
+    use chrono::{TimeZone, Utc};
+    use claims::{assert_err, assert_ok};
+    use sqlx::QueryBuilder;
+
+    fn tag(tag: &str) -> TagFilter {
+        TagFilter::Tag(tag.into())
+    }
+
+    fn condition(definition: &SegmentDefinition) -> String {
+        let mut query = QueryBuilder::new("");
+        definition.push_condition(&mut query);
+        query.sql().to_owned()
+    }
+
+    const HAS_TAG: &str = "EXISTS (SELECT 1 FROM subscriber_tags \
+        WHERE subscriber_tags.list_id = list_subscriptions.list_id \
+        AND subscriber_tags.subscriber_id = list_subscriptions.subscriber_id \
+        AND subscriber_tags.tag = ";
+
+    #[test]
+    fn definitions_are_parsed_from_json() {
+        let definition: SegmentDefinition = serde_json::from_value(serde_json::json!({
+            "tags": { "and": [{ "tag": "beta" }, { "not": { "tag": "paid" } }] },
+            "subscribed_after": "2023-01-01T00:00:00Z"
+        }))
+        .unwrap();
+
+        assert_eq!(
+            definition,
+            SegmentDefinition {
+                tags: Some(TagFilter::And(vec![
+                    tag("beta"),
+                    TagFilter::Not(Box::new(tag("paid")))
+                ])),
+                subscribed_after: Some(Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap()),
+                subscribed_before: None,
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let definition =
+            serde_json::from_value::<SegmentDefinition>(serde_json::json!({ "tag": "beta" }));
+        assert_err!(definition);
+    }
+
+    #[test]
+    fn an_empty_definition_matches_everyone() {
+        assert_eq!(condition(&SegmentDefinition::default()), "TRUE");
+    }
+
+    #[test]
+    fn tag_filters_are_compiled_to_bound_conditions() {
+        let definition = SegmentDefinition {
+            tags: Some(TagFilter::Or(vec![
+                tag("beta"),
+                TagFilter::And(vec![tag("paid"), TagFilter::Not(Box::new(tag("churned")))]),
+            ])),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            condition(&definition),
+            format!("TRUE AND ({HAS_TAG}$1) OR ({HAS_TAG}$2) AND NOT ({HAS_TAG}$3))))")
+        );
+    }
+
+    #[test]
+    fn empty_and_and_or_filters_match_everyone_and_nobody() {
+        let and = SegmentDefinition {
+            tags: Some(TagFilter::And(vec![])),
+            ..Default::default()
+        };
+        let or = SegmentDefinition {
+            tags: Some(TagFilter::Or(vec![])),
+            ..Default::default()
+        };
+
+        assert_eq!(condition(&and), "TRUE AND TRUE");
+        assert_eq!(condition(&or), "TRUE AND FALSE");
+    }
+
+    #[test]
+    fn date_ranges_are_compiled_to_bound_conditions() {
+        let definition = SegmentDefinition {
+            subscribed_after: Some(Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap()),
+            subscribed_before: Some(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            condition(&definition),
+            "TRUE AND list_subscriptions.subscribed_at >= $1 \
+            AND list_subscriptions.subscribed_at < $2"
+        );
+    }
+
+    #[test]
+    fn a_valid_definition_passes_validation() {
+        let definition = SegmentDefinition {
+            tags: Some(TagFilter::And(vec![tag("beta"), tag("early_access")])),
+            subscribed_after: Some(Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap()),
+            subscribed_before: Some(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()),
+        };
+        assert_ok!(definition.validate());
+    }
+
+    #[test]
+    fn invalid_tags_are_rejected() {
+        let definition = SegmentDefinition {
+            tags: Some(TagFilter::Not(Box::new(tag("Beta Testers")))),
+            ..Default::default()
+        };
+        assert_err!(definition.validate());
+    }
+
+    #[test]
+    fn empty_date_ranges_are_rejected() {
+        let date = Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap();
+        let definition = SegmentDefinition {
+            subscribed_after: Some(date),
+            subscribed_before: Some(date),
+            ..Default::default()
+        };
+        assert_err!(definition.validate());
+    }
+
+    #[test]
+    fn deeply_nested_filters_are_rejected() {
+        let mut filter = tag("beta");
+        for _ in 0..MAX_DEPTH {
+            filter = TagFilter::Not(Box::new(filter));
+        }
+        let definition = SegmentDefinition {
+            tags: Some(filter),
+            ..Default::default()
+        };
+        assert_err!(definition.validate());
+    }
+
+    #[test]
+    fn definitions_with_too_many_tags_are_rejected() {
+        let definition = SegmentDefinition {
+            tags: Some(TagFilter::Or(vec![tag("beta"); MAX_TAGS + 1])),
+            ..Default::default()
+        };
+        assert_err!(definition.validate());
+    }
+}
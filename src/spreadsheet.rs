@@ -0,0 +1,36 @@
+//! Helpers for the CSV files admins download, which are usually opened in a
+//! spreadsheet.
+
+use std::borrow::Cow;
+
+/// Make a cell that came from someone else, e.g. a subscriber's name, safe to
+/// open in a spreadsheet.
+///
+/// Spreadsheets run cells starting with `=`, `+`, `-` or `@` as formulas, so
+/// those are prefixed with `'` to be shown as text instead.
+pub fn escape_cell(cell: &str) -> Cow<'_, str> {
+    if cell.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        Cow::Owned(format!("'{cell}"))
+    } else {
+        Cow::Borrowed(cell)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::escape_cell;
+
+    #[test]
+    fn cells_that_look_like_formulas_are_escaped() {
+        for cell in ["=1+1", "+1", "-1", "@SUM(A1)", "\t=1"] {
+            assert_eq!(escape_cell(cell), format!("'{cell}"));
+        }
+    }
+
+    #[test]
+    fn other_cells_are_left_alone() {
+        for cell in ["", "Ursula Le Guin", "ursula@example.com", "a=b"] {
+            assert_eq!(escape_cell(cell), cell);
+        }
+    }
+}
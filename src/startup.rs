@@ -1,4 +1,5 @@
 use axum::error_handling::HandleErrorLayer;
+use axum::extract::DefaultBodyLimit;
 use axum::middleware::from_fn;
 use axum::routing::{post, IntoMakeService};
 use axum::{routing::get, Router};
@@ -20,11 +21,13 @@ use tracing::Level;
 use std::net::TcpListener;
 use std::time::Duration;
 
-use crate::app_state::AppState;
+use crate::app_state::{AppState, HmacSecret, SubscribeFormTiming, WebhookSecret};
 use crate::authentication::reject_anonymous_users;
+use crate::challenge;
 use crate::configuration::{DatabaseSettings, Settings};
 use crate::email_client::DynEmailClient;
 use crate::routes;
+use crate::subscriber_import::fail_interrupted_imports;
 
 pub struct Application {
     port: u16,
@@ -44,15 +47,28 @@ impl Application {
         let secret_key = Key::generate();
 
         let db_pool = get_connection_pool(&config.database);
+        fail_interrupted_imports(&db_pool).await?;
         let address = format!("{}:{}", config.application.host, config.application.port);
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
+        let challenge_verifier =
+            challenge::from_settings(&config.bot_protection, &config.application.hmac_secret);
         let server = run(
             listener,
             AppState {
                 db_pool,
                 email_client,
                 base_url: config.application.base_url,
+                hmac_secret: HmacSecret(config.application.hmac_secret),
+                webhook_secret: WebhookSecret(config.application.email_events_webhook_secret),
+                subscription_token_ttl: chrono::Duration::hours(
+                    config.application.subscription_token_ttl_hours.into(),
+                ),
+                subscribe_form_timing: SubscribeFormTiming {
+                    min_time_to_submit: config.bot_protection.min_time_to_submit(),
+                    max_age: config.bot_protection.max_form_age(),
+                },
+                challenge_verifier,
                 flash_config: axum_flash::Config::new(secret_key.clone()),
             },
             session_store,
@@ -116,15 +132,129 @@ where
             "/password",
             get(routes::change_password_form).post(routes::change_password),
         )
+        .route(
+            "/newsletters",
+            get(routes::publish_newsletter_form).post(routes::publish_newsletter_from_form),
+        )
+        .route(
+            "/newsletters/:issue_id",
+            get(routes::newsletter_issue_form).post(routes::update_newsletter_issue),
+        )
+        .route(
+            "/newsletters/:issue_id/preview",
+            get(routes::preview_newsletter_issue),
+        )
+        .route(
+            "/newsletters/:issue_id/test",
+            post(routes::send_test_newsletter_issue),
+        )
+        .route(
+            "/newsletters/:issue_id/schedule",
+            post(routes::reschedule_newsletter_issue),
+        )
+        .route(
+            "/newsletters/:issue_id/cancel",
+            post(routes::cancel_newsletter_issue),
+        )
+        .route(
+            "/lists",
+            get(routes::mailing_lists_form).post(routes::create_mailing_list),
+        )
+        .route(
+            "/lists/:list_id",
+            get(routes::mailing_list_form).post(routes::update_mailing_list),
+        )
+        .route(
+            "/lists/:list_id/archive",
+            post(routes::archive_mailing_list),
+        )
+        .route(
+            "/lists/:list_id/segments",
+            post(routes::create_list_segment),
+        )
+        .route("/subscribers", get(routes::subscribers_page))
+        .route("/subscribers/export", get(routes::export_subscribers))
+        .route(
+            "/subscribers/import",
+            get(routes::import_subscribers_form)
+                .post(routes::upload_subscribers)
+                .layer(DefaultBodyLimit::max(routes::MAX_IMPORT_FILE_SIZE)),
+        )
+        .route(
+            "/subscribers/imports/:import_id",
+            get(routes::subscriber_import_page),
+        )
+        .route(
+            "/subscribers/imports/:import_id/rejections.csv",
+            get(routes::download_rejection_report),
+        )
+        .route("/subscribers/:subscriber_id", get(routes::subscriber_page))
+        .route(
+            "/subscribers/:subscriber_id/confirm",
+            post(routes::manually_confirm_subscriber),
+        )
+        .route(
+            "/subscribers/:subscriber_id/unsubscribe",
+            post(routes::manually_unsubscribe_subscriber),
+        )
+        .route(
+            "/subscribers/:subscriber_id/delete",
+            post(routes::delete_subscriber),
+        )
+        .route(
+            "/subscribers/:subscriber_id/erase",
+            post(routes::erase_subscriber_data),
+        )
+        .route("/deliveries/requeue", post(routes::requeue_delivery))
         .route("/logout", post(routes::log_out))
         .layer(from_fn(reject_anonymous_users));
 
     let with_state = Router::new()
         .route("/", get(routes::home))
         .route("/login", get(routes::login_form).post(routes::login))
-        .route("/subscriptions", post(routes::subscribe))
+        .route(
+            "/subscriptions",
+            get(routes::subscribe_form).post(routes::subscribe),
+        )
+        .route(
+            "/subscriptions/challenge",
+            get(routes::subscription_challenge),
+        )
         .route("/subscriptions/confirm", get(routes::confirm))
+        .route(
+            "/subscriptions/unsubscribe",
+            get(routes::unsubscribe_form).post(routes::unsubscribe),
+        )
+        .route(
+            "/subscriptions/preferences",
+            get(routes::preferences_form).post(routes::update_preferences),
+        )
+        .route(
+            "/subscriptions/data-request",
+            get(routes::data_request_form).post(routes::request_data),
+        )
+        .route("/subscriptions/data", get(routes::export_subscriber_data))
+        .route(
+            "/subscriptions/erase",
+            get(routes::erase_form).post(routes::erase),
+        )
         .route("/newsletters", post(routes::publish_newsletter))
+        .route("/webhooks/email-events", post(routes::receive_email_events))
+        .route("/t/o/:token", get(routes::track_open))
+        .route("/t/c/:token", get(routes::track_click))
+        .route(
+            "/newsletters/:issue_id/deliveries",
+            get(routes::newsletter_delivery_report),
+        )
+        .route(
+            "/lists/:list/segments",
+            get(routes::list_segments_of_list).post(routes::create_segment),
+        )
+        .route(
+            "/lists/:list/segments/preview",
+            post(routes::preview_segment),
+        )
+        .route("/lists/:list/tags", post(routes::tag_list_subscribers))
         .nest("/admin", admin_routes)
         .with_state(shared_state);
 
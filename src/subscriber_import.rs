@@ -0,0 +1,878 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::PathBuf,
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::Secret;
+use sqlx::{PgPool, Postgres, Transaction};
+use tokio::{io::AsyncRead, sync::mpsc};
+use tokio_util::io::SyncIoBridge;
+use uuid::Uuid;
+
+use crate::{
+    confirmation_email_queue::enqueue_confirmation_emails,
+    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
+    routes::{erased_email_hash, generate_subscription_token, MailingList},
+    spreadsheet,
+};
+
+/// How many rows are saved per transaction.
+pub const BATCH_SIZE: usize = 500;
+
+/// What the subscribers of an import are added to their list as.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ImportStatus {
+    /// They already agreed to receive the list, e.g. with the provider we are
+    /// migrating from. `consent_source` records where.
+    Confirmed { consent_source: String },
+    /// They are sent a confirmation email, like people who subscribe through
+    /// the form.
+    PendingConfirmation,
+}
+
+impl ImportStatus {
+    pub fn parse(status: &str, consent_source: Option<&str>) -> Result<ImportStatus, String> {
+        match status {
+            "confirmed" => match consent_source.map(str::trim) {
+                Some(consent_source) if !consent_source.is_empty() => Ok(ImportStatus::Confirmed {
+                    consent_source: consent_source.into(),
+                }),
+                _ => Err("Importing confirmed subscribers requires a consent source".into()),
+            },
+            "pending_confirmation" => Ok(ImportStatus::PendingConfirmation),
+            _ => Err(format!("{status} is not a valid import status")),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportStatus::Confirmed { .. } => "confirmed",
+            ImportStatus::PendingConfirmation => "pending_confirmation",
+        }
+    }
+
+    fn consent_source(&self) -> Option<&str> {
+        match self {
+            ImportStatus::Confirmed { consent_source } => Some(consent_source),
+            ImportStatus::PendingConfirmation => None,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImportError {
+    /// The file can't be read as a CSV file with `email` and `name` columns.
+    /// Rows imported before the problem was found are kept.
+    #[error("{0}")]
+    InvalidFile(String),
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+pub struct SubscriberImport {
+    pub import_id: Uuid,
+    pub list_id: Uuid,
+    pub list_name: String,
+    pub file_name: String,
+    pub status: String,
+    pub consent_source: Option<String>,
+    pub n_imported: i32,
+    pub n_duplicates: i32,
+    pub n_rejected: i32,
+    pub started_at: DateTime<Utc>,
+    /// `None` while the import is running.
+    pub finished_at: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+}
+
+/// A row that wasn't imported, and why.
+#[derive(Debug, PartialEq)]
+pub struct RejectedRow {
+    /// Where the row starts in the file, counting the header as line 1.
+    pub line_number: i64,
+    pub email: String,
+    pub name: String,
+    pub reason: String,
+}
+
+/// A row of the file, as read.
+#[derive(Debug, PartialEq)]
+struct Row {
+    line_number: i64,
+    email: String,
+    name: String,
+}
+
+#[derive(Debug)]
+enum Record {
+    Row(Row),
+    Unreadable(RejectedRow),
+}
+
+/// What to do with a row.
+enum Validated {
+    New(i64, NewSubscriber),
+    /// The address appears earlier in the file.
+    Duplicate,
+    Rejected(RejectedRow),
+}
+
+/// Validate a row, remembering the addresses seen so far in `seen`.
+fn validate_row(row: Row, seen: &mut HashSet<String>) -> Validated {
+    let reject = |reason: String| {
+        Validated::Rejected(RejectedRow {
+            line_number: row.line_number,
+            email: row.email.clone(),
+            name: row.name.clone(),
+            reason,
+        })
+    };
+    let email = match SubscriberEmail::parse(row.email.clone()) {
+        Ok(email) => email,
+        Err(e) => return reject(e),
+    };
+    let name = match SubscriberName::parse(row.name.clone()) {
+        Ok(name) => name,
+        Err(e) => return reject(e),
+    };
+    if !seen.insert(email.as_ref().to_owned()) {
+        return Validated::Duplicate;
+    }
+
+    Validated::New(row.line_number, NewSubscriber { email, name })
+}
+
+/// The position of the columns we need in the header of the file.
+struct Columns {
+    email: usize,
+    name: usize,
+}
+
+impl Columns {
+    fn from_headers(headers: &csv::StringRecord) -> Result<Columns, String> {
+        let position = |column: &str| {
+            headers
+                .iter()
+                .position(|header| header.trim().eq_ignore_ascii_case(column))
+                .ok_or_else(|| format!("The file has no `{column}` column"))
+        };
+        Ok(Columns {
+            email: position("email")?,
+            name: position("name")?,
+        })
+    }
+
+    fn row(&self, record: &csv::StringRecord) -> Row {
+        let field = |i| record.get(i).unwrap_or_default().trim().to_owned();
+        Row {
+            line_number: record.position().map_or(0, |p| p.line() as i64),
+            email: field(self.email),
+            name: field(self.name),
+        }
+    }
+}
+
+/// Parse a CSV file, sending its rows one by one so that the whole file never
+/// needs to be held in memory. Stops early if the receiver goes away.
+fn read_records(reader: impl io::Read, records: mpsc::Sender<Result<Record, ImportError>>) {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(reader);
+    let columns = match reader.headers() {
+        Ok(headers) => Columns::from_headers(headers).map_err(ImportError::InvalidFile),
+        Err(e) => Err(invalid_file(e)),
+    };
+    let columns = match columns {
+        Ok(columns) => columns,
+        Err(e) => {
+            let _ = records.blocking_send(Err(e));
+            return;
+        }
+    };
+
+    let mut record = csv::StringRecord::new();
+    loop {
+        let next = match reader.read_record(&mut record) {
+            Ok(false) => return,
+            Ok(true) => Ok(Record::Row(columns.row(&record))),
+            // The rest of the file can still be read.
+            Err(e) if matches!(e.kind(), csv::ErrorKind::Utf8 { .. }) => {
+                Ok(Record::Unreadable(RejectedRow {
+                    line_number: e.position().map_or(0, |p| p.line() as i64),
+                    email: String::new(),
+                    name: String::new(),
+                    reason: "The row is not valid UTF-8".into(),
+                }))
+            }
+            Err(e) => Err(invalid_file(e)),
+        };
+        let failed = next.is_err();
+        if records.blocking_send(next).is_err() || failed {
+            return;
+        }
+    }
+}
+
+fn invalid_file(e: csv::Error) -> ImportError {
+    ImportError::InvalidFile(format!("The file could not be read: {e}"))
+}
+
+/// The rows waiting to be saved.
+#[derive(Default)]
+struct Batch {
+    /// With the line they are on.
+    subscribers: Vec<(i64, NewSubscriber)>,
+    rejected: Vec<RejectedRow>,
+    n_duplicates: i32,
+}
+
+impl Batch {
+    fn len(&self) -> usize {
+        self.subscribers.len() + self.rejected.len()
+    }
+}
+
+/// Import the subscribers listed in a CSV file into `list`, see
+/// [`run_import`]. Returns the id of the import. The import is recorded even
+/// if it fails half way through.
+pub async fn import_subscribers<R>(
+    pool: &PgPool,
+    list: &MailingList,
+    status: &ImportStatus,
+    file_name: &str,
+    reader: R,
+    hmac_secret: &Secret<String>,
+) -> Result<Uuid, ImportError>
+where
+    R: AsyncRead + Send + Unpin + 'static,
+{
+    let import_id = create_import(pool, list, status, file_name).await?;
+    run_import(pool, import_id, list, status, reader, hmac_secret).await?;
+
+    Ok(import_id)
+}
+
+/// Record an import of `file_name` that is about to start, returning its id.
+#[tracing::instrument(skip(pool, list), fields(list = %list.slug))]
+pub async fn create_import(
+    pool: &PgPool,
+    list: &MailingList,
+    status: &ImportStatus,
+    file_name: &str,
+) -> Result<Uuid, anyhow::Error> {
+    let import_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_imports (import_id, list_id, file_name, status, consent_source)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        import_id,
+        list.list_id,
+        file_name,
+        status.as_str(),
+        status.consent_source()
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the import")?;
+
+    Ok(import_id)
+}
+
+/// Import the subscribers listed in a CSV file with `email` and `name`
+/// columns into `list`, streaming the file and saving it in batches of
+/// [`BATCH_SIZE`] rows.
+///
+/// Addresses that are already on the list, or that appear more than once in
+/// the file, are skipped and the existing subscriptions are left untouched.
+/// Addresses of subscribers who had their data erased are rejected, which is
+/// looked up with `hmac_secret`, see [`erased_email_hash`].
+/// Rows that don't pass validation are rejected and recorded with the reason,
+/// see [`get_rejected_rows`]. The confirmation emails of pending subscribers
+/// are queued along with their batch, for the background worker to send.
+///
+/// The import is marked as finished when this returns, with the error if
+/// there is one.
+#[tracing::instrument(skip(pool, list, reader, hmac_secret), fields(list = %list.slug))]
+pub async fn run_import<R>(
+    pool: &PgPool,
+    import_id: Uuid,
+    list: &MailingList,
+    status: &ImportStatus,
+    reader: R,
+    hmac_secret: &Secret<String>,
+) -> Result<(), ImportError>
+where
+    R: AsyncRead + Send + Unpin + 'static,
+{
+    let (sender, mut records) = mpsc::channel(BATCH_SIZE);
+    let reader = SyncIoBridge::new(reader);
+    let parser = tokio::task::spawn_blocking(move || read_records(reader, sender));
+
+    let outcome = async {
+        let mut seen = HashSet::new();
+        let mut batch = Batch::default();
+        while let Some(record) = records.recv().await {
+            match record? {
+                Record::Row(row) => match validate_row(row, &mut seen) {
+                    Validated::New(line_number, subscriber) => {
+                        batch.subscribers.push((line_number, subscriber))
+                    }
+                    Validated::Duplicate => batch.n_duplicates += 1,
+                    Validated::Rejected(row) => batch.rejected.push(row),
+                },
+                Record::Unreadable(row) => batch.rejected.push(row),
+            }
+            if batch.len() >= BATCH_SIZE {
+                let full = std::mem::take(&mut batch);
+                save_batch(pool, import_id, list, status, full, hmac_secret).await?;
+            }
+        }
+        save_batch(pool, import_id, list, status, batch, hmac_secret).await
+    }
+    .await;
+    // Stop reading if the import failed before the end of the file.
+    drop(records);
+    parser.await.context("Failed to read the file to the end")?;
+
+    let error = outcome.as_ref().err().map(ToString::to_string);
+    finish_import(pool, import_id, error.as_deref()).await?;
+
+    outcome
+}
+
+/// Mark an import as finished, with the error that stopped it if any.
+pub async fn finish_import(
+    pool: &PgPool,
+    import_id: Uuid,
+    error: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "UPDATE subscriber_imports SET finished_at = now(), error = $2 WHERE import_id = $1",
+        import_id,
+        error
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the end of the import")?;
+
+    Ok(())
+}
+
+/// Where an uploaded file is kept until its import has run.
+pub fn upload_path(import_id: Uuid) -> PathBuf {
+    std::env::temp_dir().join(format!("subscriber-import-{import_id}.csv"))
+}
+
+/// Mark the imports that were still running when the process stopped as
+/// failed and delete their uploads, since nothing will finish them.
+///
+/// Meant to be called on startup, before any import is started. Imports are
+/// run by the process that received them, so this assumes there is a single
+/// one.
+#[tracing::instrument(skip(pool))]
+pub async fn fail_interrupted_imports(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let interrupted = sqlx::query_scalar!(
+        r#"
+        UPDATE subscriber_imports
+        SET
+            finished_at = now(),
+            error = 'The import was interrupted by a restart'
+        WHERE finished_at IS NULL
+        RETURNING import_id
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to mark interrupted imports as failed")?;
+    for import_id in interrupted {
+        tracing::warn!(%import_id, "An import was interrupted by a restart");
+        match tokio::fs::remove_file(upload_path(import_id)).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!(error.message = %e, "Failed to delete an interrupted upload"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Save a batch of rows in a single transaction, along with the confirmation
+/// emails of the pending subscribers it added.
+async fn save_batch(
+    pool: &PgPool,
+    import_id: Uuid,
+    list: &MailingList,
+    status: &ImportStatus,
+    mut batch: Batch,
+    hmac_secret: &Secret<String>,
+) -> Result<(), ImportError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let erased = find_erased(&mut transaction, &batch.subscribers, hmac_secret)
+        .await
+        .context("Failed to look up erased subscribers")?;
+    let (subscribers, erased): (Vec<_>, Vec<_>) = batch
+        .subscribers
+        .into_iter()
+        .partition(|(_, subscriber)| !erased.contains(subscriber.email.as_ref()));
+    let subscribers: Vec<_> = subscribers.into_iter().map(|(_, s)| s).collect();
+    // Only the line is recorded, so that the import doesn't keep the data
+    // the subscriber asked us to erase.
+    batch
+        .rejected
+        .extend(erased.into_iter().map(|(line_number, _)| RejectedRow {
+            line_number,
+            email: String::new(),
+            name: String::new(),
+            reason: "The subscriber asked for their data to be erased".into(),
+        }));
+    let added = insert_subscribers(&mut transaction, list.list_id, status, &subscribers)
+        .await
+        .context("Failed to insert a batch of subscribers")?;
+    if let ImportStatus::PendingConfirmation = status {
+        let tokens: Vec<_> = added
+            .iter()
+            .map(|_| generate_subscription_token())
+            .collect();
+        sqlx::query!(
+            r#"
+            INSERT INTO subscription_tokens (subscription_token, subscriber_id, list_id)
+            SELECT token, subscriber_id, $3
+            FROM UNNEST($1::text[], $2::uuid[]) AS batch(token, subscriber_id)
+            "#,
+            &tokens,
+            &added.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            list.list_id
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to store the confirmation tokens")?;
+        enqueue_confirmation_emails(&mut transaction, &tokens)
+            .await
+            .context("Failed to queue the confirmation emails")?;
+    }
+    insert_rejected_rows(&mut transaction, import_id, &batch.rejected)
+        .await
+        .context("Failed to record the rejected rows")?;
+    let n_imported = added.len() as i32;
+    let n_duplicates = batch.n_duplicates + subscribers.len() as i32 - n_imported;
+    sqlx::query!(
+        r#"
+        UPDATE subscriber_imports
+        SET
+            n_imported = n_imported + $2,
+            n_duplicates = n_duplicates + $3,
+            n_rejected = n_rejected + $4
+        WHERE import_id = $1
+        "#,
+        import_id,
+        n_imported,
+        n_duplicates,
+        batch.rejected.len() as i32
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update the progress of the import")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a batch of subscribers")?;
+
+    Ok(())
+}
+
+/// The addresses of the subscribers that were erased at their request.
+async fn find_erased(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscribers: &[(i64, NewSubscriber)],
+    hmac_secret: &Secret<String>,
+) -> Result<HashSet<String>, sqlx::Error> {
+    let hashes: HashMap<String, &str> = subscribers
+        .iter()
+        .map(|(_, s)| {
+            let hash = erased_email_hash(s.email.as_ref(), hmac_secret);
+            (hash, s.email.as_ref())
+        })
+        .collect();
+    let erased = sqlx::query_scalar!(
+        "SELECT email_hash FROM erased_subscribers WHERE email_hash = ANY($1)",
+        &hashes.keys().cloned().collect::<Vec<_>>()
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+
+    Ok(erased
+        .iter()
+        .filter_map(|hash| hashes.get(hash))
+        .map(|email| email.to_string())
+        .collect())
+}
+
+/// Add the subscribers to the list, creating the ones we don't know yet.
+/// Returns the id and address of the subscribers who weren't on the list.
+async fn insert_subscribers(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    status: &ImportStatus,
+    subscribers: &[NewSubscriber],
+) -> Result<Vec<(Uuid, String)>, sqlx::Error> {
+    let emails: Vec<String> = subscribers
+        .iter()
+        .map(|s| s.email.as_ref().to_owned())
+        .collect();
+    let names: Vec<String> = subscribers
+        .iter()
+        .map(|s| s.name.as_ref().to_owned())
+        .collect();
+    let ids: Vec<Uuid> = subscribers.iter().map(|_| Uuid::new_v4()).collect();
+    let now = Utc::now();
+    let confirmed_at = match status {
+        ImportStatus::Confirmed { .. } => Some(now),
+        ImportStatus::PendingConfirmation => None,
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status, confirmed_at)
+        SELECT id, email, name, $4, $5, $6
+        FROM UNNEST($1::uuid[], $2::text[], $3::text[]) AS batch(id, email, name)
+        ON CONFLICT (email) DO NOTHING
+        "#,
+        &ids,
+        &emails,
+        &names,
+        now,
+        status.as_str(),
+        confirmed_at
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    let rows = sqlx::query!(
+        r#"
+        WITH added AS (
+            INSERT INTO list_subscriptions (list_id, subscriber_id, status, consent_source)
+            SELECT $1, id, $3, $4 FROM subscriptions WHERE email = ANY($2)
+            ON CONFLICT (list_id, subscriber_id) DO NOTHING
+            RETURNING subscriber_id
+        )
+        SELECT subscriptions.id, subscriptions.email
+        FROM added
+        JOIN subscriptions ON subscriptions.id = added.subscriber_id
+        "#,
+        list_id,
+        &emails,
+        status.as_str(),
+        status.consent_source()
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| (row.id, row.email)).collect())
+}
+
+async fn insert_rejected_rows(
+    transaction: &mut Transaction<'_, Postgres>,
+    import_id: Uuid,
+    rows: &[RejectedRow],
+) -> Result<(), sqlx::Error> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_import_rejections (import_id, line_number, email, name, reason)
+        SELECT $1, *
+        FROM UNNEST($2::bigint[], $3::text[], $4::text[], $5::text[])
+        "#,
+        import_id,
+        &rows.iter().map(|r| r.line_number).collect::<Vec<_>>(),
+        &rows.iter().map(|r| r.email.clone()).collect::<Vec<_>>(),
+        &rows.iter().map(|r| r.name.clone()).collect::<Vec<_>>(),
+        &rows.iter().map(|r| r.reason.clone()).collect::<Vec<_>>()
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_import(
+    pool: &PgPool,
+    import_id: Uuid,
+) -> Result<Option<SubscriberImport>, sqlx::Error> {
+    sqlx::query_as!(
+        SubscriberImport,
+        r#"
+        SELECT
+            subscriber_imports.import_id,
+            subscriber_imports.list_id,
+            lists.name AS list_name,
+            subscriber_imports.file_name,
+            subscriber_imports.status,
+            subscriber_imports.consent_source,
+            subscriber_imports.n_imported,
+            subscriber_imports.n_duplicates,
+            subscriber_imports.n_rejected,
+            subscriber_imports.started_at,
+            subscriber_imports.finished_at,
+            subscriber_imports.error
+        FROM subscriber_imports
+        JOIN lists ON lists.list_id = subscriber_imports.list_id
+        WHERE import_id = $1
+        "#,
+        import_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// The most recent imports, newest first.
+#[tracing::instrument(skip(pool))]
+pub async fn recent_imports(pool: &PgPool) -> Result<Vec<SubscriberImport>, sqlx::Error> {
+    sqlx::query_as!(
+        SubscriberImport,
+        r#"
+        SELECT
+            subscriber_imports.import_id,
+            subscriber_imports.list_id,
+            lists.name AS list_name,
+            subscriber_imports.file_name,
+            subscriber_imports.status,
+            subscriber_imports.consent_source,
+            subscriber_imports.n_imported,
+            subscriber_imports.n_duplicates,
+            subscriber_imports.n_rejected,
+            subscriber_imports.started_at,
+            subscriber_imports.finished_at,
+            subscriber_imports.error
+        FROM subscriber_imports
+        JOIN lists ON lists.list_id = subscriber_imports.list_id
+        ORDER BY started_at DESC
+        LIMIT 20
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// The rows an import rejected, in the order of the file, up to `limit` rows
+/// if there is one.
+#[tracing::instrument(skip(pool))]
+pub async fn get_rejected_rows(
+    pool: &PgPool,
+    import_id: Uuid,
+    limit: Option<i64>,
+) -> Result<Vec<RejectedRow>, sqlx::Error> {
+    sqlx::query_as!(
+        RejectedRow,
+        r#"
+        SELECT line_number, email, name, reason
+        FROM subscriber_import_rejections
+        WHERE import_id = $1
+        ORDER BY line_number
+        LIMIT $2
+        "#,
+        import_id,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// How many rejected rows of an import are kept. Fewer than the import
+/// rejected if some of them were erased since.
+#[tracing::instrument(skip(pool))]
+pub async fn count_rejected_rows(pool: &PgPool, import_id: Uuid) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT count(*) AS "count!" FROM subscriber_import_rejections WHERE import_id = $1"#,
+        import_id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Write the report of rejected rows as CSV, with the line of the file each
+/// row came from and the reason it was rejected. The cells of the file are
+/// written back [escaped](spreadsheet::escape_cell).
+pub fn write_rejection_report(
+    rows: &[RejectedRow],
+    writer: impl io::Write,
+) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["line", "email", "name", "reason"])?;
+    for row in rows {
+        writer.write_record([
+            row.line_number.to_string().as_str(),
+            &spreadsheet::escape_cell(&row.email),
+            &spreadsheet::escape_cell(&row.name),
+            &spreadsheet::escape_cell(&row.reason),
+        ])?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        read_records, validate_row, write_rejection_report, ImportError, ImportStatus, Record,
+        RejectedRow, Row, Validated,
+    };
+    use claims::{assert_err, assert_matches};
+    use std::collections::HashSet;
+    use tokio::sync::mpsc;
+
+    fn read(file: &[u8]) -> Vec<Result<Record, ImportError>> {
+        let (sender, mut receiver) = mpsc::channel(100);
+        read_records(file, sender);
+        let mut records = vec![];
+        while let Ok(record) = receiver.try_recv() {
+            records.push(record);
+        }
+        records
+    }
+
+    fn row(line_number: i64, email: &str, name: &str) -> Row {
+        Row {
+            line_number,
+            email: email.into(),
+            name: name.into(),
+        }
+    }
+
+    #[test]
+    fn rows_are_read_by_column_name() {
+        let records = read(
+            b"Name,Email,Source\nUrsula, ursula@example.com ,old\nLe Guin,guin@example.com,old\n",
+        );
+
+        let rows: Vec<_> = records
+            .into_iter()
+            .map(|r| match r {
+                Ok(Record::Row(row)) => row,
+                _ => panic!("Expected a row"),
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                row(2, "ursula@example.com", "Ursula"),
+                row(3, "guin@example.com", "Le Guin")
+            ]
+        );
+    }
+
+    #[test]
+    fn quoted_fields_can_span_lines() {
+        let records = read(b"email,name\n\"a@example.com\",\"A\nB\"\nc@example.com,C\n");
+
+        assert_matches!(&records[1], Ok(Record::Row(row)) if row.line_number == 4);
+    }
+
+    #[test]
+    fn a_file_without_an_email_column_is_invalid() {
+        let records = read(b"address,name\nursula@example.com,Ursula\n");
+
+        assert_eq!(records.len(), 1);
+        assert_matches!(&records[0], Err(ImportError::InvalidFile(_)));
+    }
+
+    #[test]
+    fn rows_that_are_not_utf8_are_rejected_and_reading_goes_on() {
+        let records = read(b"email,name\n\xff@example.com,Ursula\nguin@example.com,Le Guin\n");
+
+        assert_matches!(&records[0], Ok(Record::Unreadable(row)) if row.line_number == 2);
+        assert_matches!(&records[1], Ok(Record::Row(_)));
+    }
+
+    #[test]
+    fn invalid_rows_are_rejected_with_a_reason() {
+        let mut seen = HashSet::new();
+
+        assert!(matches!(
+            validate_row(row(2, "not-an-email", "Ursula"), &mut seen),
+            Validated::Rejected(RejectedRow { line_number: 2, .. })
+        ));
+        assert!(matches!(
+            validate_row(row(3, "ursula@example.com", ""), &mut seen),
+            Validated::Rejected(RejectedRow { line_number: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn addresses_repeated_in_the_file_are_duplicates() {
+        let mut seen = HashSet::new();
+
+        assert!(matches!(
+            validate_row(row(2, "ursula@example.com", "Ursula"), &mut seen),
+            Validated::New(2, _)
+        ));
+        assert!(matches!(
+            validate_row(row(3, "ursula@example.com", "Ursula"), &mut seen),
+            Validated::Duplicate
+        ));
+    }
+
+    #[test]
+    fn confirmed_imports_require_a_consent_source() {
+        assert_err!(ImportStatus::parse("confirmed", None));
+        assert_err!(ImportStatus::parse("confirmed", Some("  ")));
+        assert_eq!(
+            ImportStatus::parse("confirmed", Some("Signup form on the old site")),
+            Ok(ImportStatus::Confirmed {
+                consent_source: "Signup form on the old site".into()
+            })
+        );
+        assert_eq!(
+            ImportStatus::parse("pending_confirmation", None),
+            Ok(ImportStatus::PendingConfirmation)
+        );
+        assert_err!(ImportStatus::parse("unsubscribed", None));
+    }
+
+    #[test]
+    fn the_rejection_report_is_csv() {
+        let mut report = vec![];
+        write_rejection_report(
+            &[RejectedRow {
+                line_number: 7,
+                email: "ursula".into(),
+                name: "Ursula, Le Guin".into(),
+                reason: "ursula is not a valid email".into(),
+            }],
+            &mut report,
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "line,email,name,reason\n7,ursula,\"Ursula, Le Guin\",ursula is not a valid email\n"
+        );
+    }
+
+    #[test]
+    fn formulas_in_the_rejection_report_are_escaped() {
+        let mut report = vec![];
+        write_rejection_report(
+            &[RejectedRow {
+                line_number: 3,
+                email: "=HYPERLINK(\"https://evil.example\")".into(),
+                name: "@SUM(A1)".into(),
+                reason: "not a valid email".into(),
+            }],
+            &mut report,
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "line,email,name,reason\n3,\"'=HYPERLINK(\"\"https://evil.example\"\")\",'@SUM(A1),not a valid email\n"
+        );
+    }
+}
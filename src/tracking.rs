@@ -0,0 +1,114 @@
+use std::sync::OnceLock;
+
+use regex::{Captures, Regex};
+use secrecy::Secret;
+use uuid::Uuid;
+
+use crate::domain::TrackingToken;
+
+/// Prepare the HTML body of a newsletter issue for a single subscriber so that
+/// opens and clicks can be recorded.
+///
+/// Every `http(s)` link is pointed at `/t/c/{token}`, which redirects to the
+/// original URL, and a `/t/o/{token}.gif` pixel is appended to the body.
+pub fn add_tracking(
+    html: &str,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    base_url: &str,
+    secret: &Secret<String>,
+) -> String {
+    static LINK: OnceLock<Regex> = OnceLock::new();
+    let link = LINK.get_or_init(|| {
+        Regex::new(r#"(?i)(<a\s[^>]*?\bhref\s*=\s*)(?:"(https?://[^"]*)"|'(https?://[^']*)')"#)
+            .expect("The link pattern is valid")
+    });
+
+    let mut tracked = link
+        .replace_all(html, |caps: &Captures| {
+            let url = caps
+                .get(2)
+                .or_else(|| caps.get(3))
+                .expect("One of the alternatives matched")
+                .as_str()
+                .replace("&amp;", "&");
+            let token = TrackingToken::click(newsletter_issue_id, subscriber_id, &url, secret);
+            format!("{}\"{base_url}/t/c/{}\"", &caps[1], token.as_ref())
+        })
+        .into_owned();
+
+    let token = TrackingToken::open(newsletter_issue_id, subscriber_id, secret);
+    tracked.push_str(&format!(
+        r#"<img src="{base_url}/t/o/{}.gif" width="1" height="1" alt="" />"#,
+        token.as_ref()
+    ));
+    tracked
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    use super::add_tracking;
+    use crate::domain::TrackingToken;
+
+    const BASE_URL: &str = "https://newsletter.example.com";
+
+    fn secret() -> Secret<String> {
+        Secret::new("super-secret".to_string())
+    }
+
+    fn track(html: &str) -> String {
+        add_tracking(html, Uuid::new_v4(), Uuid::new_v4(), BASE_URL, &secret())
+    }
+
+    /// The URLs behind the tracked links of `html`, in order.
+    fn tracked_urls(html: &str) -> Vec<String> {
+        html.split(&format!("\"{BASE_URL}/t/c/"))
+            .skip(1)
+            .map(|rest| {
+                let token = rest.split('"').next().unwrap();
+                TrackingToken::parse(token, &secret()).unwrap().url.unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn links_are_rewritten_to_the_click_endpoint() {
+        let html = track(
+            r#"<p><a href="https://example.com/a?b=c&amp;d=e">One</a> and <A class='x' HREF='http://example.org'>two</A></p>"#,
+        );
+
+        assert_eq!(
+            tracked_urls(&html),
+            ["https://example.com/a?b=c&d=e", "http://example.org"]
+        );
+    }
+
+    #[test]
+    fn other_links_are_left_alone() {
+        let html =
+            track(r##"<a href="mailto:editor@example.com">Mail</a> <a href="#top">Top</a>"##);
+
+        assert!(html.contains(r#"href="mailto:editor@example.com""#));
+        assert!(html.contains(r##"href="#top""##));
+    }
+
+    #[test]
+    fn a_tracking_pixel_is_appended() {
+        let issue_id = Uuid::new_v4();
+        let subscriber_id = Uuid::new_v4();
+        let html = add_tracking("<p>Hi</p>", issue_id, subscriber_id, BASE_URL, &secret());
+
+        let src = html
+            .split(&format!(r#"<img src="{BASE_URL}/t/o/"#))
+            .nth(1)
+            .expect("No tracking pixel");
+        let token = src.split(".gif").next().unwrap();
+        let recipient = TrackingToken::parse(token, &secret()).unwrap();
+        assert_eq!(recipient.newsletter_issue_id, issue_id);
+        assert_eq!(recipient.subscriber_id, subscriber_id);
+        assert!(html.starts_with("<p>Hi</p>"));
+    }
+}
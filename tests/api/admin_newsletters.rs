@@ -0,0 +1,288 @@
+use hyper::StatusCode;
+use uuid::Uuid;
+
+use crate::helpers::*;
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_newsletter_form() {
+    let app = spawn_app().await;
+
+    let response = app.get_publish_newsletter().await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_publish_a_newsletter_from_the_form() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "text_content": "Newsletter body as plain text",
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn newsletters_published_from_the_form_are_delivered_to_confirmed_subscribers() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.email_server.lock().unwrap().sends.clear();
+    app.log_in().await;
+
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "text_content": "",
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains("<p><i>The newsletter issue has been accepted"));
+
+    app.dispatch_all_pending_emails().await;
+    let email_server = app.email_server.lock().unwrap();
+    assert_eq!(email_server.sends.len(), 1);
+    assert_eq!(email_server.sends[0].subject, "Newsletter title");
+    assert!(email_server.sends[0]
+        .text_content
+        .contains("Newsletter body as HTML"));
+}
+
+#[tokio::test]
+async fn submitting_the_form_twice_publishes_the_issue_once() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.email_server.lock().unwrap().sends.clear();
+    app.log_in().await;
+    let body = serde_json::json!({
+        "title": "Newsletter title",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "text_content": "Newsletter body as plain text",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    });
+
+    let response = app.post_publish_newsletter(&body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let response = app.post_publish_newsletter(&body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(app.email_server.lock().unwrap().sends.len(), 1);
+}
+
+#[tokio::test]
+async fn a_missing_title_is_reported_in_a_flash_message() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.email_server.lock().unwrap().sends.clear();
+    app.log_in().await;
+
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "  ",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "text_content": "",
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains("<p><i>The newsletter issue needs a title.</i></p>"));
+    app.dispatch_all_pending_emails().await;
+    assert!(app.email_server.lock().unwrap().sends.is_empty());
+}
+
+#[tokio::test]
+async fn unknown_placeholders_are_reported_in_a_flash_message() {
+    let app = spawn_logged_in_app_with_a_subscriber().await;
+
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "html_content": "<p>Hi {{ first_name }}</p>",
+            "text_content": "",
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains("Unknown placeholder(s): {{ first_name }}."));
+    app.dispatch_all_pending_emails().await;
+    assert!(app.email_server.lock().unwrap().sends.is_empty());
+}
+
+async fn spawn_logged_in_app_with_a_subscriber() -> TestApp {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.email_server.lock().unwrap().sends.clear();
+    app.log_in().await;
+    app
+}
+
+async fn save_draft(app: &TestApp) -> Uuid {
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Draft title",
+            "html_content": "<p>Draft body as HTML</p>",
+            "text_content": "Draft body as plain text",
+            "idempotency_key": Uuid::new_v4().to_string(),
+            "action": "save_draft",
+        }))
+        .await;
+    let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{issue_id}"));
+
+    issue_id
+}
+
+#[tokio::test]
+async fn saving_a_draft_does_not_deliver_it() {
+    let app = spawn_logged_in_app_with_a_subscriber().await;
+
+    let issue_id = save_draft(&app).await;
+
+    let html_page = app
+        .get_html(&format!("/admin/newsletters/{issue_id}"))
+        .await;
+    assert!(html_page.contains("<p><i>The draft has been saved.</i></p>"));
+    assert!(html_page.contains("Status: draft"));
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains("Draft title"));
+    app.dispatch_all_pending_emails().await;
+    assert!(app.email_server.lock().unwrap().sends.is_empty());
+}
+
+#[tokio::test]
+async fn an_edited_draft_is_delivered_once_published() {
+    let app = spawn_logged_in_app_with_a_subscriber().await;
+    let issue_id = save_draft(&app).await;
+    let issue_url = format!("{}/admin/newsletters/{issue_id}", app.address);
+
+    let response = app
+        .post_form(
+            &issue_url,
+            &serde_json::json!({
+                "title": "Edited title",
+                "html_content": "<p>Edited body</p>",
+                "text_content": "",
+                "action": "publish",
+            }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    app.dispatch_all_pending_emails().await;
+    {
+        let email_server = app.email_server.lock().unwrap();
+        assert_eq!(email_server.sends.len(), 1);
+        assert_eq!(email_server.sends[0].subject, "Edited title");
+        assert!(email_server.sends[0].text_content.contains("Edited body"));
+    }
+
+    let response = app
+        .post_form(
+            &issue_url,
+            &serde_json::json!({
+                "title": "Edited again",
+                "html_content": "<p>Edited body</p>",
+                "text_content": "",
+                "action": "publish",
+            }),
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{issue_id}"));
+    let html_page = app
+        .get_html(&format!("/admin/newsletters/{issue_id}"))
+        .await;
+    assert!(html_page.contains("<p><i>Only drafts can be edited.</i></p>"));
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(app.email_server.lock().unwrap().sends.len(), 1);
+}
+
+#[tokio::test]
+async fn the_preview_renders_the_issue_as_an_email() {
+    let app = spawn_logged_in_app_with_a_subscriber().await;
+    let issue_id = save_draft(&app).await;
+
+    let response = app
+        .get(&format!(
+            "{}/admin/newsletters/{issue_id}/preview",
+            app.address
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("<p>Draft body as HTML</p>"));
+    assert!(html.contains("Unsubscribe"));
+
+    let response = app
+        .get(&format!(
+            "{}/admin/newsletters/{issue_id}/preview?format=text",
+            app.address
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let text = response.text().await.unwrap();
+    assert!(text.contains("Draft body as plain text"));
+    assert!(!text.contains('<'));
+}
+
+#[tokio::test]
+async fn a_test_copy_goes_only_to_the_chosen_address() {
+    let app = spawn_logged_in_app_with_a_subscriber().await;
+    let issue_id = save_draft(&app).await;
+
+    let response = app
+        .post_form(
+            &format!("{}/admin/newsletters/{issue_id}/test", app.address),
+            &serde_json::json!({ "email": "editor@example.com" }),
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{issue_id}"));
+
+    let html_page = app
+        .get_html(&format!("/admin/newsletters/{issue_id}"))
+        .await;
+    assert!(html_page.contains("A test copy has been sent to editor@example.com."));
+    {
+        let email_server = app.email_server.lock().unwrap();
+        assert_eq!(email_server.sends.len(), 1);
+        assert_eq!(
+            email_server.sends[0].recipient.as_ref(),
+            "editor@example.com"
+        );
+        assert_eq!(email_server.sends[0].subject, "Draft title");
+    }
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(app.email_server.lock().unwrap().sends.len(), 1);
+}
+
+#[tokio::test]
+async fn unknown_issues_are_not_found() {
+    let app = spawn_logged_in_app_with_a_subscriber().await;
+
+    let response = app
+        .get(&format!(
+            "{}/admin/newsletters/{}",
+            app.address,
+            Uuid::new_v4()
+        ))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
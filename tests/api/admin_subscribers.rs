@@ -0,0 +1,308 @@
+use hyper::StatusCode;
+use uuid::Uuid;
+
+use crate::helpers::*;
+
+/// The changes recorded as made by an admin, as `(field, old, new)`.
+async fn preference_changes(app: &TestApp) -> Vec<(String, String, String)> {
+    sqlx::query!(
+        r#"
+        SELECT field, old_value AS "old_value!", new_value AS "new_value!"
+        FROM preference_changes
+        WHERE changed_by = 'admin'
+        ORDER BY field
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|change| (change.field, change.old_value, change.new_value))
+    .collect()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_subscribers() {
+    let app = spawn_app().await;
+
+    let response = app.get(format!("{}/admin/subscribers", app.address)).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn subscribers_can_be_searched_and_filtered() {
+    let app = spawn_app().await;
+    app.insert_subscribers(3).await;
+    app.create_unconfirmed_subscriber().await;
+    app.log_in().await;
+
+    for (query, expected) in [
+        (
+            "",
+            vec![
+                "ursula_le_guin",
+                "subscriber00",
+                "subscriber01",
+                "subscriber02",
+            ],
+        ),
+        ("q=SUBSCRIBER%2001", vec!["subscriber01"]),
+        ("q=le_guin", vec!["ursula_le_guin"]),
+        ("q=%25", vec![]),
+        ("status=pending_confirmation", vec!["ursula_le_guin"]),
+        (
+            "status=confirmed&q=subscriber",
+            vec!["subscriber00", "subscriber01", "subscriber02"],
+        ),
+        ("to=2000-01-01", vec![]),
+        (
+            "from=2000-01-01&status=confirmed",
+            vec!["subscriber00", "subscriber01", "subscriber02"],
+        ),
+    ] {
+        let html_page = app.get_html(&format!("/admin/subscribers?{query}")).await;
+
+        let found: Vec<_> = [
+            "ursula_le_guin",
+            "subscriber00",
+            "subscriber01",
+            "subscriber02",
+        ]
+        .into_iter()
+        .filter(|email| html_page.contains(&format!(">{email}@")))
+        .collect();
+        assert_eq!(found, expected, "{query}");
+    }
+}
+
+#[tokio::test]
+async fn subscribers_are_paginated_newest_first() {
+    let app = spawn_app().await;
+    app.insert_subscribers(60).await;
+    app.log_in().await;
+
+    let first_page = app.get_html("/admin/subscribers").await;
+    assert!(first_page.contains(">subscriber59@example.com"));
+    assert!(first_page.contains(">subscriber10@example.com"));
+    assert!(!first_page.contains(">subscriber09@example.com"));
+
+    let next_link = first_page
+        .split(r#"<a href=""#)
+        .find(|part| part.contains("Next page"))
+        .and_then(|part| part.split('"').next())
+        .expect("The first page has no link to the next one")
+        .replace("&amp;", "&");
+    let second_page = app.get_html(&next_link).await;
+    assert!(second_page.contains(">subscriber09@example.com"));
+    assert!(second_page.contains(">subscriber00@example.com"));
+    assert!(!second_page.contains(">subscriber10@example.com"));
+    assert!(!second_page.contains("Next page"));
+}
+
+#[tokio::test]
+async fn the_subscriber_page_shows_lists_tokens_deliveries_and_history() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.publish_newsletter().await;
+    app.dispatch_all_pending_emails().await;
+    app.log_in().await;
+
+    let html_page = app
+        .get_html(&format!("/admin/subscribers/{}", app.subscriber_id().await))
+        .await;
+
+    assert!(html_page.contains("<h2>ursula_le_guin@gmail.com</h2>"));
+    assert!(html_page.contains(">Newsletter</a></td>"));
+    assert!(html_page.contains("<td>valid</td>"));
+    assert!(html_page.contains(">Newsletter title</a></td>"));
+    assert!(html_page.contains("<td>sent</td>"));
+    assert!(html_page.contains("<td>Signed up</td>"));
+}
+
+#[tokio::test]
+async fn an_unknown_subscriber_is_a_404() {
+    let app = spawn_app().await;
+    app.log_in().await;
+
+    let response = app
+        .get(format!(
+            "{}/admin/subscribers/{}",
+            app.address,
+            Uuid::new_v4()
+        ))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn admins_can_confirm_a_subscriber() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber().await;
+    app.log_in().await;
+    let subscriber_id = app.subscriber_id().await;
+
+    let response = app
+        .post_form(
+            format!("{}/admin/subscribers/{subscriber_id}/confirm", app.address),
+            &serde_json::json!({}),
+        )
+        .await;
+
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{subscriber_id}"));
+    let html_page = app
+        .get_html(&format!("/admin/subscribers/{subscriber_id}"))
+        .await;
+    assert!(html_page.contains("<p><i>The subscriber has been confirmed.</i></p>"));
+    let statuses = sqlx::query!(
+        r#"
+        SELECT subscriptions.status, list_subscriptions.status AS list_status
+        FROM subscriptions
+        JOIN list_subscriptions ON list_subscriptions.subscriber_id = subscriptions.id
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(statuses.status, "confirmed");
+    assert_eq!(statuses.list_status, "confirmed");
+    let changes = preference_changes(&app).await;
+    assert_eq!(
+        changes,
+        [
+            (
+                "list:newsletter".into(),
+                "pending_confirmation".into(),
+                "confirmed".into()
+            ),
+            (
+                "status".into(),
+                "pending_confirmation".into(),
+                "confirmed".into()
+            ),
+        ]
+    );
+    assert!(html_page.contains("by an admin"));
+}
+
+#[tokio::test]
+async fn confirming_a_suppressed_subscriber_keeps_them_suppressed() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber().await;
+    sqlx::query!("UPDATE subscriptions SET status = 'bounced'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.log_in().await;
+    let subscriber_id = app.subscriber_id().await;
+
+    let response = app
+        .post_form(
+            format!("{}/admin/subscribers/{subscriber_id}/confirm", app.address),
+            &serde_json::json!({}),
+        )
+        .await;
+
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{subscriber_id}"));
+    let html_page = app
+        .get_html(&format!("/admin/subscribers/{subscriber_id}"))
+        .await;
+    assert!(html_page.contains("their address bounced or complained"));
+    let status = sqlx::query_scalar!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "bounced");
+    assert!(preference_changes(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn admins_can_unsubscribe_a_subscriber() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.log_in().await;
+    let subscriber_id = app.subscriber_id().await;
+
+    let response = app
+        .post_form(
+            format!(
+                "{}/admin/subscribers/{subscriber_id}/unsubscribe",
+                app.address
+            ),
+            &serde_json::json!({}),
+        )
+        .await;
+
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{subscriber_id}"));
+    let html_page = app
+        .get_html(&format!("/admin/subscribers/{subscriber_id}"))
+        .await;
+    assert!(html_page.contains("has been unsubscribed from every list."));
+    let status = sqlx::query!("SELECT status FROM list_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "unsubscribed");
+    assert_eq!(
+        preference_changes(&app).await,
+        [(
+            "list:newsletter".into(),
+            "confirmed".into(),
+            "unsubscribed".into()
+        )]
+    );
+}
+
+#[tokio::test]
+async fn admins_can_delete_a_subscriber() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber().await;
+    app.log_in().await;
+    let subscriber_id = app.subscriber_id().await;
+
+    let response = app
+        .post_form(
+            format!("{}/admin/subscribers/{subscriber_id}/delete", app.address),
+            &serde_json::json!({}),
+        )
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    let html_page = app.get_html("/admin/subscribers").await;
+    assert!(html_page.contains("<p><i>ursula_le_guin@gmail.com has been deleted.</i></p>"));
+    let n_subscribers = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 0);
+}
+
+#[tokio::test]
+async fn admins_can_erase_the_data_of_a_subscriber() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber().await;
+    app.log_in().await;
+    let subscriber_id = app.subscriber_id().await;
+
+    let response = app
+        .post_form(
+            format!("{}/admin/subscribers/{subscriber_id}/erase", app.address),
+            &serde_json::json!({}),
+        )
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    let html_page = app.get_html("/admin/subscribers").await;
+    assert!(
+        html_page.contains("<p><i>The data of ursula_le_guin@gmail.com has been erased.</i></p>")
+    );
+    let n_tombstones = sqlx::query!(r#"SELECT count(*) AS "count!" FROM erased_subscribers"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_tombstones, 1);
+}
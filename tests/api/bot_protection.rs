@@ -0,0 +1,154 @@
+use chrono::{Duration, Utc};
+use hyper::StatusCode;
+use sha2::{Digest, Sha256};
+use zero2prod::domain::SubscribeFormToken;
+
+use crate::helpers::*;
+
+const BODY: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+async fn n_subscribers(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+/// Post the form with the given bot check fields as JSON, to get errors that
+/// name the field.
+async fn post_with(app: &TestApp, bot_check: &BotCheck) -> reqwest::Response {
+    app.post_json(
+        format!("{}/subscriptions", app.address),
+        &serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+            "form_token": bot_check.form_token,
+            "challenge": bot_check.challenge,
+            "challenge_solution": bot_check.challenge_solution,
+        }),
+    )
+    .await
+}
+
+/// A solution that doesn't have the 4 leading zero bits the tests require.
+fn wrong_solution(challenge: &str) -> String {
+    (0u64..)
+        .map(|counter| counter.to_string())
+        .find(|solution| Sha256::digest(format!("{challenge}:{solution}").as_bytes())[0] >= 0x10)
+        .unwrap()
+}
+
+async fn assert_rejected(response: reqwest::Response, field: &str) {
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let error: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(error["field"], field);
+}
+
+#[tokio::test]
+async fn the_subscription_form_embeds_a_form_token_and_a_challenge() {
+    let app = spawn_app().await;
+
+    let html_page = app.get_html("/subscriptions").await;
+
+    assert!(html_page.contains(r#"name="website""#));
+    assert!(html_page.contains(r#"name="form_token""#));
+    assert!(html_page.contains(r#"name="challenge""#));
+    assert!(html_page.contains(r#"data-difficulty="4""#));
+}
+
+#[tokio::test]
+async fn apps_can_get_a_form_token_and_a_challenge() {
+    let app = spawn_app().await;
+
+    let response = app
+        .get(format!("{}/subscriptions/challenge", app.address))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["form_token"].is_string());
+    assert_eq!(body["challenge"]["kind"], "proof_of_work");
+    assert_eq!(body["challenge"]["difficulty"], 4);
+
+    // Once the time to fill in the form has passed, the challenge can be used
+    // to subscribe.
+    let challenge = body["challenge"]["challenge"].as_str().unwrap().to_owned();
+    let bot_check = BotCheck {
+        challenge_solution: app.solve(&challenge),
+        challenge,
+        ..app.bot_check()
+    };
+    let response = post_with(&app, &bot_check).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(n_subscribers(&app).await, 1);
+}
+
+#[tokio::test]
+async fn bots_filling_in_the_honeypot_are_silently_ignored() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions(format!("{BODY}&website=https%3A%2F%2Fspam.example.com"))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(n_subscribers(&app).await, 0);
+    assert!(app.email_server.lock().unwrap().sends.is_empty());
+}
+
+#[tokio::test]
+async fn forms_without_a_valid_form_token_are_rejected() {
+    let app = spawn_app().await;
+
+    for form_token in ["", "1700000000.abcd"] {
+        let bot_check = BotCheck {
+            form_token: form_token.into(),
+            ..app.bot_check()
+        };
+        assert_rejected(post_with(&app, &bot_check).await, "form_token").await;
+    }
+    let response = app.post_subscriptions_without_bot_check(BODY.into()).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(n_subscribers(&app).await, 0);
+}
+
+#[tokio::test]
+async fn forms_submitted_too_quickly_or_too_late_are_rejected() {
+    let app = spawn_app().await;
+    let secret = &app.config.application.hmac_secret;
+
+    for issued_at in [Utc::now(), Utc::now() - Duration::hours(3)] {
+        let bot_check = BotCheck {
+            form_token: SubscribeFormToken::generate(issued_at, secret)
+                .as_ref()
+                .to_owned(),
+            ..app.bot_check()
+        };
+        assert_rejected(post_with(&app, &bot_check).await, "form_token").await;
+    }
+    assert_eq!(n_subscribers(&app).await, 0);
+}
+
+#[tokio::test]
+async fn forms_without_a_solved_challenge_are_rejected() {
+    let app = spawn_app().await;
+
+    let bot_check = app.bot_check();
+    let unsolved = app.bot_check();
+    let unsolved = BotCheck {
+        challenge_solution: wrong_solution(&unsolved.challenge),
+        ..unsolved
+    };
+    let forged = BotCheck {
+        challenge: "1700000000.abcd.abcd".into(),
+        ..app.bot_check()
+    };
+    // Each challenge can only be solved once.
+    let response = post_with(&app, &bot_check).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    for bot_check in [unsolved, forged, bot_check] {
+        assert_rejected(post_with(&app, &bot_check).await, "challenge").await;
+    }
+    assert_eq!(app.email_server.lock().unwrap().sends.len(), 1);
+}
@@ -0,0 +1,117 @@
+use hyper::StatusCode;
+use uuid::Uuid;
+use zero2prod::issue_delivery_worker::try_execute_task;
+
+use crate::helpers::*;
+
+/// Publish an issue to two subscribers. One delivery fails for good, the other
+/// one goes through.
+async fn spawn_app_with_a_partially_delivered_issue() -> (TestApp, Uuid) {
+    let mut app = spawn_app().await;
+    app.config.issue_delivery.max_attempts = 1;
+    app.create_confirmed_subscriber().await;
+    app.post_subscriptions("name=alice&email=alice%40example.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    reqwest::get(app.confirmation_link()).await.unwrap();
+    app.email_server.lock().unwrap().sends.clear();
+    app.publish_newsletter().await;
+
+    try_execute_task(&app.db_pool, &unreachable_smtp_email_client(), &app.config)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    (app, issue_id)
+}
+
+#[tokio::test]
+async fn the_report_counts_delivery_outcomes() {
+    let (app, issue_id) = spawn_app_with_a_partially_delivered_issue().await;
+
+    let response = app.get_delivery_report(issue_id).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["title"], "Newsletter title");
+    assert_eq!(report["status"], "sent");
+    assert_eq!(
+        report["counts"],
+        serde_json::json!({ "queued": 0, "sent": 1, "failed": 1, "bounced": 0 })
+    );
+    assert!(report["last_sent_at"].is_string());
+    let failures = report["failures"].as_array().unwrap();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0]["status"], "failed");
+    assert!(failures[0]["error"].is_string());
+}
+
+#[tokio::test]
+async fn queued_deliveries_are_counted_before_they_are_sent() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.publish_newsletter().await;
+    let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+
+    let report: serde_json::Value = app
+        .get_delivery_report(issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(report["counts"]["queued"], 1);
+    assert_eq!(report["counts"]["sent"], 0);
+    assert!(report["last_sent_at"].is_null());
+}
+
+#[tokio::test]
+async fn the_report_requires_authentication() {
+    let (app, issue_id) = spawn_app_with_a_partially_delivered_issue().await;
+
+    let response = app
+        .get(&format!(
+            "{}/newsletters/{issue_id}/deliveries",
+            app.address
+        ))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        r#"Basic realm="api""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn the_report_of_an_unknown_issue_is_not_found() {
+    let app = spawn_app().await;
+
+    let response = app.get_delivery_report(Uuid::new_v4()).await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn the_issue_page_shows_the_failed_recipients() {
+    let (app, issue_id) = spawn_app_with_a_partially_delivered_issue().await;
+    app.log_in().await;
+
+    let html_page = app
+        .get_html(&format!("/admin/newsletters/{issue_id}"))
+        .await;
+
+    assert!(html_page.contains("<tr><th>Sent</th><td>1</td></tr>"));
+    assert!(html_page.contains("<tr><th>Failed</th><td>1</td></tr>"));
+    assert!(html_page.contains("Failed recipients"));
+}
@@ -0,0 +1,172 @@
+use chrono::{DateTime, Utc};
+use hyper::StatusCode;
+
+use crate::helpers::*;
+
+async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+#[tokio::test]
+async fn a_hard_bounce_stops_newsletters_to_the_subscriber() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.email_server.lock().unwrap().sends.clear();
+
+    let response = app
+        .post_email_events(&serde_json::json!({
+            "events": [{
+                "type": "bounce",
+                "email": "ursula_le_guin@gmail.com",
+                "bounce_type": "hard",
+                "description": "550 5.1.1 The email account does not exist"
+            }]
+        }))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(subscriber_status(&app).await, "bounced");
+    app.publish_newsletter().await;
+    app.dispatch_all_pending_emails().await;
+    assert!(app.email_server.lock().unwrap().sends.is_empty());
+}
+
+#[tokio::test]
+async fn a_complaint_stops_newsletters_to_the_subscriber() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+
+    let response = app
+        .post_email_events(&serde_json::json!({
+            "events": [{ "type": "complaint", "email": "ursula_le_guin@gmail.com" }]
+        }))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(subscriber_status(&app).await, "complained");
+}
+
+#[tokio::test]
+async fn a_later_bounce_does_not_hide_a_complaint() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+
+    app.post_email_events(&serde_json::json!({
+        "events": [
+            { "type": "complaint", "email": "ursula_le_guin@gmail.com" },
+            { "type": "bounce", "email": "ursula_le_guin@gmail.com", "bounce_type": "hard" }
+        ]
+    }))
+    .await;
+
+    assert_eq!(subscriber_status(&app).await, "complained");
+}
+
+#[tokio::test]
+async fn soft_bounces_and_unknown_events_are_ignored() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+
+    let response = app
+        .post_email_events(&serde_json::json!({
+            "events": [
+                { "type": "bounce", "email": "ursula_le_guin@gmail.com", "bounce_type": "soft" },
+                { "type": "delivery", "email": "ursula_le_guin@gmail.com" }
+            ]
+        }))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn a_bounce_is_counted_in_the_delivery_report_of_its_issue() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.publish_newsletter().await;
+    app.dispatch_all_pending_emails().await;
+    let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+
+    app.post_email_events(&serde_json::json!({
+        "events": [{
+            "type": "bounce",
+            "email": "ursula_le_guin@gmail.com",
+            "bounce_type": "hard",
+            "newsletter_issue_id": issue_id,
+            "description": "550 5.1.1 The email account does not exist"
+        }]
+    }))
+    .await;
+
+    let report: serde_json::Value = app
+        .get_delivery_report(issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(report["counts"]["sent"], 0);
+    assert_eq!(report["counts"]["bounced"], 1);
+    assert_eq!(report["failures"][0]["status"], "bounced");
+    assert_eq!(
+        report["failures"][0]["error"],
+        "550 5.1.1 The email account does not exist"
+    );
+    // The last attempt is when the issue was sent, not when it was queued.
+    let sent_at = sqlx::query_scalar!("SELECT sent_at FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let last_attempt_at: DateTime<Utc> = report["failures"][0]["last_attempt_at"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert_eq!(Some(last_attempt_at), sent_at);
+}
+
+#[tokio::test]
+async fn events_with_an_invalid_signature_are_rejected() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let body = serde_json::to_vec(&serde_json::json!({
+        "events": [{ "type": "complaint", "email": "ursula_le_guin@gmail.com" }]
+    }))
+    .unwrap();
+
+    let test_cases = vec![
+        ("", "missing signature"),
+        ("sha256=not-hex", "malformed signature"),
+        (
+            "sha256=0000000000000000000000000000000000000000000000000000000000000000",
+            "wrong signature",
+        ),
+    ];
+    for (signature, description) in test_cases {
+        let response = app
+            .post_email_events_with_signature(body.clone(), signature)
+            .await;
+
+        assert_status_code(StatusCode::UNAUTHORIZED, response.status(), description);
+    }
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn malformed_events_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_email_events(&serde_json::json!({ "events": [{ "type": "bounce" }] }))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
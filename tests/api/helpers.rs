@@ -3,19 +3,26 @@ use std::sync::{Arc, Mutex};
 use argon2::{password_hash::SaltString, Argon2, PasswordHasher};
 use async_fred_session::RedisSessionStore;
 use axum::async_trait;
+use chrono::{Duration, Utc};
 use fred::{pool::RedisPool, prelude::*};
+use hmac::{Hmac, Mac};
 use hyper::StatusCode;
+use lettre::{AsyncSmtpTransport, Tokio1Executor};
 use linkify::Link;
 use once_cell::sync::Lazy;
 use reqwest::{IntoUrl, Url};
 use secrecy::ExposeSecret;
 use serde::Serialize;
+use sha2::Sha256;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
 use zero2prod::{
-    configuration::{get_configuration, DatabaseSettings, Settings},
-    domain::SubscriberEmail,
-    email_client::{self, EmailClient},
+    challenge::{self, Challenge},
+    configuration::{get_configuration, ChallengeSettings, DatabaseSettings, Settings},
+    domain::{SubscribeFormToken, SubscriberEmail},
+    email_client::{self, DynEmailClient, EmailClient, EmailMessage, SmtpEmailClient},
+    issue_delivery_worker::{try_execute_next_task, ExecutionOutcome},
+    newsletter_scheduler::{try_publish_due_issue, PublishOutcome},
     startup::Application,
     telemetry,
 };
@@ -40,11 +47,36 @@ pub struct TestApp {
     pub port: u16,
     pub db_pool: PgPool,
     pub email_server: Arc<Mutex<TestEmailServer>>,
+    pub email_client: DynEmailClient,
+    pub config: Settings,
     pub api_client: reqwest::Client,
     pub test_user: TestUser,
 }
 
 impl TestApp {
+    /// Send every queued confirmation email and newsletter issue, like the
+    /// background worker does.
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_execute_next_task(&self.db_pool, &self.email_client, &self.config)
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn publish_due_issues(&self) {
+        loop {
+            if let PublishOutcome::NothingDue = try_publish_due_issue(&self.db_pool).await.unwrap()
+            {
+                break;
+            }
+        }
+    }
+
     pub async fn get<U>(&self, url: U) -> reqwest::Response
     where
         U: IntoUrl,
@@ -56,6 +88,15 @@ impl TestApp {
             .expect("Failed to execute request")
     }
 
+    /// Fetch a page by its path, e.g. `/admin/dashboard`, and return its body.
+    pub async fn get_html(&self, path: &str) -> String {
+        self.get(&format!("{}{path}", &self.address))
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
     pub async fn post_form<U, B>(&self, url: U, body: &B) -> reqwest::Response
     where
         U: IntoUrl,
@@ -82,9 +123,20 @@ impl TestApp {
             .expect("Failed to execute request")
     }
 
+    /// Post the subscription form, along with the bot check fields a person
+    /// filling it in would send.
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
+        let bot_check = self.bot_check();
+        let body = format!(
+            "{body}&form_token={}&challenge={}&challenge_solution={}",
+            bot_check.form_token, bot_check.challenge, bot_check.challenge_solution
+        );
+        self.post_subscriptions_without_bot_check(body).await
+    }
+
+    pub async fn post_subscriptions_without_bot_check(&self, body: String) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -92,6 +144,47 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    /// Post a subscription as JSON, along with the bot check fields.
+    pub async fn post_subscriptions_json(&self, mut body: serde_json::Value) -> reqwest::Response {
+        let bot_check = self.bot_check();
+        let fields = body.as_object_mut().expect("The body is not an object");
+        fields.insert("form_token".into(), bot_check.form_token.into());
+        fields.insert("challenge".into(), bot_check.challenge.into());
+        fields.insert(
+            "challenge_solution".into(),
+            bot_check.challenge_solution.into(),
+        );
+        self.post_json(format!("{}/subscriptions", self.address), &body)
+            .await
+    }
+
+    /// A form token from a form served a minute ago, and a solved challenge.
+    pub fn bot_check(&self) -> BotCheck {
+        let secret = &self.config.application.hmac_secret;
+        let form_token = SubscribeFormToken::generate(Utc::now() - Duration::minutes(1), secret);
+        let challenge = match challenge::from_settings(&self.config.bot_protection, secret).issue()
+        {
+            Challenge::ProofOfWork { challenge, .. } => challenge,
+            challenge => panic!("Unexpected challenge: {challenge:?}"),
+        };
+        let challenge_solution = self.solve(&challenge);
+
+        BotCheck {
+            form_token: form_token.as_ref().to_owned(),
+            challenge,
+            challenge_solution,
+        }
+    }
+
+    pub fn solve(&self, challenge: &str) -> String {
+        match self.config.bot_protection.challenge {
+            ChallengeSettings::ProofOfWork { difficulty } => {
+                challenge::solve(challenge, difficulty)
+            }
+            _ => panic!("The tests expect a proof-of-work challenge"),
+        }
+    }
+
     pub fn confirmation_link(&self) -> Url {
         let email_server = self.email_server.lock().unwrap();
         let mut confirmation_link =
@@ -106,7 +199,144 @@ impl TestApp {
         confirmation_link
     }
 
+    pub fn unsubscribe_link(&self) -> Url {
+        let email_server = self.email_server.lock().unwrap();
+        let unsubscribe_url = email_server
+            .sends
+            .last()
+            .unwrap()
+            .unsubscribe_url
+            .as_deref()
+            .expect("The last email has no unsubscribe link");
+        let mut unsubscribe_link = Url::parse(unsubscribe_url).unwrap();
+
+        assert_eq!(unsubscribe_link.host_str().unwrap(), "127.0.0.1");
+
+        // Rewrite URL to use test port.
+        unsubscribe_link.set_port(Some(self.port)).unwrap();
+
+        unsubscribe_link
+    }
+
+    /// Wait for emails sent in the background until `n` have been sent in
+    /// total.
+    pub async fn wait_for_emails(&self, n: usize) {
+        for _ in 0..100 {
+            if self.email_server.lock().unwrap().sends.len() >= n {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        panic!("Fewer than {n} emails were sent");
+    }
+
+    pub fn preferences_link(&self) -> Url {
+        let email_server = self.email_server.lock().unwrap();
+        let html_content = &email_server.sends.last().unwrap().html_content;
+        let mut preferences_link = get_links(html_content)
+            .into_iter()
+            .map(|link| Url::parse(link.as_str()).unwrap())
+            .find(|link| link.path() == "/subscriptions/preferences")
+            .expect("The last email has no preferences link");
+
+        assert_eq!(preferences_link.host_str().unwrap(), "127.0.0.1");
+
+        // Rewrite URL to use test port.
+        preferences_link.set_port(Some(self.port)).unwrap();
+
+        preferences_link
+    }
+
+    pub async fn create_unconfirmed_subscriber(&self) {
+        let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+        self.post_subscriptions(body.into())
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+
+    pub async fn create_confirmed_subscriber(&self) {
+        self.create_unconfirmed_subscriber().await;
+        let confirmation_link = self.confirmation_link();
+        reqwest::get(confirmation_link)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    pub async fn publish_newsletter(&self) {
+        let response = self
+            .post_newsletters(serde_json::json!({
+                "title": "Newsletter title",
+                "html_content": "<p>Newsletter body as HTML</p>",
+                "text_content": "Newsletter body as plain text",
+                "idempotency_key": Uuid::new_v4().to_string()
+            }))
+            .await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+    }
+
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_delivery_report(&self, issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/newsletters/{issue_id}/deliveries",
+                &self.address
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Post email events signed the way our email provider signs them.
+    pub async fn post_email_events(&self, body: &serde_json::Value) -> reqwest::Response {
+        let body = serde_json::to_vec(body).unwrap();
+        let mut mac = Hmac::<Sha256>::new_from_slice(
+            self.config
+                .application
+                .email_events_webhook_secret
+                .expose_secret()
+                .as_bytes(),
+        )
+        .unwrap();
+        mac.update(&body);
+        let signature = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+
+        self.post_email_events_with_signature(body, &signature)
+            .await
+    }
+
+    pub async fn post_email_events_with_signature(
+        &self,
+        body: Vec<u8>,
+        signature: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/webhooks/email-events", &self.address))
+            .header("Content-Type", "application/json")
+            .header("X-Signature", signature)
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_newsletters_without_auth(
+        &self,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
         self.post_json(&format!("{}/newsletters", &self.address), &body)
             .await
     }
@@ -127,6 +357,46 @@ impl TestApp {
             .await
     }
 
+    /// Log in as the test user, so that later requests have an admin session.
+    pub async fn log_in(&self) {
+        self.post_login(&serde_json::json!({
+            "username": &self.test_user.username,
+            "password": &self.test_user.password,
+        }))
+        .await;
+    }
+
+    /// The id of the only subscriber.
+    pub async fn subscriber_id(&self) -> Uuid {
+        sqlx::query_scalar!("SELECT id FROM subscriptions")
+            .fetch_one(&self.db_pool)
+            .await
+            .unwrap()
+    }
+
+    /// Insert `n` confirmed subscribers directly, without adding them to a
+    /// list. They are named `subscriber00@example.com` onwards and subscribed
+    /// a minute apart, the first one being the oldest.
+    pub async fn insert_subscribers(&self, n: i32) {
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+            SELECT
+                gen_random_uuid(),
+                'subscriber' || number || '@example.com',
+                'Subscriber ' || number,
+                now() - ($1 - i) * interval '1 minute',
+                'confirmed'
+            FROM generate_series(0, $1 - 1) AS i,
+                LATERAL lpad(i::text, greatest(length(i::text), 2), '0') AS number
+            "#,
+            n
+        )
+        .execute(&self.db_pool)
+        .await
+        .unwrap();
+    }
+
     pub async fn get_admin_dashboard(&self) -> reqwest::Response {
         self.get(&format!("{}/admin/dashboard", &self.address))
             .await
@@ -152,6 +422,23 @@ impl TestApp {
             .await
     }
 
+    pub async fn get_publish_newsletter(&self) -> reqwest::Response {
+        self.get(&format!("{}/admin/newsletters", &self.address))
+            .await
+    }
+
+    pub async fn get_publish_newsletter_html(&self) -> String {
+        self.get_publish_newsletter().await.text().await.unwrap()
+    }
+
+    pub async fn post_publish_newsletter<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: Serialize,
+    {
+        self.post_form(&format!("{}/admin/newsletters", &self.address), body)
+            .await
+    }
+
     pub async fn post_logout(&self) -> reqwest::Response {
         self.api_client
             .post(format!("{}/admin/logout", &self.address))
@@ -171,24 +458,43 @@ impl EmailClient for TestEmailClient {
     async fn send_email(
         &self,
         recipient: &SubscriberEmail,
-        subject: &str,
-        html_content: &str,
+        message: &EmailMessage,
     ) -> Result<(), email_client::SendEmailError> {
         self.inner.lock().unwrap().sends.push(TestEmail {
             recipient: recipient.clone(),
-            subject: subject.to_owned(),
-            html_content: html_content.to_owned(),
+            subject: message.subject.clone(),
+            html_content: message.html_content.clone(),
+            text_content: message.text_content.clone(),
+            unsubscribe_url: message.unsubscribe_url.clone(),
         });
 
         Ok(())
     }
 }
 
+/// An SMTP client pointed at a port nothing listens on, so every send fails
+/// with a transient connection error.
+pub fn unreachable_smtp_email_client() -> DynEmailClient {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mailer = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous("127.0.0.1")
+        .port(port)
+        .build();
+    let sender = SubscriberEmail::parse("sender@example.com".into()).unwrap();
+
+    Arc::new(SmtpEmailClient::new(mailer, sender))
+}
+
 #[derive(Clone)]
 pub struct TestEmail {
     pub recipient: SubscriberEmail,
     pub subject: String,
     pub html_content: String,
+    pub text_content: String,
+    pub unsubscribe_url: Option<String>,
 }
 
 #[derive(Clone, Default)]
@@ -229,6 +535,13 @@ impl TestUser {
     }
 }
 
+/// The fields the subscription form sends along to tell people from bots.
+pub struct BotCheck {
+    pub form_token: String,
+    pub challenge: String,
+    pub challenge_solution: String,
+}
+
 pub async fn spawn_app() -> TestApp {
     Lazy::force(&TRACING);
 
@@ -236,13 +549,15 @@ pub async fn spawn_app() -> TestApp {
     config.application.host = "127.0.0.1".to_string();
     config.application.port = 0;
     config.database.database_name = Uuid::new_v4().to_string();
+    // Keep the proof of work cheap, since most tests subscribe.
+    config.bot_protection.challenge = ChallengeSettings::ProofOfWork { difficulty: 4 };
     let connection_pool = configure_database(&config.database).await;
 
     let email_client = Arc::new(TestEmailClient::default());
     let email_client_inner = Arc::clone(&email_client.inner);
     let session_store = setup_redis_session_store(&config).await;
 
-    let application = Application::build(config.clone(), email_client, session_store)
+    let application = Application::build(config.clone(), email_client.clone(), session_store)
         .await
         .expect("Failed to build application");
     let application_port = application.port();
@@ -264,6 +579,8 @@ pub async fn spawn_app() -> TestApp {
         port: application_port,
         db_pool: connection_pool,
         email_server: email_client_inner,
+        email_client,
+        config,
         api_client,
         test_user,
     }
@@ -298,7 +615,7 @@ pub async fn configure_database(config: &DatabaseSettings) -> PgPool {
     connection_pool
 }
 
-pub fn get_links(html_text: &str) -> Vec<Link> {
+pub fn get_links(html_text: &str) -> Vec<Link<'_>> {
     linkify::LinkFinder::new()
         .links(html_text)
         .filter(|l| *l.kind() == linkify::LinkKind::Url)
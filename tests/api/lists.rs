@@ -0,0 +1,281 @@
+use hyper::StatusCode;
+use uuid::Uuid;
+
+use crate::helpers::*;
+
+const SUBSCRIBER: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+/// Create a list through the admin form and return its id.
+async fn create_list(app: &TestApp, name: &str, slug: &str) -> Uuid {
+    app.log_in().await;
+    let response = app
+        .post_form(
+            format!("{}/admin/lists", app.address),
+            &serde_json::json!({ "name": name, "slug": slug }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/lists");
+
+    sqlx::query!("SELECT list_id FROM lists WHERE slug = $1", slug)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .list_id
+}
+
+async fn subscribe_and_confirm(app: &TestApp, list: &str) {
+    app.post_subscriptions(format!("{SUBSCRIBER}&list={list}"))
+        .await
+        .error_for_status()
+        .unwrap();
+    reqwest::get(app.confirmation_link())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+async fn list_subscription_status(app: &TestApp, slug: &str) -> Option<String> {
+    sqlx::query!(
+        r#"
+        SELECT list_subscriptions.status
+        FROM list_subscriptions
+        JOIN lists ON lists.list_id = list_subscriptions.list_id
+        WHERE lists.slug = $1
+        "#,
+        slug
+    )
+    .fetch_optional(&app.db_pool)
+    .await
+    .unwrap()
+    .map(|r| r.status)
+}
+
+#[tokio::test]
+async fn lists_can_be_created_renamed_and_archived() {
+    let app = spawn_app().await;
+    let list_id = create_list(&app, "Weekly digest", "weekly").await;
+
+    let html_page = app.get_html("/admin/lists").await;
+    assert!(html_page.contains("<p><i>The list Weekly digest has been created.</i></p>"));
+    assert!(html_page.contains("<td>weekly</td>"));
+
+    let response = app
+        .post_form(
+            format!("{}/admin/lists/{list_id}", app.address),
+            &serde_json::json!({ "name": "The weekly digest" }),
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/lists/{list_id}"));
+    let html_page = app.get_html(&format!("/admin/lists/{list_id}")).await;
+    assert!(html_page.contains(r#"value="The weekly digest""#));
+
+    let response = app
+        .post_form(
+            format!("{}/admin/lists/{list_id}/archive", app.address),
+            &serde_json::json!({}),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/lists");
+    let html_page = app.get_html("/admin/lists").await;
+    assert!(html_page.contains("<p><i>The list has been archived.</i></p>"));
+    assert!(html_page.contains("archived "));
+}
+
+#[tokio::test]
+async fn invalid_and_duplicate_slugs_are_rejected() {
+    let app = spawn_app().await;
+    app.log_in().await;
+
+    for (slug, message) in [
+        ("Weekly Digest", "Weekly Digest is not a valid list slug"),
+        ("newsletter", "There is already a list called newsletter."),
+    ] {
+        app.post_form(
+            format!("{}/admin/lists", app.address),
+            &serde_json::json!({ "name": "Weekly digest", "slug": slug }),
+        )
+        .await;
+
+        let html_page = app.get_html("/admin/lists").await;
+        assert!(html_page.contains(message), "{slug}");
+    }
+    let n_lists = sqlx::query!(r#"SELECT count(*) AS "count!" FROM lists"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_lists, 1);
+}
+
+#[tokio::test]
+async fn subscriptions_are_confirmed_per_list() {
+    let app = spawn_app().await;
+    let list_id = create_list(&app, "Weekly digest", "weekly").await;
+    app.create_confirmed_subscriber().await;
+
+    app.post_subscriptions(format!("{SUBSCRIBER}&list={list_id}"))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    assert_eq!(app.email_server.lock().unwrap().sends.len(), 2);
+    assert!(app.email_server.lock().unwrap().sends[1]
+        .html_content
+        .contains("Welcome to Weekly digest!"));
+    assert_eq!(
+        list_subscription_status(&app, "weekly").await.as_deref(),
+        Some("pending_confirmation")
+    );
+    reqwest::get(app.confirmation_link())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(
+        list_subscription_status(&app, "weekly").await.as_deref(),
+        Some("confirmed")
+    );
+    assert_eq!(
+        list_subscription_status(&app, "newsletter")
+            .await
+            .as_deref(),
+        Some("confirmed")
+    );
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_or_archived_list_is_rejected() {
+    let app = spawn_app().await;
+    let list_id = create_list(&app, "Weekly digest", "weekly").await;
+    app.post_form(
+        format!("{}/admin/lists/{list_id}/archive", app.address),
+        &serde_json::json!({}),
+    )
+    .await;
+
+    for list in ["weekly", "monthly", &Uuid::new_v4().to_string()] {
+        let response = app
+            .post_subscriptions(format!("{SUBSCRIBER}&list={list}"))
+            .await;
+
+        assert_status_code(StatusCode::BAD_REQUEST, response.status(), list);
+    }
+    assert!(app.email_server.lock().unwrap().sends.is_empty());
+}
+
+#[tokio::test]
+async fn issues_are_only_delivered_to_the_list_they_target() {
+    let app = spawn_app().await;
+    create_list(&app, "Weekly digest", "weekly").await;
+    app.create_confirmed_subscriber().await;
+    app.post_subscriptions("name=alice&email=alice%40example.com&list=weekly".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    reqwest::get(app.confirmation_link()).await.unwrap();
+    app.email_server.lock().unwrap().sends.clear();
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "This week",
+            "html_content": "<p>This week's digest</p>",
+            "list": "weekly",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    app.dispatch_all_pending_emails().await;
+
+    let email_server = app.email_server.lock().unwrap();
+    assert_eq!(email_server.sends.len(), 1);
+    assert_eq!(
+        email_server.sends[0].recipient.as_ref(),
+        "alice@example.com"
+    );
+}
+
+#[tokio::test]
+async fn publishing_to_an_unknown_list_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "This week",
+            "html_content": "<p>This week's digest</p>",
+            "list": "weekly",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn unsubscribing_from_one_list_keeps_the_others() {
+    let app = spawn_app().await;
+    create_list(&app, "Weekly digest", "weekly").await;
+    app.create_confirmed_subscriber().await;
+    subscribe_and_confirm(&app, "weekly").await;
+    app.post_newsletters(serde_json::json!({
+        "title": "This week",
+        "html_content": "<p>This week's digest</p>",
+        "list": "weekly",
+        "idempotency_key": Uuid::new_v4().to_string()
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let response = app
+        .api_client
+        .post(app.unsubscribe_link())
+        .send()
+        .await
+        .unwrap();
+
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("You have been unsubscribed from Weekly digest."));
+    assert_eq!(
+        list_subscription_status(&app, "weekly").await.as_deref(),
+        Some("unsubscribed")
+    );
+    assert_eq!(
+        list_subscription_status(&app, "newsletter")
+            .await
+            .as_deref(),
+        Some("confirmed")
+    );
+}
+
+#[tokio::test]
+async fn segments_can_be_created_from_the_list_page() {
+    let app = spawn_app().await;
+    let list_id = create_list(&app, "Weekly digest", "weekly").await;
+    let list_url = format!("/admin/lists/{list_id}");
+
+    for (definition, message) in [
+        ("{\"tags\": ", "The segment definition is not valid"),
+        (
+            r#"{"tags": {"tag": "beta"}}"#,
+            "The segment Beta testers has been created.",
+        ),
+    ] {
+        let response = app
+            .post_form(
+                format!("{}{list_url}/segments", app.address),
+                &serde_json::json!({ "name": "Beta testers", "definition": definition }),
+            )
+            .await;
+        assert_is_redirect_to(&response, &list_url);
+
+        let html_page = app.get_html(&list_url).await;
+        assert!(html_page.contains(message), "{definition}");
+    }
+    let html_page = app.get_html(&list_url).await;
+    assert!(html_page.contains("<td>Beta testers</td>"));
+}
@@ -1,8 +1,22 @@
 mod admin_dashboard;
+mod admin_newsletters;
+mod admin_subscribers;
+mod bot_protection;
 mod change_password;
+mod delivery_reports;
+mod email_events;
 mod health_check;
 mod helpers;
+mod lists;
 mod login;
 mod newsletter;
+mod scheduled_newsletters;
+mod segments;
+mod subscriber_export;
+mod subscriber_import;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_data;
+mod subscriptions_preferences;
+mod subscriptions_unsubscribe;
+mod tracking;
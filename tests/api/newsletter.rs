@@ -1,22 +1,33 @@
+use std::sync::Arc;
+
 use hyper::StatusCode;
+use uuid::Uuid;
+use zero2prod::{
+    email_client::{DynEmailClient, RateLimitedEmailClient, RateLimiter, SystemClock},
+    issue_delivery_worker::{count_recent_sends, try_execute_task, ExecutionOutcome},
+};
 
-use crate::helpers::{assert_status_code, spawn_app, TestApp};
+use crate::helpers::{
+    assert_is_redirect_to, assert_status_code, spawn_app, unreachable_smtp_email_client,
+};
 
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     let app = spawn_app().await;
-    create_unconfirmed_subscriber(&app).await;
+    app.create_unconfirmed_subscriber().await;
 
     // Clear out confirmation emails. So we can check if newsletter emails get sent below.
     app.email_server.lock().unwrap().sends.clear();
 
     let newsletter_request_body = serde_json::json!({
         "title": "Newsletter title",
-        "content": "<p>Newsletter body as HTML</p>"
+        "content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string()
     });
 
     let response = app.post_newsletters(newsletter_request_body).await;
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    app.dispatch_all_pending_emails().await;
 
     let email_server = app.email_server.lock().unwrap();
     assert_eq!(
@@ -29,18 +40,25 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
 #[tokio::test]
 async fn newsletters_are_delivered_to_confirmed_subscribers() {
     let app = spawn_app().await;
-    create_confirmed_subscriber(&app).await;
+    app.create_confirmed_subscriber().await;
 
     // Clear out confirmation emails. So we can check if newsletter emails get sent below.
     app.email_server.lock().unwrap().sends.clear();
 
     let newsletter_request_body = serde_json::json!({
         "title": "Newsletter title",
-        "content": "<p>Newsletter body as HTML</p>"
+        "content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string()
     });
 
     let response = app.post_newsletters(newsletter_request_body).await;
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    assert_eq!(
+        0,
+        app.email_server.lock().unwrap().sends.len(),
+        "The API should only queue the issue for delivery"
+    );
+    app.dispatch_all_pending_emails().await;
 
     let email_server = app.email_server.lock().unwrap();
     assert_eq!(
@@ -48,6 +66,128 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
         email_server.sends.len(),
         "The API should send emails to confirmed subscribers"
     );
+    assert_eq!(
+        "ursula_le_guin@gmail.com",
+        email_server.sends[0].recipient.as_ref()
+    );
+    assert_eq!("Newsletter title", email_server.sends[0].subject);
+}
+
+#[tokio::test]
+async fn newsletters_are_sent_with_html_and_text_bodies() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.email_server.lock().unwrap().sends.clear();
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "text_content": "Newsletter body as plain text",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    app.dispatch_all_pending_emails().await;
+
+    let email_server = app.email_server.lock().unwrap();
+    let email = &email_server.sends[0];
+    assert!(email
+        .html_content
+        .contains("<p>Newsletter body as HTML</p>"));
+    assert!(email.text_content.contains("Newsletter body as plain text"));
+}
+
+#[tokio::test]
+async fn a_text_body_is_generated_when_only_html_is_provided() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.email_server.lock().unwrap().sends.clear();
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "html_content": "<h1>Hello</h1><p>Newsletter body as <b>HTML</b></p>",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    app.dispatch_all_pending_emails().await;
+
+    let email_server = app.email_server.lock().unwrap();
+    let text_content = &email_server.sends[0].text_content;
+    assert!(text_content.contains("Hello"));
+    assert!(text_content.contains("Newsletter body as"));
+    assert!(!text_content.contains("<p>"));
+}
+
+#[tokio::test]
+async fn placeholders_are_filled_in_for_each_subscriber() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.email_server.lock().unwrap().sends.clear();
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "html_content": r#"<p>Hi {{ name }}, this went to {{email}}.</p><a href="{{ unsubscribe_url }}">Leave</a>"#,
+            "text_content": "Hi {{ name }}, this went to {{ email }}.",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    app.dispatch_all_pending_emails().await;
+
+    let email_server = app.email_server.lock().unwrap();
+    let email = &email_server.sends[0];
+    assert!(email
+        .html_content
+        .contains("<p>Hi le guin, this went to ursula_le_guin@gmail.com.</p>"));
+    assert!(email.html_content.contains(&format!(
+        r#"<a href="{}">Leave</a>"#,
+        email.unsubscribe_url.as_deref().unwrap()
+    )));
+    assert!(email
+        .text_content
+        .contains("Hi le guin, this went to ursula_le_guin@gmail.com."));
+}
+
+#[tokio::test]
+async fn content_with_unknown_placeholders_is_rejected() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({ "html_content": "<p>Hi {{ first_name }}</p>" }),
+            "unknown placeholder in the HTML body",
+        ),
+        (
+            serde_json::json!({
+                "html_content": "<p>Hi {{ name }}</p>",
+                "text_content": "Hi {{ nmae }}",
+            }),
+            "unknown placeholder in the text body",
+        ),
+    ];
+    for (mut body, error_message) in test_cases {
+        body["title"] = "Newsletter title".into();
+        body["idempotency_key"] = Uuid::new_v4().to_string().into();
+
+        let response = app.post_newsletters(body).await;
+
+        assert_status_code(StatusCode::BAD_REQUEST, response.status(), error_message);
+        assert!(response
+            .text()
+            .await
+            .unwrap()
+            .starts_with("Unknown placeholder"));
+    }
+    let n_issues = sqlx::query!(r#"SELECT count(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
 }
 
 #[tokio::test]
@@ -57,13 +197,24 @@ async fn newsletters_returns_422_for_invalid_data() {
         (
             serde_json::json!({
             "content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string()
             }),
             "missing title",
         ),
         (
-            serde_json::json!({"title": "Newsletter!"}),
+            serde_json::json!({
+                "title": "Newsletter!",
+                "idempotency_key": Uuid::new_v4().to_string()
+            }),
             "missing content",
         ),
+        (
+            serde_json::json!({
+                "title": "Newsletter!",
+                "content": "<p>Newsletter body as HTML</p>",
+            }),
+            "missing idempotency key",
+        ),
     ];
     for (invalid_body, error_message) in test_cases {
         let response = app.post_newsletters(invalid_body).await;
@@ -76,21 +227,356 @@ async fn newsletters_returns_422_for_invalid_data() {
     }
 }
 
-async fn create_unconfirmed_subscriber(app: &TestApp) {
-    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.email_server.lock().unwrap().sends.clear();
+
+    let response = app
+        .post_newsletters_without_auth(serde_json::json!({
+            "title": "Newsletter title",
+            "content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+    assert_eq!(
+        r#"Basic realm="api""#,
+        response.headers()["WWW-Authenticate"]
+    );
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(0, app.email_server.lock().unwrap().sends.len());
+}
+
+#[tokio::test]
+async fn non_existing_user_is_rejected() {
+    let app = spawn_app().await;
+    let username = Uuid::new_v4().to_string();
+    let password = Uuid::new_v4().to_string();
+
+    let response = app
+        .api_client
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(username, Some(password))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+    assert_eq!(
+        r#"Basic realm="api""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn invalid_password_is_rejected() {
+    let app = spawn_app().await;
+    let password = Uuid::new_v4().to_string();
+
+    let response = app
+        .api_client
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(&app.test_user.username, Some(password))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+    assert_eq!(
+        r#"Basic realm="api""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn logged_in_admins_can_publish_without_basic_auth() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.email_server.lock().unwrap().sends.clear();
+
+    app.log_in().await;
+
+    let response = app
+        .post_newsletters_without_auth(serde_json::json!({
+            "title": "Newsletter title",
+            "content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+
+    assert_eq!(StatusCode::ACCEPTED, response.status());
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(1, app.email_server.lock().unwrap().sends.len());
+}
+
+#[tokio::test]
+async fn newsletters_returns_400_for_an_invalid_idempotency_key() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        ("".to_string(), "empty key"),
+        ("a".repeat(50), "key too long"),
+    ];
+    for (idempotency_key, error_message) in test_cases {
+        let response = app
+            .post_newsletters(serde_json::json!({
+                "title": "Newsletter title",
+                "content": "<p>Newsletter body as HTML</p>",
+                "idempotency_key": idempotency_key
+            }))
+            .await;
+
+        assert_status_code(StatusCode::BAD_REQUEST, response.status(), error_message);
+    }
+}
+
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.email_server.lock().unwrap().sends.clear();
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string()
+    });
+
+    let response = app.post_newsletters(newsletter_request_body.clone()).await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(
+        1,
+        app.email_server.lock().unwrap().sends.len(),
+        "Retrying with the same idempotency key should not send the issue again"
+    );
+}
+
+#[tokio::test]
+async fn concurrent_form_submission_is_handled_gracefully() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.email_server.lock().unwrap().sends.clear();
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string()
+    });
+
+    let response1 = app.post_newsletters(newsletter_request_body.clone());
+    let response2 = app.post_newsletters(newsletter_request_body);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(
+        response1.text().await.unwrap(),
+        response2.text().await.unwrap()
+    );
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(1, app.email_server.lock().unwrap().sends.len());
+}
+
+#[tokio::test]
+async fn transient_delivery_failures_are_retried_later() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.email_server.lock().unwrap().sends.clear();
+    app.publish_newsletter().await;
+
+    try_execute_task(&app.db_pool, &unreachable_smtp_email_client(), &app.config)
+        .await
+        .unwrap();
+
+    let task = sqlx::query!(
+        "SELECT status, n_attempts, execute_after > now() AS \"is_delayed!\", last_error \
+        FROM issue_delivery_queue"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(task.status, "pending");
+    assert_eq!(task.n_attempts, 1);
+    assert!(
+        task.is_delayed,
+        "The retry should be scheduled in the future"
+    );
+    assert!(task.last_error.is_some());
+
+    // The retry isn't due yet, so nothing gets sent.
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(0, app.email_server.lock().unwrap().sends.len());
+}
 
-    app.post_subscriptions(body.into())
+#[tokio::test]
+async fn deliveries_are_postponed_once_the_daily_quota_is_reached() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.post_subscriptions("name=alice&email=alice%40example.com".into())
         .await
         .error_for_status()
         .unwrap();
+    reqwest::get(app.confirmation_link()).await.unwrap();
+    app.email_server.lock().unwrap().sends.clear();
+    app.publish_newsletter().await;
+    let email_client: DynEmailClient = Arc::new(RateLimitedEmailClient::new(
+        app.email_client.clone(),
+        RateLimiter::new(100.0, 1, Arc::new(SystemClock)),
+    ));
+
+    let first = try_execute_task(&app.db_pool, &email_client, &app.config)
+        .await
+        .unwrap();
+    let second = try_execute_task(&app.db_pool, &email_client, &app.config)
+        .await
+        .unwrap();
+
+    assert!(matches!(first, ExecutionOutcome::TaskCompleted));
+    assert!(matches!(second, ExecutionOutcome::Paused(_)));
+    assert_eq!(app.email_server.lock().unwrap().sends.len(), 1);
+    let task = sqlx::query!(
+        "SELECT n_attempts, execute_after > now() + interval '23 hours' AS \"is_delayed!\" \
+        FROM issue_delivery_queue WHERE status = 'pending'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(task.n_attempts, 0);
+    assert!(
+        task.is_delayed,
+        "The delivery should resume once the quota resets"
+    );
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    create_unconfirmed_subscriber(app).await;
-    let confirmation_link = app.confirmation_link();
-    reqwest::get(confirmation_link)
+#[tokio::test]
+async fn deliveries_sent_in_the_last_day_count_against_the_quota_after_a_restart() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.post_subscriptions("name=alice&email=alice%40example.com".into())
         .await
-        .unwrap()
         .error_for_status()
         .unwrap();
+    reqwest::get(app.confirmation_link()).await.unwrap();
+    app.publish_newsletter().await;
+    app.dispatch_all_pending_emails().await;
+    sqlx::query!(
+        "UPDATE issue_delivery_queue SET sent_at = now() - interval '2 days' \
+        WHERE subscriber_email = 'alice@example.com'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let (n_sent, window_age) = count_recent_sends(&app.db_pool).await.unwrap();
+
+    assert_eq!(n_sent, 1);
+    assert!(window_age < std::time::Duration::from_secs(60));
+}
+
+#[tokio::test]
+async fn deliveries_are_dead_lettered_after_the_maximum_number_of_attempts() {
+    let mut app = spawn_app().await;
+    app.config.issue_delivery.max_attempts = 1;
+    app.create_confirmed_subscriber().await;
+    app.email_server.lock().unwrap().sends.clear();
+    app.publish_newsletter().await;
+
+    try_execute_task(&app.db_pool, &unreachable_smtp_email_client(), &app.config)
+        .await
+        .unwrap();
+
+    let task = sqlx::query!("SELECT status, n_attempts FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(task.status, "dead_letter");
+    assert_eq!(task.n_attempts, 1);
+}
+
+#[tokio::test]
+async fn deliveries_whose_email_cannot_be_built_are_dead_lettered_at_once() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.email_server.lock().unwrap().sends.clear();
+    app.publish_newsletter().await;
+    // Sabotage the issue, so that its email can't be put together.
+    sqlx::query!("ALTER TABLE newsletter_issues RENAME COLUMN text_content TO broken")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    try_execute_task(&app.db_pool, &app.email_client, &app.config)
+        .await
+        .unwrap();
+
+    let task = sqlx::query!("SELECT status, n_attempts, last_error FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(task.status, "dead_letter");
+    assert_eq!(task.n_attempts, 1);
+    assert!(task.last_error.is_some());
+    assert!(matches!(
+        try_execute_task(&app.db_pool, &app.email_client, &app.config)
+            .await
+            .unwrap(),
+        ExecutionOutcome::EmptyQueue
+    ));
+    assert!(app.email_server.lock().unwrap().sends.is_empty());
+}
+
+#[tokio::test]
+async fn dead_lettered_deliveries_can_be_requeued_from_the_dashboard() {
+    let mut app = spawn_app().await;
+    app.config.issue_delivery.max_attempts = 1;
+    app.create_confirmed_subscriber().await;
+    app.email_server.lock().unwrap().sends.clear();
+    app.publish_newsletter().await;
+    try_execute_task(&app.db_pool, &unreachable_smtp_email_client(), &app.config)
+        .await
+        .unwrap();
+
+    app.log_in().await;
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains("Failed deliveries"));
+    assert!(html_page.contains("ursula_le_guin@gmail.com"));
+
+    let task = sqlx::query!("SELECT newsletter_issue_id FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let response = app
+        .post_form(
+            format!("{}/admin/deliveries/requeue", &app.address),
+            &serde_json::json!({
+                "newsletter_issue_id": task.newsletter_issue_id,
+                "subscriber_email": "ursula_le_guin@gmail.com",
+            }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page
+        .contains("<p><i>The delivery to ursula_le_guin@gmail.com has been re-queued.</i></p>"));
+    assert!(!html_page.contains("Failed deliveries"));
+
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(1, app.email_server.lock().unwrap().sends.len());
 }
@@ -0,0 +1,311 @@
+use chrono::{Duration, Utc};
+use hyper::StatusCode;
+use uuid::Uuid;
+use zero2prod::newsletter_scheduler::{try_publish_due_issue, PublishOutcome};
+
+use crate::helpers::*;
+
+async fn spawn_app_with_a_subscriber() -> TestApp {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.email_server.lock().unwrap().sends.clear();
+    app
+}
+
+async fn make_scheduled_issues_due(app: &TestApp) {
+    sqlx::query!(
+        "UPDATE newsletter_issues SET send_at = now() - interval '1 minute' WHERE status = 'scheduled'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn get_issue_id(app: &TestApp) -> Uuid {
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+/// The value of a `datetime-local` input an hour from now.
+fn an_hour_from_now() -> String {
+    (Utc::now() + Duration::hours(1))
+        .format("%Y-%m-%dT%H:%M")
+        .to_string()
+}
+
+#[tokio::test]
+async fn issues_scheduled_through_the_api_are_delivered_once_due() {
+    let app = spawn_app_with_a_subscriber().await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string(),
+            "send_at": (Utc::now() + Duration::hours(1)).to_rfc3339(),
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    app.publish_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+    assert!(app.email_server.lock().unwrap().sends.is_empty());
+
+    make_scheduled_issues_due(&app).await;
+    app.publish_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(app.email_server.lock().unwrap().sends.len(), 1);
+    let saved = sqlx::query!("SELECT status, published_at FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "sent");
+    assert!(saved.published_at.is_some());
+}
+
+#[tokio::test]
+async fn the_api_rejects_a_send_time_in_the_past() {
+    let app = spawn_app_with_a_subscriber().await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string(),
+            "send_at": (Utc::now() - Duration::hours(1)).to_rfc3339(),
+        }))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn a_retry_after_the_send_time_has_passed_gets_the_saved_response() {
+    let app = spawn_app_with_a_subscriber().await;
+    let idempotency_key = Uuid::new_v4().to_string();
+    let body = |send_at: chrono::DateTime<Utc>| {
+        serde_json::json!({
+            "title": "Newsletter title",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": idempotency_key,
+            "send_at": send_at.to_rfc3339(),
+        })
+    };
+
+    let response = app
+        .post_newsletters(body(Utc::now() + Duration::hours(1)))
+        .await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    // The retry carries the same send time, which is in the past by now.
+    let response = app
+        .post_newsletters(body(Utc::now() - Duration::minutes(1)))
+        .await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let n_issues = sqlx::query_scalar!("SELECT count(*) FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_issues, Some(1));
+}
+
+#[tokio::test]
+async fn a_retry_after_the_list_was_archived_gets_the_saved_response() {
+    let app = spawn_app_with_a_subscriber().await;
+    let body = serde_json::json!({
+        "title": "Newsletter title",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+        "list": "newsletter",
+    });
+
+    let response = app.post_newsletters(body.clone()).await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    sqlx::query!("UPDATE lists SET archived_at = now() WHERE slug = 'newsletter'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.post_newsletters(body).await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+}
+
+#[tokio::test]
+async fn a_rejected_request_does_not_use_up_its_idempotency_key() {
+    let app = spawn_app_with_a_subscriber().await;
+    let idempotency_key = Uuid::new_v4().to_string();
+    let body = |send_at: chrono::DateTime<Utc>| {
+        serde_json::json!({
+            "title": "Newsletter title",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": idempotency_key,
+            "send_at": send_at.to_rfc3339(),
+        })
+    };
+
+    let response = app
+        .post_newsletters(body(Utc::now() - Duration::hours(1)))
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = app
+        .post_newsletters(body(Utc::now() + Duration::hours(1)))
+        .await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+}
+
+#[tokio::test]
+async fn concurrent_schedulers_publish_a_due_issue_only_once() {
+    let app = spawn_app_with_a_subscriber().await;
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+        "send_at": (Utc::now() + Duration::hours(1)).to_rfc3339(),
+    }))
+    .await;
+    make_scheduled_issues_due(&app).await;
+
+    let (first, second) = tokio::join!(
+        try_publish_due_issue(&app.db_pool),
+        try_publish_due_issue(&app.db_pool)
+    );
+    let completed = [first.unwrap(), second.unwrap()]
+        .into_iter()
+        .filter(|o| *o == PublishOutcome::Published)
+        .count();
+    assert_eq!(completed, 1);
+
+    app.publish_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(app.email_server.lock().unwrap().sends.len(), 1);
+}
+
+#[tokio::test]
+async fn issues_can_be_scheduled_from_the_admin_form() {
+    let app = spawn_app_with_a_subscriber().await;
+    app.log_in().await;
+
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "text_content": "",
+            "idempotency_key": Uuid::new_v4().to_string(),
+            "action": "schedule",
+            "send_at": an_hour_from_now(),
+        }))
+        .await;
+    let issue_id = get_issue_id(&app).await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{issue_id}"));
+
+    let html_page = app
+        .get_html(&format!("/admin/newsletters/{issue_id}"))
+        .await;
+    assert!(html_page.contains("<p><i>The newsletter issue will be sent at"));
+    assert!(html_page.contains("Status: scheduled"));
+
+    make_scheduled_issues_due(&app).await;
+    app.publish_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(app.email_server.lock().unwrap().sends.len(), 1);
+}
+
+#[tokio::test]
+async fn the_admin_form_rejects_a_send_time_in_the_past() {
+    let app = spawn_app_with_a_subscriber().await;
+    app.log_in().await;
+
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "text_content": "",
+            "idempotency_key": Uuid::new_v4().to_string(),
+            "action": "schedule",
+            "send_at": "2020-01-01T09:00",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains("<p><i>The send time must be in the future.</i></p>"));
+    let n_issues = sqlx::query!("SELECT count(*) AS \"count!\" FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
+
+#[tokio::test]
+async fn cancelled_issues_are_not_delivered() {
+    let app = spawn_app_with_a_subscriber().await;
+    app.log_in().await;
+    app.post_publish_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "text_content": "",
+        "idempotency_key": Uuid::new_v4().to_string(),
+        "action": "schedule",
+        "send_at": an_hour_from_now(),
+    }))
+    .await;
+    let issue_id = get_issue_id(&app).await;
+
+    let response = app
+        .post_form(
+            &format!("{}/admin/newsletters/{issue_id}/cancel", app.address),
+            &serde_json::json!({}),
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{issue_id}"));
+
+    let html_page = app
+        .get_html(&format!("/admin/newsletters/{issue_id}"))
+        .await;
+    assert!(html_page.contains("The scheduled delivery has been cancelled."));
+    assert!(html_page.contains("Status: draft"));
+    sqlx::query!("UPDATE newsletter_issues SET send_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.publish_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+    assert!(app.email_server.lock().unwrap().sends.is_empty());
+}
+
+#[tokio::test]
+async fn scheduled_issues_can_be_rescheduled() {
+    let app = spawn_app_with_a_subscriber().await;
+    app.log_in().await;
+    app.post_publish_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "text_content": "",
+        "idempotency_key": Uuid::new_v4().to_string(),
+        "action": "schedule",
+        "send_at": an_hour_from_now(),
+    }))
+    .await;
+    let issue_id = get_issue_id(&app).await;
+    let new_send_at = (Utc::now() + Duration::days(2)).format("%Y-%m-%dT%H:%M");
+
+    let response = app
+        .post_form(
+            &format!("{}/admin/newsletters/{issue_id}/schedule", app.address),
+            &serde_json::json!({ "send_at": new_send_at.to_string() }),
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{issue_id}"));
+
+    let saved = sqlx::query!("SELECT status, send_at FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "scheduled");
+    assert!(saved.send_at.unwrap() > Utc::now() + Duration::days(1));
+}
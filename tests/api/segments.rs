@@ -0,0 +1,339 @@
+use hyper::StatusCode;
+use uuid::Uuid;
+
+use crate::helpers::*;
+
+/// Subscribe to the default list and confirm the subscription.
+async fn subscribe(app: &TestApp, name: &str) -> String {
+    let email = format!("{name}@example.com");
+    app.post_subscriptions(format!("name={name}&email={name}%40example.com"))
+        .await
+        .error_for_status()
+        .unwrap();
+    reqwest::get(app.confirmation_link())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    email
+}
+
+async fn post_api(app: &TestApp, path: &str, body: serde_json::Value) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}{path}", app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn tag(app: &TestApp, tag: &str, emails: &[&str]) {
+    let response = post_api(
+        app,
+        "/lists/newsletter/tags",
+        serde_json::json!({ "tag": tag, "add": emails }),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+/// Create a segment of the default list and return its id.
+async fn create_segment(app: &TestApp, name: &str, definition: serde_json::Value) -> Uuid {
+    let response = post_api(
+        app,
+        "/lists/newsletter/segments",
+        serde_json::json!({ "name": name, "definition": definition }),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["segment_id"].as_str().unwrap().parse().unwrap()
+}
+
+async fn preview(app: &TestApp, definition: serde_json::Value) -> i64 {
+    let response = post_api(app, "/lists/newsletter/segments/preview", definition).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["recipients"].as_i64().unwrap()
+}
+
+#[tokio::test]
+async fn segments_combine_tags_with_and_or_and_not() {
+    let app = spawn_app().await;
+    let alice = subscribe(&app, "alice").await;
+    let bob = subscribe(&app, "bob").await;
+    subscribe(&app, "carol").await;
+    tag(&app, "beta", &[&alice, &bob]).await;
+    tag(&app, "paid", &[&bob]).await;
+
+    for (definition, expected) in [
+        (serde_json::json!({}), 3),
+        (serde_json::json!({ "tags": { "tag": "beta" } }), 2),
+        (
+            serde_json::json!({
+                "tags": { "and": [{ "tag": "beta" }, { "not": { "tag": "paid" } }] }
+            }),
+            1,
+        ),
+        (
+            serde_json::json!({ "tags": { "or": [{ "tag": "paid" }, { "tag": "nobody" }] } }),
+            1,
+        ),
+        (
+            serde_json::json!({ "tags": { "not": { "tag": "beta" } } }),
+            1,
+        ),
+        (
+            serde_json::json!({ "subscribed_before": "2000-01-01T00:00:00Z" }),
+            0,
+        ),
+        (
+            serde_json::json!({ "subscribed_after": "2000-01-01T00:00:00Z" }),
+            3,
+        ),
+    ] {
+        assert_eq!(
+            preview(&app, definition.clone()).await,
+            expected,
+            "{definition}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn tags_can_be_removed() {
+    let app = spawn_app().await;
+    let alice = subscribe(&app, "alice").await;
+    tag(&app, "beta", &[&alice]).await;
+
+    let response = post_api(
+        &app,
+        "/lists/newsletter/tags",
+        serde_json::json!({ "tag": "beta", "remove": [&alice] }),
+    )
+    .await;
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["removed"], 1);
+    assert_eq!(
+        preview(&app, serde_json::json!({ "tags": { "tag": "beta" } })).await,
+        0
+    );
+}
+
+#[tokio::test]
+async fn issues_published_to_a_segment_only_reach_its_members() {
+    let app = spawn_app().await;
+    let alice = subscribe(&app, "alice").await;
+    subscribe(&app, "bob").await;
+    tag(&app, "beta", &[&alice]).await;
+    create_segment(
+        &app,
+        "Beta testers",
+        serde_json::json!({ "tags": { "tag": "beta" } }),
+    )
+    .await;
+    app.email_server.lock().unwrap().sends.clear();
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Beta news",
+            "html_content": "<p>Try the new features</p>",
+            "segment": "Beta testers",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    app.dispatch_all_pending_emails().await;
+
+    let email_server = app.email_server.lock().unwrap();
+    assert_eq!(email_server.sends.len(), 1);
+    assert_eq!(email_server.sends[0].recipient.as_ref(), alice);
+}
+
+#[tokio::test]
+async fn the_segment_of_a_scheduled_issue_is_evaluated_when_it_is_sent() {
+    let app = spawn_app().await;
+    let alice = subscribe(&app, "alice").await;
+    let bob = subscribe(&app, "bob").await;
+    tag(&app, "beta", &[&alice]).await;
+    let segment_id = create_segment(
+        &app,
+        "Beta testers",
+        serde_json::json!({ "tags": { "tag": "beta" } }),
+    )
+    .await;
+    app.post_newsletters(serde_json::json!({
+        "title": "Beta news",
+        "html_content": "<p>Try the new features</p>",
+        "segment": segment_id,
+        "send_at": chrono::Utc::now() + chrono::Duration::hours(1),
+        "idempotency_key": Uuid::new_v4().to_string()
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    tag(&app, "beta", &[&bob]).await;
+    app.email_server.lock().unwrap().sends.clear();
+
+    sqlx::query!("UPDATE newsletter_issues SET send_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.publish_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(app.email_server.lock().unwrap().sends.len(), 2);
+}
+
+#[tokio::test]
+async fn publishing_to_an_unknown_segment_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Beta news",
+            "html_content": "<p>Try the new features</p>",
+            "segment": "Beta testers",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn invalid_segments_are_rejected() {
+    let app = spawn_app().await;
+    create_segment(
+        &app,
+        "Beta testers",
+        serde_json::json!({ "tags": { "tag": "beta" } }),
+    )
+    .await;
+
+    for (path, body, expected) in [
+        (
+            "/lists/newsletter/segments",
+            serde_json::json!({ "name": "Beta testers", "definition": {} }),
+            StatusCode::CONFLICT,
+        ),
+        (
+            "/lists/newsletter/segments",
+            serde_json::json!({ "name": " ", "definition": {} }),
+            StatusCode::BAD_REQUEST,
+        ),
+        (
+            "/lists/newsletter/segments",
+            serde_json::json!({
+                "name": "Everyone",
+                "definition": { "tags": { "tag": "Beta Testers" } }
+            }),
+            StatusCode::BAD_REQUEST,
+        ),
+        (
+            "/lists/newsletter/segments/preview",
+            serde_json::json!({
+                "subscribed_after": "2024-01-01T00:00:00Z",
+                "subscribed_before": "2023-01-01T00:00:00Z"
+            }),
+            StatusCode::BAD_REQUEST,
+        ),
+        (
+            "/lists/weekly/segments",
+            serde_json::json!({ "name": "Everyone", "definition": {} }),
+            StatusCode::NOT_FOUND,
+        ),
+        (
+            "/lists/newsletter/tags",
+            serde_json::json!({ "tag": "", "add": [] }),
+            StatusCode::BAD_REQUEST,
+        ),
+    ] {
+        let response = post_api(&app, path, body.clone()).await;
+        assert_status_code(expected, response.status(), &body.to_string());
+    }
+}
+
+#[tokio::test]
+async fn segment_endpoints_require_authentication() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_json(
+            format!("{}/lists/newsletter/segments/preview", app.address),
+            &serde_json::json!({}),
+        )
+        .await;
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        r#"Basic realm="api""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn segments_are_listed_with_their_size() {
+    let app = spawn_app().await;
+    let alice = subscribe(&app, "alice").await;
+    subscribe(&app, "bob").await;
+    tag(&app, "beta", &[&alice]).await;
+    create_segment(
+        &app,
+        "Beta testers",
+        serde_json::json!({ "tags": { "tag": "beta" } }),
+    )
+    .await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/lists/newsletter/segments", app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap();
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body[0]["name"], "Beta testers");
+    assert_eq!(body[0]["definition"]["tags"]["tag"], "beta");
+    assert_eq!(body[0]["recipients"], 1);
+}
+
+#[tokio::test]
+async fn the_admin_issue_page_previews_the_number_of_recipients() {
+    let app = spawn_app().await;
+    let alice = subscribe(&app, "alice").await;
+    subscribe(&app, "bob").await;
+    tag(&app, "beta", &[&alice]).await;
+    let segment_id = create_segment(
+        &app,
+        "Beta testers",
+        serde_json::json!({ "tags": { "tag": "beta" } }),
+    )
+    .await;
+    app.log_in().await;
+
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Beta news",
+            "html_content": "<p>Try the new features</p>",
+            "text_content": "",
+            "segment": segment_id,
+            "action": "save_draft",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    let issue_url = response
+        .headers()
+        .get("Location")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_owned();
+
+    let html_page = app.get_html(&issue_url).await;
+    assert!(html_page.contains("Segment: Beta testers"));
+    assert!(html_page.contains("this issue would currently go to 1 subscriber(s)"));
+}
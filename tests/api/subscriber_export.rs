@@ -0,0 +1,158 @@
+use hyper::StatusCode;
+use uuid::Uuid;
+
+use crate::helpers::*;
+
+async fn get_export(app: &TestApp, query: &str) -> reqwest::Response {
+    app.get(format!("{}/admin/subscribers/export?{query}", app.address))
+        .await
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_export_subscribers() {
+    let app = spawn_app().await;
+
+    let response = get_export(&app, "format=csv").await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn subscribers_are_exported_as_csv() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.log_in().await;
+
+    let response = get_export(&app, "format=csv").await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    assert_eq!(
+        response.headers()["Content-Disposition"],
+        "attachment; filename=\"subscribers.csv\""
+    );
+    let export = response.text().await.unwrap();
+    let lines: Vec<_> = export.lines().collect();
+    assert_eq!(lines[0], "id,email,name,status,subscribed_at,confirmed_at");
+    assert_eq!(lines.len(), 2);
+    let fields: Vec<_> = lines[1].split(',').collect();
+    assert_eq!(
+        fields[1..4],
+        ["ursula_le_guin@gmail.com", "le guin", "confirmed"]
+    );
+    // The subscriber confirmed through the link of the confirmation email.
+    assert!(!fields[5].is_empty());
+}
+
+#[tokio::test]
+async fn subscribers_can_be_exported_as_json_by_status() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber().await;
+    app.insert_subscribers(2).await;
+    app.log_in().await;
+
+    let response = get_export(&app, "format=json&status=pending_confirmation").await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Content-Type"], "application/json");
+    let export: serde_json::Value = response.json().await.unwrap();
+    let export = export.as_array().unwrap();
+    assert_eq!(export.len(), 1);
+    assert_eq!(export[0]["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(export[0]["status"], "pending_confirmation");
+    assert_eq!(export[0]["confirmed_at"], serde_json::Value::Null);
+}
+
+#[tokio::test]
+async fn subscribers_can_be_exported_by_list() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber().await;
+    app.insert_subscribers(2).await;
+    app.log_in().await;
+    let list_id = sqlx::query_scalar!("SELECT list_id FROM lists WHERE slug = 'newsletter'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = get_export(&app, &format!("format=json&list={list_id}")).await;
+
+    let export: serde_json::Value = response.json().await.unwrap();
+    let emails: Vec<_> = export
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|subscriber| subscriber["email"].as_str().unwrap())
+        .collect();
+    assert_eq!(emails, vec!["ursula_le_guin@gmail.com"]);
+
+    let response = get_export(&app, &format!("format=json&list={}", Uuid::new_v4())).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn exports_are_complete_across_chunks() {
+    let app = spawn_app().await;
+    app.insert_subscribers(1_234).await;
+    app.log_in().await;
+
+    let csv = get_export(&app, "format=csv").await.text().await.unwrap();
+    let json: serde_json::Value = get_export(&app, "format=json").await.json().await.unwrap();
+
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(lines.len(), 1 + 1_234);
+    assert!(lines[1].contains(",subscriber00@example.com,"));
+    assert!(lines[1_234].contains(",subscriber1233@example.com,"));
+    assert_eq!(json.as_array().unwrap().len(), 1_234);
+}
+
+#[tokio::test]
+async fn empty_exports_are_valid() {
+    let app = spawn_app().await;
+    app.log_in().await;
+
+    let csv = get_export(&app, "format=csv").await.text().await.unwrap();
+    let json = get_export(&app, "format=json").await.text().await.unwrap();
+
+    assert_eq!(csv, "id,email,name,status,subscribed_at,confirmed_at\n");
+    assert_eq!(json, "[]");
+}
+
+#[tokio::test]
+async fn unknown_formats_and_statuses_are_rejected() {
+    let app = spawn_app().await;
+    app.log_in().await;
+
+    for query in ["format=xml", "format=csv&status=happy"] {
+        let response = get_export(&app, query).await;
+
+        assert_eq!(
+            response.status(),
+            StatusCode::BAD_REQUEST,
+            "The export did not fail with {query}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn erased_subscribers_are_not_exported() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.log_in().await;
+    let erased_id = app.subscriber_id().await;
+    app.post_form(
+        format!("{}/admin/subscribers/{erased_id}/erase", app.address),
+        &serde_json::json!({}),
+    )
+    .await;
+    app.insert_subscribers(1).await;
+
+    let response = get_export(&app, "format=csv").await;
+
+    let export = response.text().await.unwrap();
+    assert_eq!(export.lines().count(), 2);
+    assert!(!export.contains("ursula_le_guin@gmail.com"));
+    assert!(!export.contains(&erased_id.to_string()));
+}
@@ -0,0 +1,436 @@
+use hyper::StatusCode;
+use reqwest::multipart::{Form, Part};
+use uuid::Uuid;
+use zero2prod::{
+    issue_delivery_worker::try_execute_next_task,
+    subscriber_import::{fail_interrupted_imports, upload_path},
+};
+
+use crate::helpers::*;
+
+/// Upload a CSV file to import into the default list. `status` and
+/// `consent_source` are sent as is, if present.
+async fn post_import(
+    app: &TestApp,
+    status: &str,
+    consent_source: Option<&str>,
+    file: impl Into<String>,
+) -> reqwest::Response {
+    let mut form = Form::new()
+        .text("list", "newsletter")
+        .text("status", status.to_owned());
+    if let Some(consent_source) = consent_source {
+        form = form.text("consent_source", consent_source.to_owned());
+    }
+    form = form.part(
+        "file",
+        Part::text(file.into())
+            .file_name("subscribers.csv")
+            .mime_str("text/csv")
+            .unwrap(),
+    );
+
+    app.api_client
+        .post(format!("{}/admin/subscribers/import", app.address))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+/// The page of the import an upload redirects to, once the import has run in
+/// the background.
+async fn import_page(app: &TestApp, response: &reqwest::Response) -> String {
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    let location = response.headers()["Location"].to_str().unwrap();
+    let import_id = location
+        .strip_prefix("/admin/subscribers/imports/")
+        .unwrap()
+        .parse::<Uuid>()
+        .unwrap();
+    for _ in 0..100 {
+        let finished_at = sqlx::query_scalar!(
+            "SELECT finished_at FROM subscriber_imports WHERE import_id = $1",
+            import_id
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+        if finished_at.is_some() {
+            return location.to_owned();
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    panic!("The import did not finish");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_import_subscribers() {
+    let app = spawn_app().await;
+
+    let response = app
+        .get(format!("{}/admin/subscribers/import", app.address))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = post_import(&app, "pending_confirmation", None, "email,name\n").await;
+    assert_is_redirect_to(&response, "/login");
+    let n_imports = sqlx::query_scalar!("SELECT count(*) FROM subscriber_imports")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_imports, Some(0));
+}
+
+#[tokio::test]
+async fn pending_subscribers_are_sent_a_confirmation_email() {
+    let app = spawn_app().await;
+    app.log_in().await;
+
+    let response = post_import(
+        &app,
+        "pending_confirmation",
+        None,
+        "email,name\nursula@example.com,Ursula\nguin@example.com,Le Guin\n",
+    )
+    .await;
+    let page = import_page(&app, &response).await;
+
+    let statuses = sqlx::query!(
+        r#"
+        SELECT subscriptions.email, subscriptions.status, list_subscriptions.status AS list_status
+        FROM subscriptions
+        JOIN list_subscriptions ON list_subscriptions.subscriber_id = subscriptions.id
+        ORDER BY email
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(statuses.len(), 2);
+    for row in statuses {
+        assert_eq!(row.status, "pending_confirmation");
+        assert_eq!(row.list_status, "pending_confirmation");
+    }
+    // The emails are queued for the background worker.
+    assert!(app.email_server.lock().unwrap().sends.is_empty());
+    app.dispatch_all_pending_emails().await;
+    let mut recipients: Vec<_> = app
+        .email_server
+        .lock()
+        .unwrap()
+        .sends
+        .iter()
+        .map(|email| email.recipient.as_ref().to_owned())
+        .collect();
+    recipients.sort();
+    assert_eq!(recipients, vec!["guin@example.com", "ursula@example.com"]);
+
+    // The confirmation link works like the one sent to people who subscribe
+    // through the form.
+    let confirmation_link = app.confirmation_link();
+    let response = reqwest::get(confirmation_link).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let html_page = app.get_html(&page).await;
+    assert!(html_page.contains("The file is being imported."));
+    assert!(html_page.contains("<tr><th>Imported</th><td>2</td></tr>"));
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_are_recorded_with_their_consent_source() {
+    let app = spawn_app().await;
+    app.log_in().await;
+
+    let response = post_import(
+        &app,
+        "confirmed",
+        Some("Signup form of our previous provider"),
+        "email,name\nursula@example.com,Ursula\n",
+    )
+    .await;
+    import_page(&app, &response).await;
+
+    let row = sqlx::query!(
+        r#"
+        SELECT subscriptions.status, list_subscriptions.status AS list_status, consent_source
+        FROM subscriptions
+        JOIN list_subscriptions ON list_subscriptions.subscriber_id = subscriptions.id
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(row.status, "confirmed");
+    assert_eq!(row.list_status, "confirmed");
+    assert_eq!(
+        row.consent_source.as_deref(),
+        Some("Signup form of our previous provider")
+    );
+    app.dispatch_all_pending_emails().await;
+    assert!(app.email_server.lock().unwrap().sends.is_empty());
+}
+
+#[tokio::test]
+async fn confirmed_imports_require_a_consent_source() {
+    let app = spawn_app().await;
+    app.log_in().await;
+
+    let response = post_import(
+        &app,
+        "confirmed",
+        Some(""),
+        "email,name\nursula@example.com,Ursula\n",
+    )
+    .await;
+    assert_is_redirect_to(&response, "/admin/subscribers/import");
+
+    let html_page = app.get_html("/admin/subscribers/import").await;
+    assert!(html_page.contains("Importing confirmed subscribers requires a consent source."));
+    let n_subscribers = sqlx::query_scalar!("SELECT count(*) FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_subscribers, Some(0));
+}
+
+#[tokio::test]
+async fn a_file_without_the_expected_columns_is_rejected() {
+    let app = spawn_app().await;
+    app.log_in().await;
+
+    let response = post_import(
+        &app,
+        "pending_confirmation",
+        None,
+        "address,full_name\nursula@example.com,Ursula\n",
+    )
+    .await;
+    let page = import_page(&app, &response).await;
+
+    let html_page = app.get_html(&page).await;
+    assert!(html_page
+        .contains("The import stopped before the end of the file: The file has no `email` column"));
+}
+
+#[tokio::test]
+async fn duplicates_are_skipped() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.email_server.lock().unwrap().sends.clear();
+    app.log_in().await;
+
+    let response = post_import(
+        &app,
+        "pending_confirmation",
+        None,
+        "email,name\n\
+        ursula_le_guin@gmail.com,Someone Else\n\
+        guin@example.com,Le Guin\n\
+        guin@example.com,Le Guin Again\n",
+    )
+    .await;
+    let page = import_page(&app, &response).await;
+
+    // The existing subscriber is left as it was.
+    let existing = sqlx::query!(
+        r#"
+        SELECT subscriptions.name, list_subscriptions.status
+        FROM subscriptions
+        JOIN list_subscriptions ON list_subscriptions.subscriber_id = subscriptions.id
+        WHERE email = 'ursula_le_guin@gmail.com'
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(existing.name, "le guin");
+    assert_eq!(existing.status, "confirmed");
+    let name =
+        sqlx::query_scalar!("SELECT name FROM subscriptions WHERE email = 'guin@example.com'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(name, "Le Guin");
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(app.email_server.lock().unwrap().sends.len(), 1);
+
+    let html_page = app.get_html(&page).await;
+    assert!(html_page.contains("<tr><th>Imported</th><td>1</td></tr>"));
+    assert!(html_page.contains("<tr><th>Duplicates skipped</th><td>2</td></tr>"));
+}
+
+#[tokio::test]
+async fn rejected_rows_can_be_downloaded_with_the_reason() {
+    let app = spawn_app().await;
+    app.log_in().await;
+
+    let response = post_import(
+        &app,
+        "pending_confirmation",
+        None,
+        "email,name\n\
+        ursula@example.com,Ursula\n\
+        not-an-email,Someone\n\
+        guin@example.com,\n",
+    )
+    .await;
+    let page = import_page(&app, &response).await;
+
+    let html_page = app.get_html(&page).await;
+    assert!(html_page.contains("<tr><th>Rejected</th><td>2</td></tr>"));
+    assert!(html_page.contains("not-an-email is not a valid email"));
+
+    let response = app
+        .get(format!("{}{page}/rejections.csv", app.address))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    assert_eq!(
+        response.text().await.unwrap(),
+        "line,email,name,reason\n\
+        3,not-an-email,Someone,not-an-email is not a valid email\n\
+        4,guin@example.com,, is not a valid subscriber name\n"
+    );
+}
+
+#[tokio::test]
+async fn large_files_are_imported_in_batches() {
+    let app = spawn_app().await;
+    app.log_in().await;
+    let mut file = String::from("email,name\n");
+    for i in 0..1_234 {
+        file.push_str(&format!("subscriber{i}@example.com,Subscriber {i}\n"));
+    }
+
+    let response = post_import(
+        &app,
+        "confirmed",
+        Some("Signup form of our previous provider"),
+        file,
+    )
+    .await;
+    import_page(&app, &response).await;
+
+    let n_subscribers =
+        sqlx::query_scalar!("SELECT count(*) FROM list_subscriptions WHERE status = 'confirmed'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(n_subscribers, Some(1_234));
+}
+
+#[tokio::test]
+async fn erased_subscribers_are_not_imported_again() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber().await;
+    app.log_in().await;
+    let subscriber_id = sqlx::query_scalar!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    app.post_form(
+        format!("{}/admin/subscribers/{subscriber_id}/erase", app.address),
+        &serde_json::json!({}),
+    )
+    .await;
+
+    let response = post_import(
+        &app,
+        "confirmed",
+        Some("Signup form of our previous provider"),
+        "email,name\n\
+        Ursula_Le_Guin@gmail.com,Ursula\n\
+        guin@example.com,Le Guin\n",
+    )
+    .await;
+    let page = import_page(&app, &response).await;
+
+    let emails = sqlx::query_scalar!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(emails, vec!["guin@example.com"]);
+    let html_page = app.get_html(&page).await;
+    assert!(html_page.contains("<tr><th>Rejected</th><td>1</td></tr>"));
+    assert!(html_page.contains("The subscriber asked for their data to be erased"));
+    assert!(!html_page.to_lowercase().contains("ursula"));
+    let rejection =
+        sqlx::query!("SELECT line_number, email, name FROM subscriber_import_rejections")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        (rejection.line_number, rejection.email, rejection.name),
+        (2, String::new(), String::new())
+    );
+}
+
+#[tokio::test]
+async fn confirmation_emails_of_an_import_are_retried_by_the_worker() {
+    let app = spawn_app().await;
+    app.log_in().await;
+    let response = post_import(
+        &app,
+        "pending_confirmation",
+        None,
+        "email,name\nursula@example.com,Ursula\n",
+    )
+    .await;
+    import_page(&app, &response).await;
+
+    try_execute_next_task(&app.db_pool, &unreachable_smtp_email_client(), &app.config)
+        .await
+        .unwrap();
+
+    let task = sqlx::query!("SELECT n_attempts, last_error FROM confirmation_email_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(task.n_attempts, 1);
+    assert!(task.last_error.is_some());
+    sqlx::query!("UPDATE confirmation_email_queue SET execute_after = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(app.email_server.lock().unwrap().sends.len(), 1);
+    let n_queued =
+        sqlx::query_scalar!("SELECT count(*) FROM confirmation_email_queue WHERE sent_at IS NULL")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(n_queued, Some(0));
+}
+
+#[tokio::test]
+async fn imports_interrupted_by_a_restart_are_marked_as_failed() {
+    let app = spawn_app().await;
+    app.log_in().await;
+    let import_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_imports (import_id, list_id, file_name, status)
+        SELECT $1, list_id, 'subscribers.csv', 'pending_confirmation'
+        FROM lists WHERE slug = 'newsletter'
+        "#,
+        import_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let upload = upload_path(import_id);
+    std::fs::write(&upload, "email,name\n").unwrap();
+
+    fail_interrupted_imports(&app.db_pool).await.unwrap();
+
+    let html_page = app
+        .get_html(&format!("/admin/subscribers/imports/{import_id}"))
+        .await;
+    assert!(html_page.contains("The import was interrupted by a restart"));
+    assert!(!html_page.contains("still running"));
+    assert!(!upload.exists());
+}
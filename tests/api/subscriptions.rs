@@ -1,6 +1,6 @@
 use hyper::StatusCode;
 
-use crate::helpers::{assert_status_code, spawn_app};
+use crate::helpers::{assert_status_code, get_links, spawn_app};
 
 #[tokio::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
@@ -93,6 +93,22 @@ async fn subscribe_sends_a_single_confirmation_email_with_a_link() {
     );
 }
 
+#[tokio::test]
+async fn the_confirmation_email_has_a_plain_text_body_with_the_same_link() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    app.post_subscriptions(body.into()).await;
+
+    let email_server = app.email_server.lock().unwrap();
+    let email = &email_server.sends[0];
+    let html_links = get_links(&email.html_content);
+    let text_links = get_links(&email.text_content);
+    assert_eq!(1, text_links.len());
+    assert_eq!(html_links[0].as_str(), text_links[0].as_str());
+    assert!(!email.text_content.contains('<'));
+}
+
 #[tokio::test]
 async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     let app = spawn_app().await;
@@ -108,3 +124,170 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
 
     assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
 }
+
+#[tokio::test]
+async fn subscribing_again_while_pending_resends_a_fresh_confirmation_link() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    app.post_subscriptions(body.into()).await;
+    let first_link = app.confirmation_link();
+    let response = app.post_subscriptions(body.into()).await;
+    let second_link = app.confirmation_link();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(app.email_server.lock().unwrap().sends.len(), 2);
+    assert_ne!(first_link, second_link);
+
+    let response = reqwest::get(second_link).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_again_when_confirmed_succeeds_without_sending_an_email() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(app.email_server.lock().unwrap().sends.len(), 1);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_again_after_unsubscribing_asks_for_confirmation() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    sqlx::query!("UPDATE list_subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(app.email_server.lock().unwrap().sends.len(), 2);
+    let saved = sqlx::query!("SELECT status FROM list_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn subscribe_accepts_json_and_responds_with_json() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions_json(serde_json::json!({
+            "name": "Le Guin",
+            "email": "ursula_le_guin@gmail.com",
+        }))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Content-Type"], "application/json");
+    let saved = sqlx::query!("SELECT email, name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+    assert_eq!(saved.name, "Le Guin");
+    assert_eq!(app.email_server.lock().unwrap().sends.len(), 1);
+}
+
+#[tokio::test]
+async fn json_errors_name_the_invalid_field() {
+    let app = spawn_app().await;
+
+    let test_cases = vec![
+        (
+            serde_json::json!({ "name": "Ursula", "email": "definitely-not-an-email" }),
+            "email",
+        ),
+        (
+            serde_json::json!({ "name": "Ursula <script>", "email": "ursula@example.com" }),
+            "name",
+        ),
+        (
+            serde_json::json!({
+                "name": "Ursula",
+                "email": "ursula@example.com",
+                "list": "no-such-list",
+            }),
+            "list",
+        ),
+    ];
+
+    for (body, field) in test_cases {
+        let response = app
+            .post_json(format!("{}/subscriptions", app.address), &body)
+            .await;
+
+        assert_status_code(StatusCode::BAD_REQUEST, response.status(), field);
+        let error: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(error["field"], field);
+        assert!(error["message"].is_string());
+    }
+}
+
+#[tokio::test]
+async fn unreadable_json_is_reported_as_json() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_json(
+            format!("{}/subscriptions", app.address),
+            &serde_json::json!({ "name": "Le Guin" }),
+        )
+        .await;
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let error: serde_json::Value = response.json().await.unwrap();
+    assert!(error["message"].as_str().unwrap().contains("email"));
+    assert!(error.get("field").is_none());
+}
+
+#[tokio::test]
+async fn the_accept_header_picks_the_format_of_the_response() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Accept", "application/json")
+        .body("name=Ursula&email=definitely-not-an-email")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let error: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(error["field"], "email");
+
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions", app.address))
+        .header("Accept", "text/html, application/json;q=0.9")
+        .json(&serde_json::json!({ "name": "Ursula", "email": "definitely-not-an-email" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        response.text().await.unwrap(),
+        "definitely-not-an-email is not a valid email"
+    );
+}
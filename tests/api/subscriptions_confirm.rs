@@ -41,3 +41,37 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
     assert_eq!(saved.name, "le guin");
     assert_eq!(saved.status, "confirmed");
 }
+
+#[tokio::test]
+async fn an_expired_confirmation_link_is_rejected_with_a_410() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber().await;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '49 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(app.confirmation_link()).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 410);
+    assert!(response.text().await.unwrap().contains("expired"));
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn an_unknown_confirmation_token_is_rejected_with_a_401() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(&format!(
+        "{}/subscriptions/confirm?subscription_token=not-a-real-token",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
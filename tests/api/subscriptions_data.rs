@@ -0,0 +1,238 @@
+use chrono::{Duration, Utc};
+use hyper::StatusCode;
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+use zero2prod::domain::DataRequestToken;
+
+use crate::helpers::*;
+
+async fn request_data(app: &TestApp, email: &str) -> reqwest::Response {
+    let bot_check = app.bot_check();
+    app.post_form(
+        format!("{}/subscriptions/data-request", app.address),
+        &serde_json::json!({
+            "email": email,
+            "form_token": bot_check.form_token,
+            "challenge": bot_check.challenge,
+            "challenge_solution": bot_check.challenge_solution,
+        }),
+    )
+    .await
+}
+
+/// The link with this path in the last email sent.
+fn link(app: &TestApp, path: &str) -> Url {
+    let email_server = app.email_server.lock().unwrap();
+    let html_content = &email_server.sends.last().unwrap().html_content;
+    let mut link = get_links(html_content)
+        .into_iter()
+        .map(|link| Url::parse(link.as_str()).unwrap())
+        .find(|link| link.path() == path)
+        .unwrap_or_else(|| panic!("The last email has no link to {path}"));
+
+    assert_eq!(link.host_str().unwrap(), "127.0.0.1");
+
+    // Rewrite URL to use test port.
+    link.set_port(Some(app.port)).unwrap();
+
+    link
+}
+
+/// Subscribe, receive an issue and ask for a data request link.
+async fn subscribe_and_request_data(app: &TestApp) {
+    app.create_confirmed_subscriber().await;
+    app.publish_newsletter().await;
+    app.dispatch_all_pending_emails().await;
+    request_data(app, "ursula_le_guin@gmail.com")
+        .await
+        .error_for_status()
+        .unwrap();
+    app.wait_for_emails(3).await;
+}
+
+#[tokio::test]
+async fn asking_for_data_emails_an_export_and_an_erase_link() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+
+    let response = request_data(&app, "ursula_le_guin@gmail.com").await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    app.wait_for_emails(2).await;
+    {
+        let sends = &app.email_server.lock().unwrap().sends;
+        assert_eq!(sends.len(), 2);
+        assert_eq!(
+            sends.last().unwrap().recipient.as_ref(),
+            "ursula_le_guin@gmail.com"
+        );
+    }
+    link(&app, "/subscriptions/data");
+    link(&app, "/subscriptions/erase");
+}
+
+#[tokio::test]
+async fn asking_for_data_does_not_reveal_whether_an_address_is_subscribed() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+
+    let known = request_data(&app, "ursula_le_guin@gmail.com").await;
+    let unknown = request_data(&app, "someone@example.com").await;
+
+    assert_eq!(unknown.status(), StatusCode::OK);
+    assert_eq!(known.text().await.unwrap(), unknown.text().await.unwrap());
+    // The confirmation email and the data request link.
+    app.wait_for_emails(2).await;
+    assert_eq!(app.email_server.lock().unwrap().sends.len(), 2);
+}
+
+#[tokio::test]
+async fn data_requests_without_a_form_token_and_challenge_are_rejected() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+
+    let response = app
+        .post_form(
+            format!("{}/subscriptions/data-request", app.address),
+            &serde_json::json!({ "email": "ursula_le_guin@gmail.com" }),
+        )
+        .await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("The form token is missing or invalid"));
+    assert!(html_page.contains(r#"name="form_token""#));
+    assert_eq!(app.email_server.lock().unwrap().sends.len(), 1);
+}
+
+#[tokio::test]
+async fn the_export_contains_everything_about_the_subscriber() {
+    let app = spawn_app().await;
+    subscribe_and_request_data(&app).await;
+
+    let response = reqwest::get(link(&app, "/subscriptions/data"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["Content-Disposition"],
+        "attachment; filename=\"my-data.json\""
+    );
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["subscriber"]["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(data["lists"][0]["list"], "newsletter");
+    assert_eq!(data["tokens"].as_array().unwrap().len(), 1);
+    assert_eq!(data["deliveries"].as_array().unwrap().len(), 1);
+    assert!(data["engagement"].is_array());
+    assert!(data["preference_changes"].is_array());
+}
+
+#[tokio::test]
+async fn invalid_and_expired_tokens_are_rejected() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let expired = DataRequestToken::generate(
+        app.subscriber_id().await,
+        Utc::now() - Duration::minutes(1),
+        &app.config.application.hmac_secret,
+    );
+
+    for token in ["not-a-token", expired.as_ref()] {
+        for path in ["/subscriptions/data", "/subscriptions/erase"] {
+            let url = format!("{}{path}?token={token}", app.address);
+
+            let response = app.get(url).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "GET {path}");
+        }
+        let response = app
+            .post_form(
+                format!("{}/subscriptions/erase?token={token}", app.address),
+                &serde_json::json!({}),
+            )
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+    let n_subscribers = sqlx::query_scalar!("SELECT count(*) FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_subscribers, Some(1));
+}
+
+#[tokio::test]
+async fn following_the_erase_link_asks_for_confirmation() {
+    let app = spawn_app().await;
+    subscribe_and_request_data(&app).await;
+
+    let response = reqwest::get(link(&app, "/subscriptions/erase"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.text().await.unwrap().contains("Erase my data"));
+    let n_subscribers = sqlx::query_scalar!("SELECT count(*) FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_subscribers, Some(1));
+}
+
+#[tokio::test]
+async fn erasing_removes_the_personal_data_and_leaves_a_tombstone() {
+    let app = spawn_app().await;
+    subscribe_and_request_data(&app).await;
+
+    let response = app
+        .post_form(link(&app, "/subscriptions/erase"), &serde_json::json!({}))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Your data has been erased."));
+    for table in [
+        "subscriptions",
+        "subscription_tokens",
+        "list_subscriptions",
+        "preference_changes",
+    ] {
+        let n_rows: i64 = sqlx::query_scalar(&format!("SELECT count(*) FROM {table}"))
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+        assert_eq!(n_rows, 0, "{table} still has rows");
+    }
+    let tombstones = sqlx::query_scalar!("SELECT email_hash FROM erased_subscribers")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tombstones.len(), 1);
+    assert_ne!(tombstones[0], "ursula_le_guin@gmail.com");
+    // The hash is keyed, so it can't be matched against a list of addresses.
+    assert_ne!(
+        tombstones[0],
+        hex::encode(Sha256::digest(b"ursula_le_guin@gmail.com"))
+    );
+    // The delivery is kept for the statistics of the issue, but not the address.
+    let delivered_to = sqlx::query_scalar!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivered_to, vec![format!("erased:{}", tombstones[0])]);
+}
+
+#[tokio::test]
+async fn erased_subscribers_can_subscribe_again() {
+    let app = spawn_app().await;
+    subscribe_and_request_data(&app).await;
+    app.post_form(link(&app, "/subscriptions/erase"), &serde_json::json!({}))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Only imports are blocked: the person themselves may come back.
+    app.create_unconfirmed_subscriber().await;
+}
@@ -0,0 +1,285 @@
+use hyper::StatusCode;
+use reqwest::Url;
+use uuid::Uuid;
+
+use crate::helpers::*;
+
+/// Subscribe, publish an issue and return the preferences link it contains.
+async fn preferences_link(app: &TestApp) -> Url {
+    app.create_confirmed_subscriber().await;
+    app.publish_newsletter().await;
+    app.dispatch_all_pending_emails().await;
+    app.preferences_link()
+}
+
+async fn save_preferences(app: &TestApp, link: &Url, body: &str) -> reqwest::Response {
+    app.api_client
+        .post(link.clone())
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(body.to_owned())
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn create_list(app: &TestApp, slug: &str) -> Uuid {
+    let list_id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO lists (list_id, slug, name) VALUES ($1, $2, $3)",
+        list_id,
+        slug,
+        "Weekly digest"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    list_id
+}
+
+async fn list_statuses(app: &TestApp) -> Vec<(String, String)> {
+    sqlx::query!(
+        r#"
+        SELECT lists.slug, list_subscriptions.status
+        FROM list_subscriptions
+        JOIN lists ON lists.list_id = list_subscriptions.list_id
+        ORDER BY lists.slug
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| (r.slug, r.status))
+    .collect()
+}
+
+async fn default_list_id(app: &TestApp) -> Uuid {
+    sqlx::query!("SELECT list_id FROM lists WHERE slug = 'newsletter'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .list_id
+}
+
+#[tokio::test]
+async fn newsletter_issues_link_to_the_preference_center() {
+    let app = spawn_app().await;
+    let link = preferences_link(&app).await;
+
+    let newsletter = app.email_server.lock().unwrap().sends[1].clone();
+    assert!(newsletter.text_content.contains(link.path()));
+    let response = reqwest::get(link).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Preferences for ursula_le_guin@gmail.com"));
+    assert!(html_page.contains(r#"value="le guin""#));
+    assert!(html_page.contains(r#"value="every_issue" checked"#));
+}
+
+#[tokio::test]
+async fn an_invalid_token_is_rejected_with_a_401() {
+    let app = spawn_app().await;
+
+    let response = app
+        .get(format!(
+            "{}/subscriptions/preferences?token={}.abcd",
+            app.address,
+            Uuid::new_v4().simple()
+        ))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn preferences_are_saved_and_every_change_is_recorded() {
+    let app = spawn_app().await;
+    let link = preferences_link(&app).await;
+    let default_list_id = default_list_id(&app).await;
+    let weekly_id = create_list(&app, "weekly").await;
+
+    let response = save_preferences(
+        &app,
+        &link,
+        &format!(
+            "name=Ursula&frequency=monthly&list={default_list_id}&list={weekly_id}&action=save"
+        ),
+    )
+    .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Your preferences have been saved."));
+    assert!(html_page.contains("Last changed at"));
+    let subscriber = sqlx::query!("SELECT name, frequency FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.name, "Ursula");
+    assert_eq!(subscriber.frequency, "monthly");
+    assert_eq!(
+        list_statuses(&app).await,
+        [
+            ("newsletter".to_owned(), "confirmed".to_owned()),
+            ("weekly".to_owned(), "confirmed".to_owned())
+        ]
+    );
+    let changes =
+        sqlx::query!("SELECT field, old_value, new_value FROM preference_changes ORDER BY field")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+    let changes: Vec<_> = changes
+        .into_iter()
+        .map(|c| (c.field, c.old_value, c.new_value))
+        .collect();
+    assert_eq!(
+        changes,
+        [
+            (
+                "frequency".to_owned(),
+                Some("every_issue".to_owned()),
+                Some("monthly".to_owned())
+            ),
+            ("list:weekly".to_owned(), None, Some("confirmed".to_owned())),
+            (
+                "name".to_owned(),
+                Some("le guin".to_owned()),
+                Some("Ursula".to_owned())
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn unchecking_a_list_unsubscribes_from_it() {
+    let app = spawn_app().await;
+    let link = preferences_link(&app).await;
+
+    save_preferences(
+        &app,
+        &link,
+        "name=le%20guin&frequency=every_issue&action=save",
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    assert_eq!(
+        list_statuses(&app).await,
+        [("newsletter".to_owned(), "unsubscribed".to_owned())]
+    );
+}
+
+#[tokio::test]
+async fn an_invalid_name_is_rejected_and_nothing_is_changed() {
+    let app = spawn_app().await;
+    let link = preferences_link(&app).await;
+
+    for body in [
+        "name=%3Cscript%3E&frequency=weekly&action=save",
+        "name=Ursula&frequency=daily&action=save",
+    ] {
+        let response = save_preferences(&app, &link, body).await;
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{body}");
+    }
+    let subscriber = sqlx::query!("SELECT name, frequency FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.name, "le guin");
+    assert_eq!(subscriber.frequency, "every_issue");
+    assert_eq!(
+        list_statuses(&app).await,
+        [("newsletter".to_owned(), "confirmed".to_owned())]
+    );
+}
+
+#[tokio::test]
+async fn subscribers_can_unsubscribe_from_everything() {
+    let app = spawn_app().await;
+    let link = preferences_link(&app).await;
+    let weekly_id = create_list(&app, "weekly").await;
+    let default_list_id = default_list_id(&app).await;
+    save_preferences(
+        &app,
+        &link,
+        &format!("name=le%20guin&frequency=every_issue&list={default_list_id}&list={weekly_id}&action=save"),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    let response = save_preferences(&app, &link, "action=unsubscribe_all").await;
+
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("You have been unsubscribed from every list."));
+    assert_eq!(
+        list_statuses(&app).await,
+        [
+            ("newsletter".to_owned(), "unsubscribed".to_owned()),
+            ("weekly".to_owned(), "unsubscribed".to_owned())
+        ]
+    );
+}
+
+#[tokio::test]
+async fn issues_are_not_sent_more_often_than_the_chosen_frequency() {
+    let app = spawn_app().await;
+    let link = preferences_link(&app).await;
+    let response = save_preferences(
+        &app,
+        &link,
+        &format!(
+            "name=le%20guin&frequency=weekly&list={}&action=save",
+            default_list_id(&app).await
+        ),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // An issue was sent moments ago, so the weekly subscriber skips this one.
+    app.publish_newsletter().await;
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(app.email_server.lock().unwrap().sends.len(), 2);
+
+    // A week later they are due again.
+    sqlx::query!("UPDATE issue_delivery_queue SET sent_at = now() - interval '8 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.publish_newsletter().await;
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(app.email_server.lock().unwrap().sends.len(), 3);
+}
+
+#[tokio::test]
+async fn a_weekly_subscriber_gets_one_of_two_issues_published_back_to_back() {
+    let app = spawn_app().await;
+    let link = preferences_link(&app).await;
+    let response = save_preferences(
+        &app,
+        &link,
+        &format!(
+            "name=le%20guin&frequency=weekly&list={}&action=save",
+            default_list_id(&app).await
+        ),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    sqlx::query!("UPDATE issue_delivery_queue SET sent_at = now() - interval '8 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Neither issue has been sent when the second one is published.
+    app.publish_newsletter().await;
+    app.publish_newsletter().await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(app.email_server.lock().unwrap().sends.len(), 3);
+}
@@ -0,0 +1,122 @@
+use hyper::StatusCode;
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn newsletters_contain_an_unsubscribe_link() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.publish_newsletter().await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_server = app.email_server.lock().unwrap();
+    let newsletter = email_server.sends.last().unwrap();
+    let unsubscribe_url = newsletter
+        .unsubscribe_url
+        .as_ref()
+        .expect("Newsletters should have an unsubscribe URL");
+    assert!(newsletter.html_content.contains(unsubscribe_url.as_str()));
+    assert!(newsletter.text_content.contains(unsubscribe_url.as_str()));
+}
+
+#[tokio::test]
+async fn the_unsubscribe_link_asks_for_confirmation() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.publish_newsletter().await;
+    app.dispatch_all_pending_emails().await;
+
+    let response = reqwest::get(app.unsubscribe_link()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Do you want to stop receiving Newsletter?"));
+
+    let saved = sqlx::query!("SELECT status FROM list_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn a_one_click_unsubscribe_request_unsubscribes_the_subscriber() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.publish_newsletter().await;
+    app.dispatch_all_pending_emails().await;
+
+    let response = app
+        .api_client
+        .post(app.unsubscribe_link())
+        .form(&[("List-Unsubscribe", "One-Click")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let saved = sqlx::query!("SELECT status FROM list_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_unsubscribed_subscribers() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.publish_newsletter().await;
+    app.dispatch_all_pending_emails().await;
+    app.api_client
+        .post(app.unsubscribe_link())
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.email_server.lock().unwrap().sends.clear();
+
+    app.publish_newsletter().await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(0, app.email_server.lock().unwrap().sends.len());
+}
+
+#[tokio::test]
+async fn tampered_unsubscribe_tokens_are_rejected_with_a_401() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.publish_newsletter().await;
+    app.dispatch_all_pending_emails().await;
+
+    let mut unsubscribe_link = app.unsubscribe_link();
+    let token = unsubscribe_link
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .unwrap()
+        .1
+        .into_owned();
+    // Flip the last character of the signature.
+    let last_char = if token.ends_with('0') { '1' } else { '0' };
+    let tampered_token = format!("{}{last_char}", &token[..token.len() - 1]);
+    unsubscribe_link.set_query(Some(&format!("token={tampered_token}")));
+
+    let response = app
+        .api_client
+        .post(unsubscribe_link.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = reqwest::get(unsubscribe_link).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let saved = sqlx::query!("SELECT status FROM list_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
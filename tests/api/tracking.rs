@@ -0,0 +1,149 @@
+use hyper::StatusCode;
+use reqwest::Url;
+use uuid::Uuid;
+
+use crate::helpers::*;
+
+const ARTICLE_URL: &str = "https://example.com/articles/1?utm_source=newsletter";
+
+async fn publish_newsletter_with_a_link(app: &TestApp) -> Uuid {
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "html_content": r#"<p>Read <a href="https://example.com/articles/1?utm_source=newsletter">the article</a>.</p>"#,
+            "text_content": "Read the article.",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    app.dispatch_all_pending_emails().await;
+
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+/// The first link in the last email whose path starts with `prefix`, pointed
+/// at the test server.
+fn tracking_link(app: &TestApp, prefix: &str) -> Url {
+    let email_server = app.email_server.lock().unwrap();
+    let html = &email_server.sends.last().unwrap().html_content;
+    let mut link = get_links(html)
+        .into_iter()
+        .map(|l| Url::parse(l.as_str()).unwrap())
+        .find(|url| url.path().starts_with(prefix))
+        .unwrap_or_else(|| panic!("The email has no {prefix} link"));
+    link.set_port(Some(app.port)).unwrap();
+    link
+}
+
+#[tokio::test]
+async fn links_are_not_rewritten_when_tracking_is_disabled() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+
+    publish_newsletter_with_a_link(&app).await;
+
+    let email_server = app.email_server.lock().unwrap();
+    let html = &email_server.sends.last().unwrap().html_content;
+    assert!(html.contains(r#"href="https://example.com/articles/1?utm_source=newsletter""#));
+    assert!(!html.contains("/t/o/"));
+}
+
+#[tokio::test]
+async fn opens_and_clicks_are_recorded_and_reported() {
+    let mut app = spawn_app().await;
+    app.config.tracking.enabled = true;
+    app.create_confirmed_subscriber().await;
+    let issue_id = publish_newsletter_with_a_link(&app).await;
+
+    let pixel = app.get(tracking_link(&app, "/t/o/")).await;
+    assert_eq!(pixel.status(), StatusCode::OK);
+    assert_eq!(pixel.headers()["content-type"], "image/gif");
+    app.get(tracking_link(&app, "/t/o/")).await;
+    let click = app.get(tracking_link(&app, "/t/c/")).await;
+    assert_eq!(click.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(click.headers()["location"], ARTICLE_URL);
+
+    let report: serde_json::Value = app
+        .get_delivery_report(issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        report["engagement"],
+        serde_json::json!({
+            "opens": 2,
+            "unique_opens": 1,
+            "clicks": 1,
+            "unique_clicks": 1,
+            "links": [{ "url": ARTICLE_URL, "clicks": 1 }],
+        })
+    );
+}
+
+#[tokio::test]
+async fn engagement_is_shown_on_the_issue_page() {
+    let mut app = spawn_app().await;
+    app.config.tracking.enabled = true;
+    app.create_confirmed_subscriber().await;
+    let issue_id = publish_newsletter_with_a_link(&app).await;
+    app.get(tracking_link(&app, "/t/c/")).await;
+
+    app.log_in().await;
+    let html_page = app
+        .get_html(&format!("/admin/newsletters/{issue_id}"))
+        .await;
+
+    assert!(html_page.contains("<tr><th>Unique clicks</th><td>1</td></tr>"));
+    assert!(html_page.contains(ARTICLE_URL));
+}
+
+#[tokio::test]
+async fn tampered_click_links_are_rejected() {
+    let mut app = spawn_app().await;
+    app.config.tracking.enabled = true;
+    app.create_confirmed_subscriber().await;
+    publish_newsletter_with_a_link(&app).await;
+    let mut link = tracking_link(&app, "/t/c/");
+    let tampered = format!("{}0", link.path());
+    link.set_path(&tampered);
+
+    let response = app.get(link).await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let n_events = sqlx::query!(r#"SELECT count(*) AS "count!" FROM tracking_events"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_events, 0);
+}
+
+#[tokio::test]
+async fn placeholders_in_tracked_links_are_filled_in_before_tracking() {
+    let mut app = spawn_app().await;
+    app.config.tracking.enabled = true;
+    app.create_confirmed_subscriber().await;
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "html_content": r#"<p><a href="https://example.com/welcome?email={{ email }}">Welcome</a></p>"#,
+            "text_content": "Welcome!",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    app.dispatch_all_pending_emails().await;
+
+    let click = app.get(tracking_link(&app, "/t/c/")).await;
+
+    assert_eq!(click.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(
+        click.headers()["location"],
+        "https://example.com/welcome?email=ursula_le_guin@gmail.com"
+    );
+}